@@ -1,10 +1,16 @@
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use inference_graph::bench::{deep_graph, diamond_graph, wide_graph};
 use inference_graph::{graph, wrap};
 
 async fn concat(x: Vec<String>) -> String {
     x.concat()
 }
 
+/// Keeps payloads from growing along the synthetic graphs, so only the executor is measured.
+async fn first(x: Vec<String>) -> String {
+    x.into_iter().next().unwrap_or_default()
+}
+
 pub fn criterion_benchmark(c: &mut Criterion) {
     let mut graph = graph::Graph::default();
     graph.stage_node("A".into(), vec!["entrypoint".into()], wrap!(concat));
@@ -20,6 +26,17 @@ pub fn criterion_benchmark(c: &mut Criterion) {
     c.bench_function("graph.run", |b| {
         b.iter(|| tokio_rt.block_on(graph.run(black_box(h.clone()), black_box(n.clone()))))
     });
+
+    let synthetic = [
+        ("wide 1000", wide_graph(1000, wrap!(first))),
+        ("deep 1000", deep_graph(1000, wrap!(first))),
+        ("diamond 250", diamond_graph(250, wrap!(first))),
+    ];
+    for (name, graph) in &synthetic {
+        c.bench_function(name, |b| {
+            b.iter(|| tokio_rt.block_on(graph.run(black_box("x".into()), "output".into())))
+        });
+    }
 }
 
 criterion_group!(benches, criterion_benchmark);
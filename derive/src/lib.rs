@@ -0,0 +1,79 @@
+/*!
+Derive macros for `inference_graph`. Use them through the re-exports in that crate rather than depending on this one.
+*/
+
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{parse_macro_input, DeriveInput, LitStr};
+
+/// Derives `inference_graph::registry::StructOp` for a struct that has an
+/// `async fn call(&self, inputs: Vec<String>) -> String` method. The op is registered under the struct's name in
+/// snake_case, or under the name given with `#[op(name = "...")]`.
+#[proc_macro_derive(Op, attributes(op))]
+pub fn derive_op(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let ident = &input.ident;
+    let mut name = snake_case(&ident.to_string());
+    for attr in input.attrs.iter().filter(|attr| attr.path().is_ident("op")) {
+        let parsed = attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("name") {
+                name = meta.value()?.parse::<LitStr>()?.value();
+                Ok(())
+            } else {
+                Err(meta.error("expected `name = \"...\"`"))
+            }
+        });
+        if let Err(e) = parsed {
+            return e.to_compile_error().into();
+        }
+    }
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    quote! {
+        impl #impl_generics ::inference_graph::registry::StructOp for #ident #ty_generics #where_clause {
+            const NAME: &'static str = #name;
+
+            fn run(
+                self: ::std::rc::Rc<Self>,
+                inputs: ::std::vec::Vec<::std::string::String>,
+            ) -> ::inference_graph::graph::BoxedFuture {
+                ::std::boxed::Box::pin(async move { self.call(inputs).await })
+            }
+        }
+    }
+    .into()
+}
+
+/// A new word starts at an uppercase letter that follows a lowercase letter or digit, or that ends a run of
+/// uppercase letters and is followed by a lowercase one, so `HTTPClient` becomes `http_client`.
+fn snake_case(name: &str) -> String {
+    let chars: Vec<char> = name.chars().collect();
+    let mut snake = String::new();
+    for (i, &c) in chars.iter().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            let previous = chars[i - 1];
+            let next_is_lower = chars.get(i + 1).is_some_and(|next| next.is_lowercase());
+            if previous.is_lowercase()
+                || previous.is_ascii_digit()
+                || (previous.is_uppercase() && next_is_lower)
+            {
+                snake.push('_');
+            }
+        }
+        snake.extend(c.to_lowercase());
+    }
+    snake
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_names_into_snake_case_words() {
+        assert_eq!(snake_case("Summarize"), "summarize");
+        assert_eq!(snake_case("AskModel"), "ask_model");
+        assert_eq!(snake_case("HTTPClient"), "http_client");
+        assert_eq!(snake_case("LoadURL"), "load_url");
+        assert_eq!(snake_case("Gpt4Call"), "gpt4_call");
+    }
+}
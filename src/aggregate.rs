@@ -0,0 +1,182 @@
+use crate::trace::RunTrace;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, VecDeque};
+use std::time::{Duration, Instant};
+
+/// How one `Node` fared across the runs of an `AggregateReport`. `latencies` are sorted from fastest to slowest and
+/// only count executions that succeeded.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct NodeAggregate {
+    pub executions: usize,
+    pub failures: usize,
+    pub latencies: Vec<Duration>,
+}
+
+impl NodeAggregate {
+    /// The share of executions that failed, between `0.0` and `1.0`.
+    pub fn failure_rate(&self) -> f64 {
+        if self.executions == 0 {
+            return 0.0;
+        }
+        self.failures as f64 / self.executions as f64
+    }
+
+    /// The latency below which `percentile` (between `0.0` and `1.0`) of the successful executions finished.
+    pub fn percentile(&self, percentile: f64) -> Option<Duration> {
+        self::percentile(&self.latencies, percentile)
+    }
+}
+
+/// The latency below which `percentile` (between `0.0` and `1.0`) of the `sorted` latencies fall, rounding to the
+/// nearest one.
+pub(crate) fn percentile(sorted: &[Duration], percentile: f64) -> Option<Duration> {
+    let last = sorted.len().checked_sub(1)?;
+    let index = (percentile.clamp(0.0, 1.0) * last as f64).round() as usize;
+    Some(sorted[index])
+}
+
+/// What a `RunAggregator` has seen within its window. A run counts as failed if any of its `Node`s did.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AggregateReport {
+    pub runs: usize,
+    pub failed_runs: usize,
+    pub nodes: BTreeMap<String, NodeAggregate>,
+}
+
+impl AggregateReport {
+    /// The report as JSON, with the p50, p90 and p99 latency of each `Node` in milliseconds, for a metrics
+    /// endpoint or dashboard to pick up.
+    pub fn to_json(&self) -> Value {
+        let ms = |latency: Option<Duration>| latency.map(|l| l.as_secs_f64() * 1000.0);
+        let nodes: serde_json::Map<String, Value> = self
+            .nodes
+            .iter()
+            .map(|(name, node)| {
+                let stats = json!({
+                    "executions": node.executions,
+                    "failures": node.failures,
+                    "failure_rate": node.failure_rate(),
+                    "p50_ms": ms(node.percentile(0.5)),
+                    "p90_ms": ms(node.percentile(0.9)),
+                    "p99_ms": ms(node.percentile(0.99)),
+                });
+                (name.clone(), stats)
+            })
+            .collect();
+        json!({"runs": self.runs, "failed_runs": self.failed_runs, "nodes": nodes})
+    }
+}
+
+/// A `RunAggregator` combines the `RunTrace`s of many runs into per-`Node` latency percentiles and failure rates over
+/// a sliding `window`, e.g. the last five minutes. Traces older than the window are dropped as new ones come in.
+pub struct RunAggregator {
+    window: Duration,
+    traces: VecDeque<(Instant, RunTrace)>,
+}
+
+impl RunAggregator {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            traces: VecDeque::new(),
+        }
+    }
+
+    /// Adds a run that just finished.
+    pub fn record(&mut self, trace: RunTrace) {
+        self.record_at(Instant::now(), trace);
+    }
+
+    /// Adds a run that finished `at`. Runs must be recorded in the order they finished.
+    pub fn record_at(&mut self, at: Instant, trace: RunTrace) {
+        self.traces.push_back((at, trace));
+        self.expire(at);
+    }
+
+    /// Aggregates the runs within the window as of now.
+    pub fn report(&mut self) -> AggregateReport {
+        self.report_at(Instant::now())
+    }
+
+    /// Aggregates the runs within the window that ends `at`.
+    pub fn report_at(&mut self, at: Instant) -> AggregateReport {
+        self.expire(at);
+        let mut report = AggregateReport::default();
+        for (_, trace) in &self.traces {
+            report.runs += 1;
+            if trace.nodes.iter().any(|node| node.output.is_err()) {
+                report.failed_runs += 1;
+            }
+            for node in &trace.nodes {
+                let aggregate = report.nodes.entry(node.node.clone()).or_default();
+                aggregate.executions += 1;
+                match node.output {
+                    Ok(_) => aggregate.latencies.push(node.finished - node.started),
+                    Err(_) => aggregate.failures += 1,
+                }
+            }
+        }
+        for aggregate in report.nodes.values_mut() {
+            aggregate.latencies.sort();
+        }
+        report
+    }
+
+    fn expire(&mut self, now: Instant) {
+        while let Some((at, _)) = self.traces.front() {
+            if now.saturating_duration_since(*at) <= self.window {
+                break;
+            }
+            self.traces.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::id::RunId;
+    use crate::trace::{NodeTrace, Source};
+
+    fn run(latency_ms: u64, fails: bool) -> RunTrace {
+        let node = |name: &str, output: Result<String, String>| NodeTrace {
+            node: name.into(),
+            inputs: vec![],
+            output: output.map(Some),
+            source: Source::Op,
+            started: Duration::ZERO,
+            finished: Duration::from_millis(latency_ms),
+        };
+        let b = if fails {
+            Err("boom".to_string())
+        } else {
+            Ok("b".to_string())
+        };
+        RunTrace {
+            run_id: RunId::new(),
+            input: "x".into(),
+            output_node: "B".into(),
+            nodes: vec![node("A", Ok("a".into())), node("B", b)],
+            graph: BTreeMap::new(),
+            versions: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn aggregates_runs_within_the_window() {
+        let start = Instant::now();
+        let mut aggregator = RunAggregator::new(Duration::from_secs(60));
+        aggregator.record_at(start, run(500, true));
+        for i in 1..=10 {
+            aggregator.record_at(start + Duration::from_secs(60 + i), run(i * 10, i == 10));
+        }
+
+        let report = aggregator.report_at(start + Duration::from_secs(71));
+        assert_eq!((report.runs, report.failed_runs), (10, 1));
+        let a = &report.nodes["A"];
+        assert_eq!(a.percentile(0.5), Some(Duration::from_millis(60)));
+        assert_eq!(a.percentile(0.99), Some(Duration::from_millis(100)));
+        assert_eq!(report.nodes["B"].failure_rate(), 0.1);
+        assert_eq!(report.to_json()["nodes"]["B"]["p90_ms"], json!(80.0));
+    }
+}
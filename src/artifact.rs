@@ -0,0 +1,136 @@
+use crate::dedicated::DedicatedThread;
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::path::PathBuf;
+use std::pin::Pin;
+
+/// What the methods of an `ArtifactStore` return.
+pub type ArtifactFuture<'a, T> = Pin<Box<dyn Future<Output = io::Result<T>> + 'a>>;
+
+/// A lightweight reference to an artifact in an `ArtifactStore`, passed between `Node`s in place of the artifact
+/// itself. It is written as `artifact:<name>`, so a `Node` can send it downstream as its output and the next `Node`
+/// can `parse` it back.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ArtifactHandle {
+    name: String,
+}
+
+impl ArtifactHandle {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Reads a handle written by `to_string`, or returns `None` if `value` isn't one.
+    pub fn parse(value: &str) -> Option<Self> {
+        let name = value.strip_prefix("artifact:")?;
+        valid_name(name).then(|| Self {
+            name: name.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for ArtifactHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "artifact:{}", self.name)
+    }
+}
+
+/// An `ArtifactStore` keeps the large things `Node`s make, such as generated files, images or long transcripts, so
+/// that only an `ArtifactHandle` has to travel along the edges of the `Graph`. Names are relative paths like
+/// `run-1/transcript.txt`; putting an artifact under a name that is taken replaces it.
+pub trait ArtifactStore {
+    fn put<'a>(&'a self, name: &'a str, data: Vec<u8>) -> ArtifactFuture<'a, ArtifactHandle>;
+
+    fn get<'a>(&'a self, handle: &'a ArtifactHandle) -> ArtifactFuture<'a, Vec<u8>>;
+}
+
+/// Keeps artifacts as files under a local directory. Files are read and written on a `DedicatedThread` of the store,
+/// so runs don't block on disk.
+#[derive(Clone, Debug)]
+pub struct LocalDirStore {
+    root: PathBuf,
+    thread: DedicatedThread<()>,
+}
+
+impl LocalDirStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            thread: DedicatedThread::spawn("artifacts", || ()),
+        }
+    }
+
+    /// Runs `io` on the thread of the store.
+    async fn on_thread<T: Send + 'static>(
+        &self,
+        io: impl FnOnce() -> io::Result<T> + Send + 'static,
+    ) -> io::Result<T> {
+        match self.thread.call(move |()| io()).await {
+            Some(result) => result,
+            None => Err(io::Error::other("the artifact store's thread has stopped")),
+        }
+    }
+}
+
+impl ArtifactStore for LocalDirStore {
+    fn put<'a>(&'a self, name: &'a str, data: Vec<u8>) -> ArtifactFuture<'a, ArtifactHandle> {
+        Box::pin(async move {
+            if !valid_name(name) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("`{name}` is not a valid artifact name"),
+                ));
+            }
+            let path = self.root.join(name);
+            self.on_thread(move || {
+                if let Some(parent) = path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                std::fs::write(path, data)
+            })
+            .await?;
+            Ok(ArtifactHandle {
+                name: name.to_string(),
+            })
+        })
+    }
+
+    fn get<'a>(&'a self, handle: &'a ArtifactHandle) -> ArtifactFuture<'a, Vec<u8>> {
+        let path = self.root.join(&handle.name);
+        Box::pin(self.on_thread(move || std::fs::read(path)))
+    }
+}
+
+/// Names must be relative paths that stay inside the store.
+fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('/')
+        && name
+            .split('/')
+            .all(|part| !part.is_empty() && part != "." && part != ".." && !part.contains('\\'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn stores_artifacts_and_passes_handles() {
+        let root = std::env::temp_dir().join(format!("artifacts-{}", std::process::id()));
+        let store = LocalDirStore::new(&root);
+
+        let handle = store
+            .put("run-1/transcript.txt", b"hello".to_vec())
+            .await
+            .unwrap();
+        let passed = handle.to_string();
+        assert_eq!(passed, "artifact:run-1/transcript.txt");
+        let received = ArtifactHandle::parse(&passed).unwrap();
+        assert_eq!(store.get(&received).await.unwrap(), b"hello".to_vec());
+
+        assert!(store.put("../escape", vec![]).await.is_err());
+        assert_eq!(ArtifactHandle::parse("plain text"), None);
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,213 @@
+use crate::aggregate;
+use crate::graph::{Graph, OpFn};
+use crate::jsonl;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+
+/// A global allocator that counts allocations, so `bench_op` can report how many an `op` makes. It is opt-in
+/// instrumentation; install it in a benchmark binary with:
+/// ```
+/// #[global_allocator]
+/// static ALLOCATOR: inference_graph::bench::CountingAllocator = inference_graph::bench::CountingAllocator;
+/// # fn main() {}
+/// ```
+pub struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        INSTALLED.store(true, Ordering::Relaxed);
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+/// How an `op` fared in `bench_op`. Latencies are sorted from fastest to slowest.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OpBenchReport {
+    pub latencies: Vec<Duration>,
+    /// Allocations per call, on average, when `CountingAllocator` is installed.
+    pub allocations_per_call: Option<u64>,
+}
+
+impl OpBenchReport {
+    /// The latency below which `percentile` (between `0.0` and `1.0`) of the calls finished.
+    pub fn percentile(&self, percentile: f64) -> Option<Duration> {
+        aggregate::percentile(&self.latencies, percentile)
+    }
+
+    pub fn mean(&self) -> Option<Duration> {
+        let count = u32::try_from(self.latencies.len())
+            .ok()
+            .filter(|&n| n > 0)?;
+        Some(self.latencies.iter().sum::<Duration>() / count)
+    }
+}
+
+/// `bench_op` calls `op` on each of `inputs` in turn, `rounds` times over, and reports how long each call took.
+/// Feed it `sampled_inputs` to benchmark an `op` on real traffic rather than made-up data.
+pub async fn bench_op(op: OpFn, inputs: &[Vec<String>], rounds: usize) -> OpBenchReport {
+    let mut latencies = Vec::with_capacity(inputs.len() * rounds);
+    let mut allocations = 0;
+    for _ in 0..rounds {
+        for input in inputs {
+            let input = input.clone();
+            let before = ALLOCATIONS.load(Ordering::Relaxed);
+            let started = Instant::now();
+            let _ = op(input).await;
+            latencies.push(started.elapsed());
+            allocations += ALLOCATIONS.load(Ordering::Relaxed) - before;
+        }
+    }
+    latencies.sort();
+    let calls = latencies.len() as u64;
+    OpBenchReport {
+        allocations_per_call: (INSTALLED.load(Ordering::Relaxed) && calls > 0)
+            .then(|| allocations / calls),
+        latencies,
+    }
+}
+
+/// Reads the inputs of every recorded execution of `node` from a dataset written by a `sampling::Sampler`.
+pub fn sampled_inputs(path: impl AsRef<Path>, node: &str) -> io::Result<Vec<Vec<String>>> {
+    let mut inputs = vec![];
+    jsonl::read(path, |value| {
+        if value["node"] != node {
+            return Ok(());
+        }
+        let record: Option<Vec<String>> = value["inputs"].as_array().and_then(|values| {
+            values
+                .iter()
+                .map(|v| v.as_str().map(str::to_string))
+                .collect()
+        });
+        inputs.push(record.ok_or("`inputs` must be an array of strings")?);
+        Ok(())
+    })?;
+    Ok(inputs)
+}
+
+/// `wide_graph` builds a `Graph` of `width` siblings that all read `entrypoint`, joined by a `Node` called `output`
+/// that takes every one of them. It stresses fan-out and the delivery of values.
+pub fn wide_graph(width: usize, op: OpFn) -> Graph {
+    let mut graph = Graph::default();
+    let siblings: Vec<String> = (0..width).map(|i| format!("n{i}")).collect();
+    for name in &siblings {
+        graph.stage_node(name.clone(), vec!["entrypoint".into()], op);
+    }
+    graph.stage_node("output".into(), siblings, op);
+    graph
+}
+
+/// `deep_graph` builds a chain of `depth` `Node`s, each reading the one before it, ending in `output`. It stresses
+/// scheduling of long dependency chains. It panics if `depth` is 0, which would leave no `output`.
+pub fn deep_graph(depth: usize, op: OpFn) -> Graph {
+    assert!(depth > 0, "A deep graph needs a depth of at least 1");
+    let mut graph = Graph::default();
+    let mut previous = "entrypoint".to_string();
+    for i in 0..depth {
+        let name = if i + 1 == depth {
+            "output".to_string()
+        } else {
+            format!("n{i}")
+        };
+        graph.stage_node(name.clone(), vec![previous], op);
+        previous = name;
+    }
+    graph
+}
+
+/// `diamond_graph` stacks `diamonds` diamonds: a `Node` feeding two siblings that a fourth `Node` joins again, which
+/// feeds the next diamond. The last join is `output`. It panics if `diamonds` is 0, which would leave no `output`.
+pub fn diamond_graph(diamonds: usize, op: OpFn) -> Graph {
+    assert!(diamonds > 0, "A diamond graph needs at least 1 diamond");
+    let mut graph = Graph::default();
+    let mut previous = "entrypoint".to_string();
+    for i in 0..diamonds {
+        let (top, left, right) = (format!("top{i}"), format!("left{i}"), format!("right{i}"));
+        let bottom = if i + 1 == diamonds {
+            "output".to_string()
+        } else {
+            format!("bottom{i}")
+        };
+        graph.stage_node(top.clone(), vec![previous], op);
+        graph.stage_node(left.clone(), vec![top.clone()], op);
+        graph.stage_node(right.clone(), vec![top], op);
+        graph.stage_node(bottom.clone(), vec![left, right], op);
+        previous = bottom;
+    }
+    graph
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wrap;
+    use std::fs;
+
+    async fn concat(x: Vec<String>) -> String {
+        x.concat()
+    }
+
+    #[tokio::test]
+    async fn benchmarks_op_on_recorded_inputs() {
+        let path = std::env::temp_dir().join(format!("bench-{}.jsonl", std::process::id()));
+        fs::write(
+            &path,
+            concat!(
+                r#"{"inputs":["a","b"],"node":"A","output":"ab"}"#,
+                "\n",
+                r#"{"inputs":["z"],"node":"B","output":"z"}"#,
+                "\n",
+            ),
+        )
+        .unwrap();
+        let inputs = sampled_inputs(&path, "A").unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(inputs, vec![vec!["a".to_string(), "b".to_string()]]);
+
+        let report = bench_op(wrap!(concat), &inputs, 5).await;
+        assert_eq!(report.latencies.len(), 5);
+        assert!(report.percentile(0.5) <= report.percentile(0.99));
+        assert_eq!(report.allocations_per_call, None);
+    }
+
+    async fn count(x: Vec<String>) -> String {
+        x.iter()
+            .map(|v| v.parse::<u64>().unwrap())
+            .sum::<u64>()
+            .max(1)
+            .to_string()
+    }
+
+    #[tokio::test]
+    async fn synthetic_graphs_run() {
+        let output = wide_graph(10, wrap!(count))
+            .run("1".into(), "output".into())
+            .await;
+        assert_eq!(output.unwrap(), "10");
+        let output = deep_graph(10, wrap!(count))
+            .run("1".into(), "output".into())
+            .await;
+        assert_eq!(output.unwrap(), "1");
+        let output = diamond_graph(3, wrap!(count))
+            .run("1".into(), "output".into())
+            .await;
+        assert_eq!(output.unwrap(), "8");
+    }
+
+    #[test]
+    #[should_panic(expected = "A deep graph needs a depth of at least 1")]
+    fn rejects_a_graph_without_output() {
+        deep_graph(0, wrap!(count));
+    }
+}
@@ -0,0 +1,169 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+/// A `CachePolicy` describes how long a `Node`s output stays valid for a given set of inputs. An entry younger than
+/// `ttl` is fresh and is returned without running the `op`. An entry older than `ttl` but still within the additional
+/// `stale_while_revalidate` window is returned immediately as well, and a refresh of it is queued on the `Graph`.
+/// Entries past both are dropped, and with a `capacity` the oldest entries make room for new ones.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CachePolicy {
+    pub ttl: Duration,
+    pub stale_while_revalidate: Duration,
+    pub capacity: Option<usize>,
+}
+
+impl CachePolicy {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            stale_while_revalidate: Duration::ZERO,
+            capacity: None,
+        }
+    }
+
+    /// Keeps serving an expired entry for up to `window` past its `ttl` while it is refreshed in the background.
+    pub fn with_stale_while_revalidate(mut self, window: Duration) -> Self {
+        self.stale_while_revalidate = window;
+        self
+    }
+
+    /// Keeps at most `capacity` entries, dropping the oldest one when a new one doesn't fit.
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = Some(capacity.max(1));
+        self
+    }
+
+    fn expired(&self, age: Duration) -> bool {
+        age >= self.ttl + self.stale_while_revalidate
+    }
+}
+
+/// What `Graph::prime_cache` did: how many inputs it ran, and the inputs whose runs failed, with why.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PrimeReport {
+    pub runs: usize,
+    pub failures: Vec<(String, String)>,
+}
+
+struct Entry {
+    value: String,
+    stored_at: Instant,
+    refreshing: bool,
+}
+
+pub(crate) enum Lookup {
+    Hit(String),
+    Stale(String),
+    Miss,
+}
+
+/// The cached outputs of a single `Node`, keyed by the inputs its `op` was called with.
+pub(crate) struct NodeCache {
+    policy: CachePolicy,
+    entries: HashMap<Vec<String>, Entry>,
+    /// The inputs of every entry in the order they were stored, oldest first, with when. Every entry lives for the
+    /// same `ttl`, so the expired ones are always at the front. Entries stored again since are still listed under
+    /// their old time, and are skipped.
+    stored: VecDeque<(Vec<String>, Instant)>,
+}
+
+impl NodeCache {
+    pub(crate) fn new(policy: CachePolicy) -> Self {
+        Self {
+            policy,
+            entries: HashMap::new(),
+            stored: VecDeque::new(),
+        }
+    }
+
+    /// Looks up `inputs`. A `Stale` result marks the entry as refreshing, so only the first caller to see it
+    /// stale is responsible for the refresh.
+    pub(crate) fn lookup(&mut self, inputs: &[String]) -> Lookup {
+        let Some(entry) = self.entries.get_mut(inputs) else {
+            return Lookup::Miss;
+        };
+        let age = entry.stored_at.elapsed();
+        if age < self.policy.ttl {
+            Lookup::Hit(entry.value.clone())
+        } else if age < self.policy.ttl + self.policy.stale_while_revalidate {
+            if entry.refreshing {
+                Lookup::Hit(entry.value.clone())
+            } else {
+                entry.refreshing = true;
+                Lookup::Stale(entry.value.clone())
+            }
+        } else {
+            self.entries.remove(inputs);
+            Lookup::Miss
+        }
+    }
+
+    /// Lets a later lookup retry refreshing an entry whose refresh failed.
+    pub(crate) fn abandon_refresh(&mut self, inputs: &[String]) {
+        if let Some(entry) = self.entries.get_mut(inputs) {
+            entry.refreshing = false;
+        }
+    }
+
+    pub(crate) fn store(&mut self, inputs: Vec<String>, value: String) {
+        let stored_at = Instant::now();
+        self.stored.push_back((inputs.clone(), stored_at));
+        let entry = Entry {
+            value,
+            stored_at,
+            refreshing: false,
+        };
+        self.entries.insert(inputs, entry);
+        self.evict();
+    }
+
+    /// Drops expired entries, and the oldest ones while there are more than the `capacity`.
+    fn evict(&mut self) {
+        while let Some((inputs, stored_at)) = self.stored.front() {
+            let current = self
+                .entries
+                .get(inputs)
+                .is_some_and(|entry| entry.stored_at == *stored_at);
+            let full = self
+                .policy
+                .capacity
+                .is_some_and(|capacity| self.entries.len() > capacity);
+            if current && !full && !self.policy.expired(stored_at.elapsed()) {
+                break;
+            }
+            if current {
+                self.entries.remove(inputs);
+            }
+            self.stored.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inputs(input: &str) -> Vec<String> {
+        vec![input.to_string()]
+    }
+
+    #[test]
+    fn drops_expired_and_oldest_entries() {
+        let mut cache = NodeCache::new(CachePolicy::new(Duration::from_secs(60)).with_capacity(2));
+        cache.store(inputs("a"), "1".into());
+        cache.store(inputs("b"), "2".into());
+        cache.store(inputs("a"), "3".into());
+        cache.store(inputs("c"), "4".into());
+        assert!(matches!(cache.lookup(&inputs("b")), Lookup::Miss));
+        assert!(matches!(cache.lookup(&inputs("a")), Lookup::Hit(value) if value == "3"));
+        assert_eq!(cache.entries.len(), 2);
+        assert_eq!(cache.stored.len(), 2);
+
+        let mut cache = NodeCache::new(CachePolicy::new(Duration::ZERO));
+        for input in ["a", "b", "c"] {
+            cache.store(inputs(input), input.into());
+        }
+        assert!(cache.entries.is_empty());
+        assert!(cache.stored.is_empty());
+    }
+}
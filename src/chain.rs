@@ -0,0 +1,439 @@
+/*!
+Import pipelines written as LangChain Expression Language style chain definitions. A chain is a JSON tree of steps,
+each an object with a `type`:
+
+- `{"type": "sequence", "steps": [...]}` feeds the output of each step into the next one.
+- `{"type": "parallel", "steps": [...]}` runs every step on the same input.
+- `{"type": "map", "steps": {"key": ..., ...}}` runs every step on the same input, naming each branch by its key.
+- `{"type": "prompt", "template": "..."}` fills in a prompt template. `{input}` is the step's input when it has only
+  one; after a `map`, `{key}` is the output of that branch, and after a `parallel`, `{0}`, `{1}`, ... are the outputs
+  in order.
+- `{"type": "model", "op": "..."}` and `{"type": "op", "op": "..."}` call an op from the `OpRegistry`.
+
+Any step except `sequence` may also have a `name`, which becomes the name of its `Node`. The first step receives the
+value passed to `Graph::run`, and the chain must end in a single step, whose name is returned as the output to ask for.
+```
+use inference_graph::chain::import_chain;
+use inference_graph::registry::OpRegistry;
+use inference_graph::wrap;
+
+async fn shout(x: Vec<String>) -> String {
+  x.concat().to_uppercase()
+}
+
+#[tokio::main]
+async fn main() {
+  let mut registry = OpRegistry::default();
+  registry.register("llm", wrap!(shout));
+  let chain = import_chain(r#"{"type": "sequence", "steps": [
+    {"type": "prompt", "template": "say {input}"},
+    {"type": "model", "op": "llm"}
+  ]}"#, &registry).unwrap();
+  let output = chain.graph.run("hi".into(), chain.output).await;
+  assert_eq!(output.unwrap(), "SAY HI".to_string());
+}
+```
+*/
+
+use crate::graph::{Graph, Op};
+use crate::registry::OpRegistry;
+use crate::spec::{expected, object, pointer, required_string, SpecError};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::rc::Rc;
+
+/// The `Graph` built from a chain definition, and the name of the `Node` producing the chain's final output.
+pub struct ImportedChain {
+    pub graph: Graph,
+    pub output: String,
+}
+
+/// An edge into a step: the `Node` providing the value, and the key a prompt template refers to it by.
+#[derive(Clone)]
+struct Input {
+    node: String,
+    key: String,
+}
+
+/// Parses the chain definition in `text` and builds it into a `Graph`, looking up `model` and `op` steps in
+/// `registry`. Problems are reported with the JSON pointer of the step they were found in.
+pub fn import_chain(text: &str, registry: &OpRegistry) -> Result<ImportedChain, Vec<SpecError>> {
+    let value: Value = serde_json::from_str(text)
+        .map_err(|e| vec![SpecError::new("", format!("invalid JSON: {e}"))])?;
+    let mut importer = Importer {
+        registry,
+        graph: Graph::default(),
+        names: HashSet::new(),
+        errors: vec![],
+    };
+    let entry = Input {
+        node: "entrypoint".into(),
+        key: "input".into(),
+    };
+    let outputs = importer.step(&value, "", vec![entry], None);
+    if importer.errors.is_empty() && outputs.len() != 1 {
+        importer.errors.push(SpecError::new(
+            "",
+            format!(
+                "a chain must end in a single step, this one ends in {}",
+                outputs.len()
+            ),
+        ));
+    }
+    if !importer.errors.is_empty() {
+        return Err(importer.errors);
+    }
+    Ok(ImportedChain {
+        graph: importer.graph,
+        output: outputs.into_iter().next().expect("checked above").node,
+    })
+}
+
+struct Importer<'a> {
+    registry: &'a OpRegistry,
+    graph: Graph,
+    names: HashSet<String>,
+    errors: Vec<SpecError>,
+}
+
+impl Importer<'_> {
+    /// Stages the step at `path` with `inputs` and returns the edges out of it.
+    fn step(
+        &mut self,
+        value: &Value,
+        path: &str,
+        inputs: Vec<Input>,
+        key: Option<&str>,
+    ) -> Vec<Input> {
+        let kind = match value.get("type") {
+            Some(Value::String(kind)) => kind.as_str(),
+            Some(other) => {
+                self.errors
+                    .push(expected(&format!("{path}/type"), "a string", other));
+                return vec![];
+            }
+            None => {
+                self.errors
+                    .push(SpecError::new(path, "missing required property `type`"));
+                return vec![];
+            }
+        };
+        match kind {
+            "sequence" => self.sequence(value, path, inputs),
+            "parallel" | "map" => self.parallel(value, path, inputs, kind == "map"),
+            "prompt" => self.prompt(value, path, inputs, key),
+            "model" | "op" => self.op(value, path, inputs, key),
+            other => {
+                self.errors.push(SpecError::new(
+                    format!("{path}/type"),
+                    format!(
+                        "unknown step type `{other}`, expected one of: sequence, parallel, map, prompt, model, op"
+                    ),
+                ));
+                vec![]
+            }
+        }
+    }
+
+    fn sequence(&mut self, value: &Value, path: &str, inputs: Vec<Input>) -> Vec<Input> {
+        let Some(step) = object(value, path, &["type", "steps"], &mut self.errors) else {
+            return vec![];
+        };
+        let steps = match self.steps(step, path) {
+            Some(Value::Array(steps)) => steps,
+            Some(other) => {
+                self.errors
+                    .push(expected(&format!("{path}/steps"), "an array", other));
+                return vec![];
+            }
+            None => return vec![],
+        };
+        steps.iter().enumerate().fold(inputs, |inputs, (i, step)| {
+            self.step(step, &format!("{path}/steps/{i}"), inputs, None)
+        })
+    }
+
+    fn parallel(&mut self, value: &Value, path: &str, inputs: Vec<Input>, map: bool) -> Vec<Input> {
+        let Some(step) = object(value, path, &["type", "steps"], &mut self.errors) else {
+            return vec![];
+        };
+        let Some(steps) = self.steps(step, path) else {
+            return vec![];
+        };
+        let branches: Vec<(String, &Value)> = match (map, steps) {
+            (true, Value::Object(steps)) => steps.iter().map(|(k, v)| (k.clone(), v)).collect(),
+            (false, Value::Array(steps)) => steps
+                .iter()
+                .enumerate()
+                .map(|(i, v)| (i.to_string(), v))
+                .collect(),
+            (true, other) => {
+                self.errors
+                    .push(expected(&format!("{path}/steps"), "an object", other));
+                return vec![];
+            }
+            (false, other) => {
+                self.errors
+                    .push(expected(&format!("{path}/steps"), "an array", other));
+                return vec![];
+            }
+        };
+        let mut outputs = vec![];
+        for (key, branch) in branches {
+            let branch_path = pointer(&format!("{path}/steps"), &key);
+            let hint = map.then_some(key.as_str());
+            let branch_outputs = self.step(branch, &branch_path, inputs.clone(), hint);
+            let single = branch_outputs.len() == 1;
+            for (i, output) in branch_outputs.into_iter().enumerate() {
+                let key = if single {
+                    key.clone()
+                } else {
+                    format!("{key}.{i}")
+                };
+                outputs.push(Input {
+                    node: output.node,
+                    key,
+                });
+            }
+        }
+        outputs
+    }
+
+    fn steps<'v>(
+        &mut self,
+        step: &'v serde_json::Map<String, Value>,
+        path: &str,
+    ) -> Option<&'v Value> {
+        let steps = step.get("steps");
+        if steps.is_none() {
+            self.errors
+                .push(SpecError::new(path, "missing required property `steps`"));
+        }
+        steps
+    }
+
+    fn prompt(
+        &mut self,
+        value: &Value,
+        path: &str,
+        inputs: Vec<Input>,
+        key: Option<&str>,
+    ) -> Vec<Input> {
+        let Some(step) = object(value, path, &["type", "name", "template"], &mut self.errors)
+        else {
+            return vec![];
+        };
+        let Some(template) = required_string(step, path, "template", &mut self.errors) else {
+            return vec![];
+        };
+        let keys: Vec<String> = inputs.iter().map(|input| input.key.clone()).collect();
+        let op: Op = Rc::new(move |x: Vec<String>| {
+            let prompt = render(&template, &keys, &x);
+            Box::pin(async move { Ok(Some(prompt)) })
+        });
+        self.stage(step, path, "prompt", inputs, key, op)
+    }
+
+    fn op(
+        &mut self,
+        value: &Value,
+        path: &str,
+        inputs: Vec<Input>,
+        key: Option<&str>,
+    ) -> Vec<Input> {
+        let Some(step) = object(value, path, &["type", "name", "op"], &mut self.errors) else {
+            return vec![];
+        };
+        let Some(name) = required_string(step, path, "op", &mut self.errors) else {
+            return vec![];
+        };
+        let op = match self.registry.build(&name, None) {
+            Some(Ok(op)) => op,
+            Some(Err(message)) => {
+                self.errors
+                    .push(SpecError::new(format!("{path}/op"), message));
+                return vec![];
+            }
+            None => {
+                self.errors.push(SpecError::new(
+                    format!("{path}/op"),
+                    format!("no op is registered as `{name}`"),
+                ));
+                return vec![];
+            }
+        };
+        let kind = if step["type"] == "model" {
+            "model"
+        } else {
+            "op"
+        };
+        self.stage(step, path, kind, inputs, key, op)
+    }
+
+    /// Stages a leaf step as a `Node`, named after its `name` property, its key in a `map`, or its position.
+    fn stage(
+        &mut self,
+        step: &serde_json::Map<String, Value>,
+        path: &str,
+        kind: &str,
+        inputs: Vec<Input>,
+        key: Option<&str>,
+        op: Op,
+    ) -> Vec<Input> {
+        let name = match step.get("name") {
+            Some(Value::String(name)) => name.clone(),
+            Some(other) => {
+                self.errors
+                    .push(expected(&format!("{path}/name"), "a string", other));
+                return vec![];
+            }
+            None => match key {
+                Some(key) if !self.names.contains(key) => key.to_string(),
+                _ => (self.names.len()..)
+                    .map(|i| format!("{kind}_{i}"))
+                    .find(|name| !self.names.contains(name))
+                    .expect("some name is free"),
+            },
+        };
+        if name == "entrypoint" || !self.names.insert(name.clone()) {
+            self.errors.push(SpecError::new(
+                format!("{path}/name"),
+                format!("node name `{name}` is already taken"),
+            ));
+            return vec![];
+        }
+        let inputs = inputs.into_iter().map(|input| input.node).collect();
+        self.graph.stage_op(name.clone(), inputs, op);
+        vec![Input {
+            node: name,
+            key: key.unwrap_or("input").to_string(),
+        }]
+    }
+}
+
+/// Fills in `template` in one pass, so placeholders within the values themselves are left as they are.
+fn render(template: &str, keys: &[String], values: &[String]) -> String {
+    let lookup = |name: &str| match values {
+        [value] if name == "input" => Some(value),
+        _ => keys.iter().position(|key| key == name).map(|i| &values[i]),
+    };
+    let mut prompt = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        prompt.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = rest.find('}');
+        match end.and_then(|end| lookup(&rest[1..end]).map(|value| (end, value))) {
+            Some((end, value)) => {
+                prompt.push_str(value);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                prompt.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    prompt.push_str(rest);
+    prompt
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wrap;
+
+    async fn shout(x: Vec<String>) -> String {
+        x.concat().to_uppercase()
+    }
+
+    async fn reverse(x: Vec<String>) -> String {
+        x.concat().chars().rev().collect()
+    }
+
+    #[tokio::test]
+    async fn imports_sequence_with_map() {
+        let mut registry = OpRegistry::default();
+        registry.register("shout", wrap!(shout));
+        registry.register("reverse", wrap!(reverse));
+        let chain = import_chain(
+            r#"{"type": "sequence", "steps": [
+                {"type": "map", "steps": {
+                    "loud": {"type": "model", "op": "shout"},
+                    "back": {"type": "op", "op": "reverse"}
+                }},
+                {"type": "prompt", "name": "combine", "template": "{loud} / {back}"}
+            ]}"#,
+            &registry,
+        )
+        .unwrap();
+
+        assert_eq!(chain.output, "combine");
+        let output = chain.graph.run("abc".into(), chain.output).await;
+        assert_eq!(output.unwrap(), "ABC / cba".to_string());
+    }
+
+    #[tokio::test]
+    async fn leaves_placeholders_in_values_alone() {
+        let mut registry = OpRegistry::default();
+        registry.register("shout", wrap!(shout));
+        let chain = import_chain(
+            r#"{"type": "sequence", "steps": [
+                {"type": "map", "steps": {
+                    "loud": {"type": "model", "op": "shout"},
+                    "back": {"type": "prompt", "template": "{input}"}
+                }},
+                {"type": "prompt", "template": "{back} / {loud} / {missing}"}
+            ]}"#,
+            &registry,
+        )
+        .unwrap();
+        let output = chain.graph.run("{loud}".into(), chain.output).await;
+        assert_eq!(output.unwrap(), "{loud} / {LOUD} / {missing}");
+    }
+
+    #[test]
+    fn names_unnamed_steps_around_named_ones() {
+        let chain = import_chain(
+            r#"{"type": "sequence", "steps": [
+                {"type": "prompt", "name": "prompt_1", "template": "{input}"},
+                {"type": "prompt", "template": "{input}!"}
+            ]}"#,
+            &OpRegistry::default(),
+        )
+        .unwrap();
+        assert_eq!(chain.output, "prompt_2");
+    }
+
+    #[derive(serde::Deserialize, crate::Op)]
+    struct Exclaim {}
+
+    impl Exclaim {
+        async fn call(&self, x: Vec<String>) -> String {
+            x.concat() + "!"
+        }
+    }
+
+    #[tokio::test]
+    async fn imports_derived_ops() {
+        let mut registry = OpRegistry::default();
+        registry.register_struct::<Exclaim>();
+        let chain = import_chain(r#"{"type": "model", "op": "exclaim"}"#, &registry).unwrap();
+        let output = chain.graph.run("hi".into(), chain.output).await;
+        assert_eq!(output.unwrap(), "hi!");
+    }
+
+    #[test]
+    fn reports_unknown_ops_by_location() {
+        let errors = import_chain(
+            r#"{"type": "sequence", "steps": [{"type": "model", "op": "gpt"}]}"#,
+            &OpRegistry::default(),
+        )
+        .err()
+        .unwrap();
+        assert_eq!(
+            errors[0].to_string(),
+            "/steps/0/op: no op is registered as `gpt`"
+        );
+    }
+}
@@ -0,0 +1,279 @@
+/*!
+Build a `Graph` by composing smaller pieces instead of naming every `Node` and edge by hand.
+
+- `op(f)` is a single `Node` running `f`.
+- `seq(a, b)` feeds the outputs of `a` into `b`.
+- `par([a, b, ...])` runs every piece on the same inputs, and outputs all of their outputs in order.
+- `branch(predicate, a, b)` runs `a` on its input if `predicate` holds for it, and `b` otherwise.
+
+Wherever a single value is needed, such as at the end of the pipeline, at the start of a `branch` or at the end of
+either side of one, several outputs are joined into a JSON array of them. Pieces are named after their kind and
+position, like `op_0` or `gate_3`, unless they are given a name with `Piece::named`.
+```
+use inference_graph::compose::{branch, op, par, seq};
+use inference_graph::wrap;
+
+async fn shout(x: Vec<String>) -> String {
+  x.concat().to_uppercase()
+}
+
+async fn reverse(x: Vec<String>) -> String {
+  x.concat().chars().rev().collect()
+}
+
+async fn join(x: Vec<String>) -> String {
+  x.join(" ")
+}
+
+#[tokio::main]
+async fn main() {
+  let pipeline = seq(
+    par([op(wrap!(shout)), op(wrap!(reverse))]),
+    op(wrap!(join)).named("join"),
+  );
+  let composed = branch(|x| x.len() > 3, pipeline, op(wrap!(shout))).build();
+  let output = composed.graph.run("abcd".into(), composed.output.clone()).await;
+  assert_eq!(output.unwrap(), "ABCD dcba");
+  let output = composed.graph.run("abc".into(), composed.output).await;
+  assert_eq!(output.unwrap(), "ABC");
+}
+```
+*/
+
+use crate::graph::{op_from_fn, Graph, OpFn, OpOn};
+use std::collections::HashSet;
+use std::rc::Rc;
+
+/// One piece of a pipeline, built with `op`, `seq`, `par` or `branch`.
+pub struct Piece {
+    kind: Kind,
+}
+
+enum Kind {
+    Op {
+        name: Option<String>,
+        op: OpFn,
+    },
+    Seq(Box<Piece>, Box<Piece>),
+    Par(Vec<Piece>),
+    Branch {
+        predicate: Rc<dyn Fn(&str) -> bool>,
+        then: Box<Piece>,
+        otherwise: Box<Piece>,
+    },
+}
+
+/// A `Piece` of a single `Node` running `op`.
+pub fn op(op: OpFn) -> Piece {
+    Piece {
+        kind: Kind::Op { name: None, op },
+    }
+}
+
+/// A `Piece` that feeds the outputs of `first` into `then`.
+pub fn seq(first: Piece, then: Piece) -> Piece {
+    Piece {
+        kind: Kind::Seq(Box::new(first), Box::new(then)),
+    }
+}
+
+/// A `Piece` that runs every one of `pieces` on the same inputs, outputting all of their outputs in order.
+pub fn par(pieces: impl IntoIterator<Item = Piece>) -> Piece {
+    Piece {
+        kind: Kind::Par(pieces.into_iter().collect()),
+    }
+}
+
+/// A `Piece` that runs `then` on its input if `predicate` holds for it, and `otherwise` if not. Only the side that
+/// is taken runs; the `Node`s of the other are skipped.
+pub fn branch(predicate: impl Fn(&str) -> bool + 'static, then: Piece, otherwise: Piece) -> Piece {
+    Piece {
+        kind: Kind::Branch {
+            predicate: Rc::new(predicate),
+            then: Box::new(then),
+            otherwise: Box::new(otherwise),
+        },
+    }
+}
+
+/// The `Graph` built from a `Piece`, and the name of the `Node` producing its final output.
+pub struct Composed {
+    pub graph: Graph,
+    pub output: String,
+}
+
+impl Piece {
+    /// `named` gives the `Node` of an `op` piece `name` instead of a generated one. It panics for any other piece.
+    pub fn named(mut self, name: &str) -> Self {
+        match &mut self.kind {
+            Kind::Op { name: named, .. } => *named = Some(name.to_string()),
+            _ => panic!("Only op pieces can be named, not {name}"),
+        }
+        self
+    }
+
+    /// `build` stages the pipeline into a new `Graph`, starting from `entrypoint`. It panics if two `Node`s end up
+    /// with the same name.
+    pub fn build(self) -> Composed {
+        let mut builder = Builder {
+            graph: Graph::default(),
+            names: HashSet::new(),
+        };
+        let outputs = builder.piece(self, vec!["entrypoint".into()], false);
+        let output = builder.single(outputs, false);
+        Composed {
+            graph: builder.graph,
+            output,
+        }
+    }
+}
+
+struct Builder {
+    graph: Graph,
+    names: HashSet<String>,
+}
+
+impl Builder {
+    /// Stages `piece` on `inputs` and returns the `Node`s it outputs. The `Node`s of a side of a `branch` are
+    /// `guarded`: they are skipped when an input has no value, because the other side was taken.
+    fn piece(&mut self, piece: Piece, inputs: Vec<String>, guarded: bool) -> Vec<String> {
+        match piece.kind {
+            Kind::Op { name, op } => {
+                let name = self.name(name, "op");
+                self.graph.stage_op(name.clone(), inputs, op_from_fn(op));
+                if guarded {
+                    self.graph.require_some(&name);
+                }
+                vec![name]
+            }
+            Kind::Seq(first, then) => {
+                let outputs = self.piece(*first, inputs, guarded);
+                self.piece(*then, outputs, guarded)
+            }
+            Kind::Par(pieces) => pieces
+                .into_iter()
+                .flat_map(|piece| self.piece(piece, inputs.clone(), guarded))
+                .collect(),
+            Kind::Branch {
+                predicate,
+                then,
+                otherwise,
+            } => {
+                let input = self.single(inputs, guarded);
+                let mut sides = vec![];
+                for (side, taken) in [(*then, true), (*otherwise, false)] {
+                    let gate = self.name(None, "gate");
+                    let predicate = predicate.clone();
+                    self.graph
+                        .stage_optional_node(gate.clone(), vec![input.clone()], move |x| {
+                            let pass = predicate(&x[0]) == taken;
+                            async move { pass.then(|| x.concat()) }
+                        });
+                    self.graph.require_some(&gate);
+                    let outputs = self.piece(side, vec![gate], true);
+                    sides.push(self.single(outputs, true));
+                }
+                let merge = self.name(None, "merge");
+                let first: OpOn<Vec<Option<String>>> = Rc::new(|x: Vec<Option<String>>| {
+                    let value = x.into_iter().flatten().next();
+                    Box::pin(async move { Ok(value) })
+                });
+                self.graph.stage_lenient_op(merge.clone(), sides, first);
+                // Inside another `branch`, neither side may have been taken.
+                if guarded {
+                    self.graph.set_optional(&merge);
+                }
+                vec![merge]
+            }
+        }
+    }
+
+    /// The one `Node` holding `outputs`: the `Node` itself if there is only one, or a new one joining them into a
+    /// JSON array.
+    fn single(&mut self, outputs: Vec<String>, guarded: bool) -> String {
+        if let [output] = outputs.as_slice() {
+            return output.clone();
+        }
+        let name = self.name(None, "join");
+        self.graph.stage_op(
+            name.clone(),
+            outputs,
+            Rc::new(|x: Vec<String>| {
+                let joined = serde_json::json!(x).to_string();
+                Box::pin(async move { Ok(Some(joined)) })
+            }),
+        );
+        if guarded {
+            self.graph.require_some(&name);
+        }
+        name
+    }
+
+    /// `name`, or a new name for a `Node` of `kind` if it is `None`.
+    fn name(&mut self, name: Option<String>, kind: &str) -> String {
+        let name = name.unwrap_or_else(|| {
+            (self.names.len()..)
+                .map(|i| format!("{kind}_{i}"))
+                .find(|name| !self.names.contains(name))
+                .expect("some name is free")
+        });
+        assert!(
+            name != "entrypoint" && self.names.insert(name.clone()),
+            "Node name {name} is already taken"
+        );
+        name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wrap;
+
+    async fn shout(x: Vec<String>) -> String {
+        x.concat().to_uppercase()
+    }
+
+    async fn reverse(x: Vec<String>) -> String {
+        x.concat().chars().rev().collect()
+    }
+
+    #[tokio::test]
+    async fn composes_nested_branches() {
+        let short = branch(
+            |x| x.starts_with('a'),
+            op(wrap!(shout)).named("loud"),
+            op(wrap!(reverse)),
+        );
+        let composed = branch(
+            |x| x.len() > 3,
+            par([op(wrap!(shout)), op(wrap!(reverse))]),
+            short,
+        )
+        .build();
+        assert_eq!(composed.graph.validate(), vec![]);
+
+        let output = composed
+            .graph
+            .run("abcd".into(), composed.output.clone())
+            .await;
+        assert_eq!(output.unwrap(), r#"["ABCD","dcba"]"#);
+        let output = composed
+            .graph
+            .run("abc".into(), composed.output.clone())
+            .await;
+        assert_eq!(output.unwrap(), "ABC");
+        let output = composed.graph.run("xyz".into(), composed.output).await;
+        assert_eq!(output.unwrap(), "zyx");
+    }
+
+    #[test]
+    #[should_panic(expected = "Node name loud is already taken")]
+    fn rejects_duplicate_names() {
+        seq(
+            op(wrap!(shout)).named("loud"),
+            op(wrap!(shout)).named("loud"),
+        )
+        .build();
+    }
+}
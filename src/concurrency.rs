@@ -0,0 +1,389 @@
+use crate::options::Priority;
+use std::cell::RefCell;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+
+/// `ConcurrencyLimit` caps how many `op`s of a `Graph` may be running at the same time. `Fixed` is a hand-set limit,
+/// which must be at least 1. `Adaptive` starts at `AimdConfig::initial` and tunes itself from how long `op`s take to
+/// finish and whether they fail.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConcurrencyLimit {
+    Fixed(usize),
+    Adaptive(AimdConfig),
+}
+
+/// Settings for additive-increase/multiplicative-decrease tuning of the in-flight limit. Every `op` that succeeds
+/// within `latency_target` grows the limit by roughly one per full window of `op`s, and every `op` that takes longer,
+/// fails or times out multiplies it by `backoff`. The limit always stays between `min` and `max`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AimdConfig {
+    pub initial: usize,
+    pub min: usize,
+    pub max: usize,
+    pub latency_target: Duration,
+    pub backoff: f64,
+}
+
+impl AimdConfig {
+    pub fn new(latency_target: Duration) -> Self {
+        Self {
+            initial: 4,
+            min: 1,
+            max: 256,
+            latency_target,
+            backoff: 0.5,
+        }
+    }
+}
+
+/// `AdmissionLimit` bounds how many runs of a `Graph` may be in flight at once (`max_running`) and how many more may
+/// wait for a turn (`max_queued`). Runs beyond that fail straight away with `RunError::Overloaded`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AdmissionLimit {
+    pub max_running: usize,
+    pub max_queued: usize,
+}
+
+type Waiter = (Duration, oneshot::Sender<Permit>);
+
+struct State {
+    mode: ConcurrencyLimit,
+    limit: f64,
+    in_flight: usize,
+    /// Waiting callers, highest `Priority` first, then queued per tenant with how urgent each caller is.
+    waiters: BTreeMap<Reverse<Priority>, HashMap<String, VecDeque<Waiter>>>,
+    weights: HashMap<String, u32>,
+    /// Start-time fair queuing between tenants: the next permit goes to the waiting tenant with the lowest virtual
+    /// time, which then advances by `1 / weight`. A tenant that starts waiting again resumes no earlier than `clock`,
+    /// so idle tenants can't bank credit.
+    virtual_times: HashMap<String, f64>,
+    clock: f64,
+}
+
+impl State {
+    fn new(mode: ConcurrencyLimit) -> Self {
+        let limit = match mode {
+            ConcurrencyLimit::Fixed(n) => n,
+            ConcurrencyLimit::Adaptive(config) => {
+                config.initial.clamp(config.min, config.max).max(1)
+            }
+        };
+        Self {
+            mode,
+            limit: limit as f64,
+            in_flight: 0,
+            waiters: BTreeMap::new(),
+            weights: HashMap::new(),
+            virtual_times: HashMap::new(),
+            clock: 0.0,
+        }
+    }
+
+    fn enqueue(
+        &mut self,
+        priority: Priority,
+        tenant: &str,
+        urgency: Duration,
+        tx: oneshot::Sender<Permit>,
+    ) {
+        let queue = self
+            .waiters
+            .entry(Reverse(priority))
+            .or_default()
+            .entry(tenant.to_string())
+            .or_default();
+        if queue.is_empty() {
+            let time = self.virtual_times.entry(tenant.to_string()).or_default();
+            *time = time.max(self.clock);
+        }
+        queue.push_back((urgency, tx));
+    }
+
+    fn dequeue(&mut self) -> Option<oneshot::Sender<Permit>> {
+        let mut level = self.waiters.first_entry()?;
+        let tenants = level.get_mut();
+        let tenant = tenants
+            .keys()
+            .min_by(|a, b| {
+                self.virtual_times[*a]
+                    .total_cmp(&self.virtual_times[*b])
+                    .then(a.cmp(b))
+            })?
+            .clone();
+        let queue = tenants.get_mut(&tenant).expect("tenant was just found");
+        // The most urgent waiter goes first, and the earliest of those.
+        let most_urgent = queue
+            .iter()
+            .enumerate()
+            .max_by_key(|(i, (urgency, _))| (*urgency, Reverse(*i)))
+            .map(|(i, _)| i)?;
+        let tx = queue.remove(most_urgent).map(|(_, tx)| tx);
+        if queue.is_empty() {
+            tenants.remove(&tenant);
+        }
+        if tenants.is_empty() {
+            level.remove();
+        }
+        let weight = self.weights.get(&tenant).copied().unwrap_or(1).max(1);
+        let time = self
+            .virtual_times
+            .get_mut(&tenant)
+            .expect("tenant was queued");
+        self.clock = *time;
+        *time += 1.0 / f64::from(weight);
+        tx
+    }
+
+    fn limit(&self) -> usize {
+        self.limit as usize
+    }
+
+    /// Tunes an adaptive limit after an `op` took `latency`, and `failed` if it returned an error or timed out.
+    fn record(&mut self, latency: Duration, failed: bool) {
+        let ConcurrencyLimit::Adaptive(config) = self.mode else {
+            return;
+        };
+        let limit = if !failed && latency <= config.latency_target {
+            self.limit + 1.0 / self.limit
+        } else {
+            self.limit * config.backoff
+        };
+        self.limit = limit.clamp(config.min.max(1) as f64, config.max.max(1) as f64);
+    }
+}
+
+/// Hands out `Permit`s by `Priority`, then fairly between tenants by weight, then by urgency and finally in the order
+/// they were asked for, never more at once than the current limit.
+pub(crate) struct Limiter {
+    state: RefCell<State>,
+}
+
+impl Limiter {
+    pub(crate) fn new(mode: ConcurrencyLimit) -> Rc<Self> {
+        Rc::new(Self {
+            state: RefCell::new(State::new(mode)),
+        })
+    }
+
+    pub(crate) fn limit(&self) -> usize {
+        self.state.borrow().limit()
+    }
+
+    /// Whether a caller of `acquire` would have to wait, and how many already are.
+    pub(crate) fn queue(&self) -> Option<usize> {
+        let state = self.state.borrow();
+        let waiting: usize = state
+            .waiters
+            .values()
+            .flat_map(HashMap::values)
+            .map(VecDeque::len)
+            .sum();
+        (waiting > 0 || state.in_flight >= state.limit()).then_some(waiting)
+    }
+
+    pub(crate) fn set_weight(&self, tenant: &str, weight: u32) {
+        self.state
+            .borrow_mut()
+            .weights
+            .insert(tenant.to_string(), weight);
+    }
+
+    /// `urgency` orders the waiters of one tenant at the same `Priority`, most urgent first.
+    pub(crate) async fn acquire(
+        self: &Rc<Self>,
+        priority: Priority,
+        tenant: &str,
+        urgency: Duration,
+    ) -> Permit {
+        let waiting = {
+            let mut state = self.state.borrow_mut();
+            if state.waiters.is_empty() && state.in_flight < state.limit() {
+                state.in_flight += 1;
+                None
+            } else {
+                let (tx, rx) = oneshot::channel();
+                state.enqueue(priority, tenant, urgency, tx);
+                Some(rx)
+            }
+        };
+        let mut permit = match waiting {
+            None => Permit::new(self.clone()),
+            Some(rx) => rx
+                .await
+                .expect("Limiter dropped while a permit was pending"),
+        };
+        permit.started = Some(Instant::now());
+        permit
+    }
+
+    fn release(self: &Rc<Self>, latency: Option<Duration>, failed: bool) {
+        {
+            let mut state = self.state.borrow_mut();
+            state.in_flight -= 1;
+            if let Some(latency) = latency {
+                state.record(latency, failed);
+            }
+        }
+        self.dispatch();
+    }
+
+    fn dispatch(self: &Rc<Self>) {
+        loop {
+            let tx = {
+                let mut state = self.state.borrow_mut();
+                if state.in_flight >= state.limit() {
+                    return;
+                }
+                let Some(tx) = state.dequeue() else {
+                    return;
+                };
+                state.in_flight += 1;
+                tx
+            };
+            // A waiter that gave up hands its permit straight back, which releases it again.
+            let _ = tx.send(Permit::new(self.clone()));
+        }
+    }
+}
+
+/// Held while an `op` runs. Dropping it frees the slot and, for an adaptive limit, reports how long the `op` took and
+/// whether it failed.
+pub(crate) struct Permit {
+    limiter: Rc<Limiter>,
+    started: Option<Instant>,
+    failed: bool,
+}
+
+impl Permit {
+    fn new(limiter: Rc<Limiter>) -> Self {
+        Self {
+            limiter,
+            started: None,
+            failed: false,
+        }
+    }
+
+    /// Records that the `op` failed or timed out.
+    pub(crate) fn fail(&mut self) {
+        self.failed = true;
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let latency = self.started.map(|started| started.elapsed());
+        self.limiter.release(latency, self.failed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aimd_grows_when_fast_and_backs_off_when_slow() {
+        let mut config = AimdConfig::new(Duration::from_millis(100));
+        config.initial = 2;
+        let mut state = State::new(ConcurrencyLimit::Adaptive(config));
+
+        for _ in 0..10 {
+            state.record(Duration::from_millis(1), false);
+        }
+        assert!(state.limit() > 2);
+
+        let grown = state.limit();
+        state.record(Duration::from_secs(1), false);
+        assert_eq!(state.limit(), grown / 2);
+
+        let halved = state.limit();
+        state.record(Duration::from_millis(1), true);
+        assert_eq!(state.limit(), halved / 2);
+
+        for _ in 0..20 {
+            state.record(Duration::from_secs(1), false);
+        }
+        assert_eq!(state.limit(), 1);
+    }
+
+    #[tokio::test]
+    async fn higher_priority_waiters_go_first() {
+        let limiter = Limiter::new(ConcurrencyLimit::Fixed(1));
+        let held = limiter.acquire(Priority::Normal, "", Duration::ZERO).await;
+        let order = RefCell::new(vec![]);
+        let wait = |priority| {
+            let limiter = limiter.clone();
+            let order = &order;
+            async move {
+                let _permit = limiter.acquire(priority, "", Duration::ZERO).await;
+                order.borrow_mut().push(priority);
+            }
+        };
+        let release = async { drop(held) };
+        futures::join!(
+            wait(Priority::Batch),
+            wait(Priority::Interactive),
+            wait(Priority::Normal),
+            release
+        );
+        assert_eq!(
+            *order.borrow(),
+            vec![Priority::Interactive, Priority::Normal, Priority::Batch]
+        );
+    }
+
+    #[tokio::test]
+    async fn tenants_share_by_weight() {
+        let limiter = Limiter::new(ConcurrencyLimit::Fixed(1));
+        limiter.set_weight("big", 2);
+        let held = limiter.acquire(Priority::Normal, "", Duration::ZERO).await;
+        let order = RefCell::new(vec![]);
+        let wait = |tenant: &'static str| {
+            let limiter = limiter.clone();
+            let order = &order;
+            async move {
+                let _permit = limiter
+                    .acquire(Priority::Normal, tenant, Duration::ZERO)
+                    .await;
+                order.borrow_mut().push(tenant);
+            }
+        };
+        let release = async { drop(held) };
+        futures::join!(
+            wait("noisy"),
+            wait("noisy"),
+            wait("noisy"),
+            wait("noisy"),
+            wait("big"),
+            wait("big"),
+            wait("big"),
+            wait("big"),
+            release
+        );
+        assert_eq!(
+            *order.borrow(),
+            vec!["big", "noisy", "big", "big", "noisy", "big", "noisy", "noisy"]
+        );
+    }
+
+    #[tokio::test]
+    async fn more_urgent_waiters_go_first() {
+        let limiter = Limiter::new(ConcurrencyLimit::Fixed(1));
+        let held = limiter.acquire(Priority::Normal, "", Duration::ZERO).await;
+        let order = RefCell::new(vec![]);
+        let wait = |urgency: u64| {
+            let limiter = limiter.clone();
+            let order = &order;
+            async move {
+                let urgency = Duration::from_millis(urgency);
+                let _permit = limiter.acquire(Priority::Normal, "", urgency).await;
+                order.borrow_mut().push(urgency.as_millis());
+            }
+        };
+        let release = async { drop(held) };
+        futures::join!(wait(10), wait(50), wait(10), wait(30), release);
+        assert_eq!(*order.borrow(), vec![50, 30, 10, 10]);
+    }
+}
@@ -0,0 +1,199 @@
+use crate::id::RunId;
+use serde_json::{json, Value};
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::rc::Rc;
+use tokio::sync::Notify;
+
+/// A `RunControl` lets an operator reach into a run while it is in flight. Attach a clone of it to the run with
+/// `RunOptions::with_control` and keep the original; both refer to the same run. A `RunControl` is meant for one run
+/// at a time.
+#[derive(Clone, Default)]
+pub struct RunControl {
+    inner: Rc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    overrides: RefCell<HashMap<String, String>>,
+    notify: Notify,
+    states: RefCell<BTreeMap<String, NodeState>>,
+    run_id: Cell<Option<RunId>>,
+    cancelled: Cell<bool>,
+}
+
+/// Where one `Node` of a run is at, as seen by `RunControl::snapshot`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NodeState {
+    /// Still waiting for the values of the `Node`s in `on`.
+    Waiting {
+        on: Vec<String>,
+    },
+    /// Has all of its inputs and is working out its value, which includes waiting for a rate limit or a
+    /// concurrency slot.
+    Running,
+    Done,
+    Failed {
+        error: String,
+    },
+}
+
+/// A `RunSnapshot` says where every `Node` of a run is at. When a run hangs, the `Running` nodes are the ones to
+/// look at, and the `Waiting` ones say what they are blocked on.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RunSnapshot {
+    /// The run being tracked, once it has started.
+    pub run_id: Option<RunId>,
+    pub nodes: BTreeMap<String, NodeState>,
+}
+
+impl RunSnapshot {
+    /// The snapshot as JSON, e.g. `{"run_id": "01ARZ3NDEKTSV4RRFFQ69G5FAV", "nodes": {"A": {"state": "waiting",
+    /// "on": ["entrypoint"]}}}`.
+    pub fn to_json(&self) -> Value {
+        let nodes: serde_json::Map<String, Value> = self
+            .nodes
+            .iter()
+            .map(|(name, state)| {
+                let state = match state {
+                    NodeState::Waiting { on } => json!({"state": "waiting", "on": on}),
+                    NodeState::Running => json!({"state": "running"}),
+                    NodeState::Done => json!({"state": "done"}),
+                    NodeState::Failed { error } => json!({"state": "failed", "error": error}),
+                };
+                (name.clone(), state)
+            })
+            .collect();
+        let run_id = self.run_id.map(|id| id.to_string());
+        json!({ "run_id": run_id, "nodes": nodes })
+    }
+}
+
+impl RunControl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `override_node` makes the `Node` called `node` finish with `value` straight away, whether it is still waiting
+    /// for its inputs or in the middle of its `op`, which is dropped. This unblocks a run stuck behind a `Node` that
+    /// hangs, by letting an operator supply the value it should have produced. Overriding a `Node` that has already
+    /// finished has no effect on the run.
+    pub fn override_node(&self, node: &str, value: String) {
+        self.inner
+            .overrides
+            .borrow_mut()
+            .insert(node.to_string(), value);
+        self.inner.notify.notify_waiters();
+    }
+
+    /// `cancel` stops the run: `Node`s that are running are dropped, no more are started, and the run ends with
+    /// `RunOutcome::Cancelled` unless it has already finished.
+    pub fn cancel(&self) {
+        self.inner.cancelled.set(true);
+        self.inner.notify.notify_waiters();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.get()
+    }
+
+    /// `snapshot` reports where every `Node` of the run is at right now. Before the run starts it is empty, and after
+    /// it ends it shows where the run stopped.
+    pub fn snapshot(&self) -> RunSnapshot {
+        RunSnapshot {
+            run_id: self.inner.run_id.get(),
+            nodes: self.inner.states.borrow().clone(),
+        }
+    }
+
+    /// Starts tracking a new run of `nodes`, each listed with its inputs.
+    pub(crate) fn begin<'a>(
+        &self,
+        run_id: RunId,
+        nodes: impl IntoIterator<Item = (&'a str, &'a [String])>,
+    ) {
+        self.inner.run_id.set(Some(run_id));
+        *self.inner.states.borrow_mut() = nodes
+            .into_iter()
+            .map(|(name, inputs)| {
+                let on = inputs.to_vec();
+                (name.to_string(), NodeState::Waiting { on })
+            })
+            .collect();
+    }
+
+    /// Records that `node` received the value of `input`.
+    pub(crate) fn received(&self, node: &str, input: &str) {
+        if let Some(NodeState::Waiting { on }) = self.inner.states.borrow_mut().get_mut(node) {
+            if let Some(i) = on.iter().position(|name| name == input) {
+                on.remove(i);
+            }
+        }
+    }
+
+    pub(crate) fn set_state(&self, node: &str, state: NodeState) {
+        self.inner
+            .states
+            .borrow_mut()
+            .insert(node.to_string(), state);
+    }
+
+    /// The overridden `Node`s that are still waiting for their inputs.
+    pub(crate) fn waiting_overrides(&self) -> Vec<String> {
+        let states = self.inner.states.borrow();
+        let overrides = self.inner.overrides.borrow();
+        let waiting = overrides
+            .keys()
+            .filter(|node| matches!(states.get(*node), Some(NodeState::Waiting { .. })));
+        waiting.cloned().collect()
+    }
+
+    /// Waits until some `Node` is overridden or the run is cancelled.
+    pub(crate) async fn changed(&self) {
+        self.inner.notify.notified().await;
+    }
+
+    /// Waits until `node` is overridden and returns the value it was given.
+    pub(crate) async fn overridden(&self, node: &str) -> String {
+        loop {
+            let notified = self.inner.notify.notified();
+            if let Some(value) = self.inner.overrides.borrow_mut().remove(node) {
+                return value;
+            }
+            notified.await;
+        }
+    }
+}
+
+impl fmt::Debug for RunControl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RunControl")
+            .field("overrides", &self.inner.overrides.borrow().keys())
+            .field("states", &self.inner.states.borrow())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshot_tracks_what_nodes_wait_on() {
+        let control = RunControl::new();
+        let inputs = ["A".to_string(), "B".to_string()];
+        let run_id = "01ARZ3NDEKTSV4RRFFQ69G5FAV".parse().unwrap();
+        control.begin(run_id, [("A", &inputs[..0]), ("C", &inputs[..])]);
+        control.set_state("A", NodeState::Done);
+        control.received("C", "A");
+
+        assert_eq!(
+            control.snapshot().to_json(),
+            json!({"run_id": "01ARZ3NDEKTSV4RRFFQ69G5FAV", "nodes": {
+                "A": {"state": "done"},
+                "C": {"state": "waiting", "on": ["B"]},
+            }})
+        );
+    }
+}
@@ -0,0 +1,131 @@
+use std::fmt;
+use std::sync::mpsc;
+use tokio::sync::oneshot;
+
+type Job<T> = Box<dyn FnOnce(&mut T) + Send>;
+
+/// A `DedicatedThread` is an OS thread of its own that holds some state `T`, such as a handle into a native inference
+/// library that is neither `Send` nor async. `T` is built on the thread and never leaves it: `Node`s staged with
+/// `Graph::stage_threaded_node` send their calls over a channel and wait for the answer without blocking the run.
+/// Calls are handled one at a time, in the order they were made. Clones send to the same thread, which stops once
+/// every clone is dropped.
+pub struct DedicatedThread<T> {
+    jobs: mpsc::Sender<Job<T>>,
+}
+
+impl<T: 'static> DedicatedThread<T> {
+    /// Starts a thread called `name` and builds its state there with `build`.
+    pub fn spawn(name: &str, build: impl FnOnce() -> T + Send + 'static) -> Self {
+        let (jobs, queue) = mpsc::channel::<Job<T>>();
+        std::thread::Builder::new()
+            .name(name.to_string())
+            .spawn(move || {
+                let mut state = build();
+                for job in queue {
+                    job(&mut state);
+                }
+            })
+            .expect("failed to spawn a dedicated thread");
+        Self { jobs }
+    }
+
+    /// `call` runs `f` on the thread with its state and waits for what it returns. It returns `None` if the thread
+    /// has stopped, because an earlier call (or building the state) panicked.
+    pub async fn call<R: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut T) -> R + Send + 'static,
+    ) -> Option<R> {
+        let (reply, answer) = oneshot::channel();
+        let job: Job<T> = Box::new(move |state| {
+            let _ = reply.send(f(state));
+        });
+        self.jobs.send(job).ok()?;
+        answer.await.ok()
+    }
+
+    /// `send` runs `f` on the thread without waiting for it, after the calls made so far. It returns whether the
+    /// thread was still there to take it.
+    pub fn send(&self, f: impl FnOnce(&mut T) + Send + 'static) -> bool {
+        self.jobs.send(Box::new(f)).is_ok()
+    }
+}
+
+impl<T> fmt::Debug for DedicatedThread<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DedicatedThread").finish_non_exhaustive()
+    }
+}
+
+impl<T> Clone for DedicatedThread<T> {
+    fn clone(&self) -> Self {
+        Self {
+            jobs: self.jobs.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::RunError;
+    use crate::graph::Graph;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    /// Stands in for a native model handle: not `Send`, so it can only live on one thread.
+    struct Model {
+        calls: Rc<Cell<usize>>,
+    }
+
+    fn model() -> Model {
+        Model {
+            calls: Rc::new(Cell::new(0)),
+        }
+    }
+
+    async fn join(x: Vec<String>) -> String {
+        x.join(" ")
+    }
+
+    #[tokio::test]
+    async fn keeps_non_send_state_on_its_own_thread() {
+        let thread = DedicatedThread::spawn("model", model);
+        let mut graph = Graph::default();
+        for name in ["A", "B"] {
+            graph.stage_threaded_node(
+                name.into(),
+                vec!["entrypoint".into()],
+                thread.clone(),
+                |model, x| {
+                    model.calls.set(model.calls.get() + 1);
+                    let thread = std::thread::current();
+                    format!(
+                        "{}:{}:{}",
+                        thread.name().unwrap_or_default(),
+                        model.calls.get(),
+                        x.concat()
+                    )
+                },
+            );
+        }
+        graph.stage_node("C".into(), vec!["A".into(), "B".into()], crate::wrap!(join));
+        let output = graph.run("x".into(), "C".into()).await.unwrap();
+        assert!(
+            output == "model:1:x model:2:x" || output == "model:2:x model:1:x",
+            "{output}"
+        );
+
+        let mut graph = Graph::default();
+        graph.stage_threaded_node(
+            "D".into(),
+            vec!["entrypoint".into()],
+            DedicatedThread::spawn("broken", model),
+            |_, _| panic!("the model crashed"),
+        );
+        let error = graph.run("x".into(), "D".into()).await.unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<RunError>(),
+            Some(&RunError::ThreadStopped { node: "D".into() })
+        );
+    }
+}
@@ -0,0 +1,109 @@
+use std::error::Error;
+use std::fmt;
+use std::time::Duration;
+
+/// A `RunError` is returned (boxed) by `Graph::run` when a `Node` could not produce its value. Use
+/// `downcast_ref::<RunError>()` on the error to tell the cases apart.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RunError {
+    /// Every attempt at running `node`s `op` took longer than its timeout.
+    Timeout { node: String, attempts: u32 },
+    /// The run was turned away because its `Graph` already had as many runs in flight and queued as its
+    /// `AdmissionLimit` allows.
+    Overloaded,
+    /// `node` produced a value that would have brought the payloads held by the run to `bytes`, over the `limit` set
+    /// with `Graph::set_memory_limit`.
+    MemoryLimit {
+        node: String,
+        bytes: usize,
+        limit: usize,
+    },
+    /// The typed `op` of `node` could not decode the JSON value it got from the `Node` called `input`.
+    Decode {
+        node: String,
+        input: String,
+        message: String,
+    },
+    /// The typed `op` of `node` produced a value that could not be encoded as JSON.
+    Encode { node: String, message: String },
+    /// The `InjectionGuard` of the `Graph` blocked the value `node` got from the untrusted `Node` called `input`.
+    InjectionBlocked {
+        node: String,
+        input: String,
+        reason: String,
+    },
+    /// `node` had used up its `Quota`, which fails fast, and could have run again in `retry_after`.
+    QuotaExceeded { node: String, retry_after: Duration },
+    /// The output `Node` of the run had no value, because its `op` returned none or an input it requires had none.
+    NoValue { node: String },
+    /// Another `Node` subscribes to the output called `output` of `node`, but the value `node` produced isn't a JSON
+    /// object with that field.
+    MissingOutput { node: String, output: String },
+    /// The `RunOptions::config_overlays` entry for `node` could not be applied, because of `message`.
+    Overlay { node: String, message: String },
+    /// The value of `node` still didn't fit its `OutputSchema` after every repair it allows, for `problems`.
+    SchemaMismatch { node: String, problems: Vec<String> },
+    /// The `DedicatedThread` of `node` has stopped, because an earlier call on it panicked.
+    ThreadStopped { node: String },
+    /// The run was stopped with `RunControl::cancel`.
+    Cancelled,
+    /// The output `Node` of the run can never get all of its inputs, because it is on a cycle or depends on a `Node`
+    /// that is.
+    Unreachable { node: String },
+}
+
+impl fmt::Display for RunError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Timeout { node, attempts } => {
+                write!(f, "Node {node} timed out after {attempts} attempt(s)")
+            }
+            Self::Overloaded => write!(f, "Graph is overloaded, try again later"),
+            Self::MemoryLimit { node, bytes, limit } => write!(
+                f,
+                "Node {node} brought the run to {bytes} bytes of payloads, over the limit of {limit}"
+            ),
+            Self::Decode {
+                node,
+                input,
+                message,
+            } => write!(f, "Node {node} could not decode its input from {input}: {message}"),
+            Self::Encode { node, message } => {
+                write!(f, "Node {node} could not encode its output: {message}")
+            }
+            Self::InjectionBlocked {
+                node,
+                input,
+                reason,
+            } => write!(
+                f,
+                "Node {node} was not given its input from {input}, which looks like a prompt injection: {reason}"
+            ),
+            Self::QuotaExceeded { node, retry_after } => write!(
+                f,
+                "Node {node} has used up its quota, it can run again in {retry_after:?}"
+            ),
+            Self::NoValue { node } => write!(f, "Node {node} has no value"),
+            Self::MissingOutput { node, output } => {
+                write!(f, "Node {node} has no output `{output}`")
+            }
+            Self::Overlay { node, message } => {
+                write!(f, "Node {node} could not take its config overlay: {message}")
+            }
+            Self::SchemaMismatch { node, problems } => write!(
+                f,
+                "Node {node} produced a value that doesn't fit its output schema: {}",
+                problems.join("; ")
+            ),
+            Self::Cancelled => write!(f, "The run was cancelled"),
+            Self::ThreadStopped { node } => {
+                write!(f, "Node {node} could not run, its dedicated thread has stopped")
+            }
+            Self::Unreachable { node } => {
+                write!(f, "Node {node} can never run, it is on or after a cycle")
+            }
+        }
+    }
+}
+
+impl Error for RunError {}
@@ -0,0 +1,446 @@
+use crate::aggregate;
+use crate::graph::Graph;
+use crate::jsonl;
+use crate::metric::{ExactMatch, Metric};
+use crate::options::RunOptions;
+use crate::trace::Source;
+use futures::StreamExt;
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// A single `EvalCase` is the value sent to `entrypoint` and, optionally, the output the graph is expected to produce.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EvalCase {
+    pub input: String,
+    pub expected: Option<String>,
+}
+
+impl EvalCase {
+    pub fn new(input: String, expected: Option<String>) -> Self {
+        Self { input, expected }
+    }
+
+    /// Reads a dataset with one JSON object per line, each with an `input` string and an optional `expected` string.
+    pub fn load_jsonl(path: impl AsRef<Path>) -> io::Result<Vec<Self>> {
+        let mut cases = vec![];
+        jsonl::read(path, |value| {
+            let input = value["input"].as_str().ok_or("`input` must be a string")?;
+            let expected = match &value["expected"] {
+                serde_json::Value::Null => None,
+                serde_json::Value::String(expected) => Some(expected.clone()),
+                _ => return Err("`expected` must be a string".into()),
+            };
+            cases.push(Self::new(input.into(), expected));
+            Ok(())
+        })?;
+        Ok(cases)
+    }
+
+    /// Reads the executions of `node` from a dataset written by a `sampling::Sampler`, as cases whose `expected` value
+    /// is the output that was recorded. Sample a `Node` that takes only `entrypoint`, and its history holds the inputs
+    /// real runs were started with; evaluating them against a new version of the graph is then a regression check,
+    /// where `EvalReport::mismatches` lists every run whose output changed. Executions of other `Node`s are skipped.
+    pub fn from_samples(path: impl AsRef<Path>, node: &str) -> io::Result<Vec<Self>> {
+        let mut cases = vec![];
+        jsonl::read(path, |value| {
+            if value["node"] != node {
+                return Ok(());
+            }
+            let input = match value["inputs"].as_array().map(Vec::as_slice) {
+                Some([serde_json::Value::String(input)]) => input,
+                _ => return Err("`inputs` must be a single string".into()),
+            };
+            let output = value["output"]
+                .as_str()
+                .ok_or("`output` must be a string")?;
+            cases.push(Self::new(input.clone(), Some(output.into())));
+            Ok(())
+        })?;
+        Ok(cases)
+    }
+}
+
+/// The outcome of running one `EvalCase`. `output` holds the error message if the run failed, `scores` holds
+/// every score a `Metric` produced, keyed by `Metric::name`, and `cost` is the sum of `Evaluator::with_cost` over
+/// the `op`s that ran.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CaseResult {
+    pub case: EvalCase,
+    pub output: Result<String, String>,
+    pub scores: BTreeMap<String, f64>,
+    pub latency: Duration,
+    pub cost: f64,
+}
+
+/// `EvalReport` collects every `CaseResult` of an evaluation, in dataset order, along with summary metrics.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EvalReport {
+    pub cases: Vec<CaseResult>,
+}
+
+impl EvalReport {
+    /// The average score of the `Metric` called `metric` over the cases it scored.
+    pub fn mean_score(&self, metric: &str) -> Option<f64> {
+        mean(
+            self.cases
+                .iter()
+                .filter_map(|c| c.scores.get(metric).copied()),
+        )
+    }
+
+    /// The fraction of cases with an `expected` value whose output matched it exactly. Cases whose run failed count
+    /// as mismatches.
+    pub fn exact_match_rate(&self) -> Option<f64> {
+        self.mean_score(ExactMatch.name())
+    }
+
+    /// The cases with an `expected` value whose output, or error, differs from it.
+    pub fn mismatches(&self) -> Vec<&CaseResult> {
+        self.cases
+            .iter()
+            .filter(|c| {
+                c.case
+                    .expected
+                    .as_ref()
+                    .is_some_and(|expected| c.output.as_ref() != Ok(expected))
+            })
+            .collect()
+    }
+
+    /// The fraction of cases whose run returned an error.
+    pub fn error_rate(&self) -> Option<f64> {
+        mean(
+            self.cases
+                .iter()
+                .map(|c| f64::from(c.output.is_err() as u8)),
+        )
+    }
+
+    /// The average latency of a case.
+    pub fn mean_latency(&self) -> Option<Duration> {
+        let count = u32::try_from(self.cases.len()).ok().filter(|&n| n > 0)?;
+        Some(self.cases.iter().map(|c| c.latency).sum::<Duration>() / count)
+    }
+
+    /// The latency below which `percentile` (between `0.0` and `1.0`) of the cases finished.
+    pub fn latency_percentile(&self, percentile: f64) -> Option<Duration> {
+        let mut latencies: Vec<Duration> = self.cases.iter().map(|c| c.latency).collect();
+        latencies.sort();
+        aggregate::percentile(&latencies, percentile)
+    }
+
+    /// What every case cost together.
+    pub fn total_cost(&self) -> f64 {
+        self.cases.iter().map(|c| c.cost).sum()
+    }
+
+    /// The average cost of a case.
+    pub fn mean_cost(&self) -> Option<f64> {
+        mean(self.cases.iter().map(|c| c.cost))
+    }
+}
+
+/// How often the candidate scored higher, lower, or the same as the baseline on one `Metric`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HeadToHead {
+    pub wins: usize,
+    pub losses: usize,
+    pub ties: usize,
+}
+
+impl HeadToHead {
+    /// The candidate's win rate, counting a tie as half a win.
+    pub fn win_rate(&self) -> Option<f64> {
+        let total = self.wins + self.losses + self.ties;
+        (total > 0).then(|| (self.wins as f64 + self.ties as f64 / 2.0) / total as f64)
+    }
+}
+
+/// `ComparisonReport` holds the `EvalReport`s of a baseline and a candidate graph that ran over the same dataset,
+/// so `baseline.cases[i]` and `candidate.cases[i]` are always the same `EvalCase`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ComparisonReport {
+    pub baseline: EvalReport,
+    pub candidate: EvalReport,
+}
+
+impl ComparisonReport {
+    /// Compares the two graphs case by case on `metric`, skipping cases where either side has no score.
+    pub fn head_to_head(&self, metric: &str) -> HeadToHead {
+        let mut result = HeadToHead::default();
+        for (baseline, candidate) in self.baseline.cases.iter().zip(&self.candidate.cases) {
+            let (Some(b), Some(c)) = (baseline.scores.get(metric), candidate.scores.get(metric))
+            else {
+                continue;
+            };
+            match c.partial_cmp(b) {
+                Some(std::cmp::Ordering::Greater) => result.wins += 1,
+                Some(std::cmp::Ordering::Less) => result.losses += 1,
+                _ => result.ties += 1,
+            }
+        }
+        result
+    }
+
+    /// How many seconds slower (positive) or faster (negative) the candidate is on average.
+    pub fn mean_latency_delta(&self) -> Option<f64> {
+        let baseline = self.baseline.mean_latency()?;
+        let candidate = self.candidate.mean_latency()?;
+        Some(candidate.as_secs_f64() - baseline.as_secs_f64())
+    }
+
+    /// How much more (positive) or less (negative) a case of the candidate costs on average, see
+    /// `Evaluator::with_cost`.
+    pub fn mean_cost_delta(&self) -> Option<f64> {
+        Some(self.candidate.mean_cost()? - self.baseline.mean_cost()?)
+    }
+
+    /// How much the candidate's mean score on `metric` differs from the baseline's.
+    pub fn mean_score_delta(&self, metric: &str) -> Option<f64> {
+        Some(self.candidate.mean_score(metric)? - self.baseline.mean_score(metric)?)
+    }
+}
+
+fn mean(values: impl Iterator<Item = f64>) -> Option<f64> {
+    let (sum, count) = values.fold((0.0, 0usize), |(sum, count), v| (sum + v, count + 1));
+    (count > 0).then(|| sum / count as f64)
+}
+
+/// An `Evaluator` runs a `Graph` over a dataset of `EvalCase`s and scores each output with its `Metric`s.
+/// `ExactMatch` is always included; more, such as an `LlmJudge`, can be added with `with_metric`.
+pub struct Evaluator {
+    output_name: String,
+    concurrency: usize,
+    metrics: Vec<Box<dyn Metric>>,
+    costs: BTreeMap<String, f64>,
+}
+
+impl Evaluator {
+    pub fn new(output_name: String) -> Self {
+        Self {
+            output_name,
+            concurrency: 1,
+            metrics: vec![Box::new(ExactMatch)],
+            costs: BTreeMap::new(),
+        }
+    }
+
+    /// Sets how many cases may run at once.
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    pub fn with_metric(mut self, metric: impl Metric + 'static) -> Self {
+        self.metrics.push(Box::new(metric));
+        self
+    }
+
+    /// Sets what one call of the `op` of `node` costs, e.g. in dollars of API usage. Values taken from a cache, or
+    /// passed on by a disabled `Node`, cost nothing.
+    pub fn with_cost(mut self, node: &str, cost: f64) -> Self {
+        self.costs.insert(node.to_string(), cost);
+        self
+    }
+
+    pub async fn evaluate(&self, graph: &Graph, cases: &[EvalCase]) -> EvalReport {
+        let cases = futures::stream::iter(cases)
+            .map(|case| self.evaluate_case(graph, case))
+            .buffered(self.concurrency)
+            .collect()
+            .await;
+        EvalReport { cases }
+    }
+
+    /// `compare` evaluates `baseline` and then `candidate` over the same `cases`. The graphs run one after the other
+    /// rather than side by side, so neither one's latency is skewed by the other competing for the same resources.
+    pub async fn compare(
+        &self,
+        baseline: &Graph,
+        candidate: &Graph,
+        cases: &[EvalCase],
+    ) -> ComparisonReport {
+        ComparisonReport {
+            baseline: self.evaluate(baseline, cases).await,
+            candidate: self.evaluate(candidate, cases).await,
+        }
+    }
+
+    async fn evaluate_case(&self, graph: &Graph, case: &EvalCase) -> CaseResult {
+        let started = Instant::now();
+        let (outcome, trace) = graph
+            .run_traced(
+                case.input.clone(),
+                self.output_name.clone(),
+                &RunOptions::default(),
+            )
+            .await;
+        let latency = started.elapsed();
+        let output = outcome.into_result().map_err(|e| e.to_string());
+        let cost = trace
+            .nodes
+            .iter()
+            .filter(|n| matches!(n.source, Source::Op | Source::Canary))
+            .filter_map(|n| self.costs.get(&n.node))
+            .sum();
+
+        let mut scores = BTreeMap::new();
+        match &output {
+            Ok(output) => {
+                for metric in &self.metrics {
+                    let score = metric
+                        .score(&case.input, output, case.expected.as_deref())
+                        .await;
+                    if let Some(score) = score {
+                        scores.insert(metric.name().to_string(), score);
+                    }
+                }
+            }
+            Err(_) => {
+                for metric in &self.metrics {
+                    if let Some(score) = metric.failure_score(&case.input, case.expected.as_deref())
+                    {
+                        scores.insert(metric.name().to_string(), score);
+                    }
+                }
+            }
+        }
+        CaseResult {
+            case: case.clone(),
+            output,
+            scores,
+            latency,
+            cost,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metric::LlmJudge;
+    use crate::wrap;
+    use std::fs;
+
+    async fn shout(x: Vec<String>) -> String {
+        x.concat().to_uppercase()
+    }
+
+    async fn judge_nonempty(x: Vec<String>) -> String {
+        if x[0].ends_with(": ") { "0" } else { "1" }.to_string()
+    }
+
+    #[tokio::test]
+    async fn reports_exact_match_and_judge_scores() {
+        let mut graph = Graph::default();
+        graph.stage_node("A".into(), vec!["entrypoint".into()], wrap!(shout));
+        let cases = vec![
+            EvalCase::new("hi".into(), Some("HI".into())),
+            EvalCase::new("yo".into(), Some("yo".into())),
+            EvalCase::new("".into(), None),
+        ];
+
+        let report = Evaluator::new("A".into())
+            .with_concurrency(2)
+            .with_cost("A", 0.25)
+            .with_metric(LlmJudge::new(
+                wrap!(judge_nonempty),
+                "Output: {output}".into(),
+            ))
+            .evaluate(&graph, &cases)
+            .await;
+
+        assert_eq!(report.cases.len(), 3);
+        assert_eq!(report.cases[1].output, Ok("YO".to_string()));
+        assert_eq!(report.exact_match_rate(), Some(0.5));
+        assert_eq!(report.mean_score("judge"), Some(2.0 / 3.0));
+        assert_eq!(report.error_rate(), Some(0.0));
+        assert!(report.latency_percentile(0.95).is_some());
+        assert_eq!(report.total_cost(), 0.75);
+        assert_eq!(report.mean_cost(), Some(0.25));
+
+        let mut failing = Graph::default();
+        failing.stage_optional_node(
+            "A".into(),
+            vec!["entrypoint".into()],
+            |_: Vec<String>| async { None },
+        );
+        let report = Evaluator::new("A".into())
+            .with_metric(LlmJudge::new(wrap!(judge_nonempty), "{output}".into()))
+            .evaluate(&failing, &cases)
+            .await;
+        assert_eq!(report.error_rate(), Some(1.0));
+        assert_eq!(report.exact_match_rate(), Some(0.0));
+        assert_eq!(report.mean_score("judge"), Some(0.0));
+        assert_eq!(report.cases[2].scores.len(), 1);
+    }
+
+    async fn identity(x: Vec<String>) -> String {
+        x.concat()
+    }
+
+    #[tokio::test]
+    async fn compares_two_graphs() {
+        let mut baseline = Graph::default();
+        baseline.stage_node("A".into(), vec!["entrypoint".into()], wrap!(identity));
+        let mut candidate = Graph::default();
+        candidate.stage_node("B".into(), vec!["entrypoint".into()], wrap!(identity));
+        candidate.stage_node("A".into(), vec!["B".into()], wrap!(shout));
+        let cases = vec![
+            EvalCase::new("HI".into(), Some("HI".into())),
+            EvalCase::new("yo".into(), Some("YO".into())),
+            EvalCase::new("ok".into(), Some("ok".into())),
+        ];
+
+        let report = Evaluator::new("A".into())
+            .with_cost("A", 0.5)
+            .with_cost("B", 0.25)
+            .compare(&baseline, &candidate, &cases)
+            .await;
+
+        let h2h = report.head_to_head("exact_match");
+        assert_eq!(
+            h2h,
+            HeadToHead {
+                wins: 1,
+                losses: 1,
+                ties: 1
+            }
+        );
+        assert_eq!(h2h.win_rate(), Some(0.5));
+        assert_eq!(report.mean_score_delta("exact_match"), Some(0.0));
+        assert!(report.mean_latency_delta().is_some());
+        assert_eq!(report.mean_cost_delta(), Some(0.25));
+    }
+
+    #[tokio::test]
+    async fn replays_sampled_runs_against_new_version() {
+        let path = std::env::temp_dir().join(format!("replay-{}.jsonl", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let mut old = Graph::default();
+        old.stage_node("A".into(), vec!["entrypoint".into()], wrap!(identity));
+        old.set_sampler(crate::sampling::Sampler::to_file(1.0, &path).unwrap());
+        for input in ["HI", "yo"] {
+            old.run(input.into(), "A".into()).await.unwrap();
+        }
+        let flushed = old.sampler().unwrap().flush();
+        flushed.await.unwrap();
+        drop(old);
+
+        let cases = EvalCase::from_samples(&path, "A").unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(cases[1], EvalCase::new("yo".into(), Some("yo".into())));
+
+        let mut new = Graph::default();
+        new.stage_node("A".into(), vec!["entrypoint".into()], wrap!(shout));
+        let report = Evaluator::new("A".into()).evaluate(&new, &cases).await;
+        let changed: Vec<&str> = report
+            .mismatches()
+            .iter()
+            .map(|c| c.case.input.as_str())
+            .collect();
+        assert_eq!(changed, vec!["yo"]);
+    }
+}
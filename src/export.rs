@@ -0,0 +1,190 @@
+use crate::trace::{RunTrace, Source};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::time::Duration;
+
+/// Fill colours for the fastest, middle and slowest third of annotated `Node`s.
+const HEAT: [&str; 3] = ["#b7e4c7", "#ffe08a", "#f4978e"];
+
+/// `Annotations` add recorded performance to the diagrams of `Graph::to_dot` and `Graph::to_mermaid`: each
+/// annotated `Node` is labelled with its mean latency and cost, and coloured from green to red by how slow it is
+/// compared to the slowest one, turning the topology into a heat map.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Annotations {
+    latencies: BTreeMap<String, (Duration, u32)>,
+    costs: BTreeMap<String, f64>,
+}
+
+impl Annotations {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Annotations with the mean latency of every `Node` whose `op` ran in `traces`. Values that came from a cache
+    /// or an override don't count.
+    pub fn from_traces<'a>(traces: impl IntoIterator<Item = &'a RunTrace>) -> Self {
+        let mut annotations = Self::new();
+        for trace in traces {
+            for node in &trace.nodes {
+                if matches!(node.source, Source::Op | Source::Canary) {
+                    annotations.record_latency(&node.node, node.finished - node.started);
+                }
+            }
+        }
+        annotations
+    }
+
+    pub fn record_latency(&mut self, node: &str, latency: Duration) {
+        let (total, count) = self.latencies.entry(node.to_string()).or_default();
+        *total += latency;
+        *count += 1;
+    }
+
+    /// Sets what one execution of `node` costs, e.g. in dollars of API usage.
+    pub fn with_cost(mut self, node: &str, cost: f64) -> Self {
+        self.costs.insert(node.to_string(), cost);
+        self
+    }
+
+    pub fn latency(&self, node: &str) -> Option<Duration> {
+        let (total, count) = self.latencies.get(node)?;
+        Some(*total / *count)
+    }
+
+    /// The lines to put under the name of `node`.
+    fn label(&self, node: &str) -> Vec<String> {
+        let mut lines = vec![];
+        if let Some(latency) = self.latency(node) {
+            lines.push(format!("{:.1} ms", latency.as_secs_f64() * 1000.0));
+        }
+        if let Some(cost) = self.costs.get(node) {
+            lines.push(format!("${cost:.4}"));
+        }
+        lines
+    }
+
+    fn colour(&self, node: &str) -> Option<&'static str> {
+        let latency = self.latency(node)?;
+        let slowest = self
+            .latencies
+            .keys()
+            .filter_map(|name| self.latency(name))
+            .max()?;
+        if slowest.is_zero() {
+            return Some(HEAT[0]);
+        }
+        let share = latency.as_secs_f64() / slowest.as_secs_f64();
+        Some(HEAT[((share * 3.0) as usize).min(2)])
+    }
+}
+
+/// The topology `to_dot` and `to_mermaid` draw: every `Node` with its inputs, in staging order.
+pub(crate) type Topology<'a> = [(&'a str, &'a [String])];
+
+pub(crate) fn to_dot(nodes: &Topology, annotations: Option<&Annotations>) -> String {
+    let quote = |s: &str| format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""));
+    let mut dot = String::from("digraph {\n");
+    for (name, _) in nodes {
+        let mut label = vec![name.to_string()];
+        let mut style = String::new();
+        if let Some(annotations) = annotations {
+            label.extend(annotations.label(name));
+            if let Some(colour) = annotations.colour(name) {
+                style = format!(", style=filled, fillcolor=\"{colour}\"");
+            }
+        }
+        let label = quote(&label.join("\n")).replace('\n', "\\n");
+        let _ = writeln!(dot, "    {} [label={label}{style}];", quote(name));
+    }
+    for (name, inputs) in nodes {
+        for input in inputs.iter() {
+            let _ = writeln!(dot, "    {} -> {};", quote(input), quote(name));
+        }
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+pub(crate) fn to_mermaid(nodes: &Topology, annotations: Option<&Annotations>) -> String {
+    // Mermaid ids must be plain words, so `Node`s get numbered ids and keep their names as labels.
+    let ids: BTreeMap<&str, String> = nodes
+        .iter()
+        .enumerate()
+        .map(|(i, (name, _))| (*name, format!("n{i}")))
+        .collect();
+    let id = |name: &str| ids.get(name).cloned().unwrap_or_else(|| name.to_string());
+    let escape = |s: &str| s.replace('"', "#quot;");
+    let mut mermaid = String::from("flowchart TD\n");
+    let uses_entrypoint = nodes
+        .iter()
+        .any(|(_, inputs)| inputs.iter().any(|input| input == "entrypoint"));
+    if uses_entrypoint && !ids.contains_key("entrypoint") {
+        mermaid.push_str("    entrypoint([\"entrypoint\"])\n");
+    }
+    let mut styles = String::new();
+    for (name, _) in nodes {
+        let mut label = vec![escape(name)];
+        if let Some(annotations) = annotations {
+            label.extend(annotations.label(name));
+            if let Some(colour) = annotations.colour(name) {
+                let _ = writeln!(styles, "    style {} fill:{colour}", id(name));
+            }
+        }
+        let _ = writeln!(mermaid, "    {}[\"{}\"]", id(name), label.join("<br/>"));
+    }
+    for (name, inputs) in nodes {
+        for input in inputs.iter() {
+            let _ = writeln!(mermaid, "    {} --> {}", id(input), id(name));
+        }
+    }
+    mermaid.push_str(&styles);
+    mermaid
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::Graph;
+    use crate::wrap;
+
+    async fn concat(x: Vec<String>) -> String {
+        x.concat()
+    }
+
+    #[test]
+    fn draws_heat_map_of_recorded_latency() {
+        let mut graph = Graph::default();
+        graph.stage_node("A".into(), vec!["entrypoint".into()], wrap!(concat));
+        graph.stage_node("B \"slow\"".into(), vec!["A".into()], wrap!(concat));
+        let mut annotations = Annotations::new().with_cost("B \"slow\"", 0.002);
+        annotations.record_latency("A", Duration::from_millis(1));
+        annotations.record_latency("B \"slow\"", Duration::from_millis(30));
+        annotations.record_latency("B \"slow\"", Duration::from_millis(10));
+
+        assert_eq!(
+            graph.to_dot(Some(&annotations)),
+            concat!(
+                "digraph {\n",
+                "    \"A\" [label=\"A\\n1.0 ms\", style=filled, fillcolor=\"#b7e4c7\"];\n",
+                "    \"B \\\"slow\\\"\" [label=\"B \\\"slow\\\"\\n20.0 ms\\n$0.0020\", style=filled, fillcolor=\"#f4978e\"];\n",
+                "    \"entrypoint\" -> \"A\";\n",
+                "    \"A\" -> \"B \\\"slow\\\"\";\n",
+                "}\n",
+            )
+        );
+        assert_eq!(
+            graph.to_mermaid(Some(&annotations)),
+            concat!(
+                "flowchart TD\n",
+                "    entrypoint([\"entrypoint\"])\n",
+                "    n0[\"A<br/>1.0 ms\"]\n",
+                "    n1[\"B #quot;slow#quot;<br/>20.0 ms<br/>$0.0020\"]\n",
+                "    entrypoint --> n0\n",
+                "    n0 --> n1\n",
+                "    style n0 fill:#b7e4c7\n",
+                "    style n1 fill:#f4978e\n",
+            )
+        );
+        assert!(!graph.to_dot(None).contains("fillcolor"));
+    }
+}
@@ -1,16 +1,87 @@
+use crate::cache::{CachePolicy, Lookup, NodeCache, PrimeReport};
+use crate::concurrency::{AdmissionLimit, ConcurrencyLimit, Limiter};
+use crate::control::NodeState;
+use crate::dedicated::DedicatedThread;
+use crate::error::RunError;
+use crate::export::{self, Annotations};
+use crate::guard::InjectionGuard;
+use crate::id::RunId;
+use crate::lint::{Diagnostic, LintNode, Linter};
+use crate::memory::RunMemory;
+use crate::metric::Metric;
+use crate::migrate::Version;
+use crate::options::{Priority, RunOptions};
+use crate::outcome::RunOutcome;
+use crate::pii::Redactor;
+use crate::policy::{NodeDefaults, NodeSettings};
+use crate::pool::{Pool, Pooled};
+use crate::profile::LatencyProfile;
+use crate::registry::OpFactory;
+use crate::sampling::Sampler;
+use crate::schedule::{self, Frontier, Wiring};
+use crate::schema::{self, OutputSchema};
+use crate::spec::{GraphSpec, MiddlewareSpec, NodeSpec, SpecError};
+use crate::trace::{NodeTrace, RunTrace, Source};
+use crate::validate::{self, NodeInfo, OpSignature, ResidencyPolicy, ValidationError};
+use futures::future::{self, Either};
 use futures::stream::FuturesUnordered;
 use futures::{Future, StreamExt};
-use std::error::Error;
+use indexmap::IndexMap;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::cell::{Cell, Ref, RefCell};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::Write;
 use std::pin::Pin;
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
-use tokio::sync::broadcast::{channel, Receiver, Sender};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
 
-type BoxedFuture<T = String> = Pin<Box<dyn Future<Output = T>>>;
+/// How many `Node`s finish between yields to the runtime without `Graph::set_yield_budget`.
+const DEFAULT_YIELD_BUDGET: usize = 64;
+
+/// Each `Node`s expected critical path, by name.
+type Urgency = HashMap<String, Duration>;
+
+/// How long a ranking of `Node`s by their critical path is used before it is worked out again. The expected
+/// latencies it is based on are moving averages, so they hardly move from one run to the next.
+const URGENCY_REFRESH: Duration = Duration::from_secs(1);
+
+pub type BoxedFuture<T = String> = Pin<Box<dyn Future<Output = T>>>;
 
 /// An `OpFn` is a regular function that returns a `Pin<Box<dyn Future<Output = String>>>`. This
 /// is because Rust gets upset if I try to create a type alias of an `async fn`. A macro `wrap!` is provided
 /// that will turn an `async fn(Vec<String>) -> String` into the `OpFn` type for you.
-type OpFn = fn(Vec<String>) -> BoxedFuture;
+pub type OpFn = fn(Vec<String>) -> BoxedFuture;
+
+/// How a `Node` holds on to its `op`. Besides plain `OpFn`s this lets the `Graph` stage ops that carry state, like
+/// a `Metric`, ops that can fail, like the typed ops of `stage_json_node`, and ops that may have no value at all,
+/// like those of `stage_optional_node`.
+pub(crate) type Op = OpOn<Vec<String>>;
+
+/// An `op` taking `I`. The ops of `stage_node_accepting_none` take inputs that may be missing.
+pub(crate) type OpOn<I> = Rc<dyn Fn(I) -> BoxedFuture<Result<Option<String>, RunError>>>;
+
+/// Turns a plain `OpFn`, which never fails, into an `Op`.
+pub(crate) fn op_from_fn(op: OpFn) -> Op {
+    Rc::new(move |x: Vec<String>| {
+        let value = op(x);
+        Box::pin(async move { Ok(Some(value.await)) })
+    })
+}
+
+/// Splits an input into the `Node` it comes from and, for an input written `node.key`, the key of the output it
+/// subscribes to. `is_node` tells which names are `Node`s. An input that names a `Node` (or `entrypoint`) outright
+/// always means its whole value, so `Node` names may contain dots too.
+pub(crate) fn split_input(input: &str, is_node: impl Fn(&str) -> bool) -> (&str, Option<&str>) {
+    let is_node = |name: &str| name == "entrypoint" || is_node(name);
+    if is_node(input) {
+        return (input, None);
+    }
+    match input.rsplit_once('.') {
+        Some((node, key)) if is_node(node) => (node, Some(key)),
+        _ => (input, None),
+    }
+}
 
 /// A `Node` contains a `name` that other nodes use to refer to it, `inputs` to list the other `Node`s that it will require input from, and an operation `op`
 /// that will run when all inputs are ready. The `Node` lists the `name`s of other `Node`s and the order they should be in. The `op` must be a function
@@ -19,33 +90,430 @@ type OpFn = fn(Vec<String>) -> BoxedFuture;
 pub struct Node {
     name: String,
     inputs: Vec<String>,
-    op: OpFn,
-    sender: Sender<String>,
+    op: Op,
+    cache: Option<NodeCache>,
+    tags: BTreeSet<String>,
+    settings: NodeSettings,
+    signature: Option<OpSignature>,
+    classifications: BTreeSet<String>,
+    destination: Option<String>,
+    canary: Option<Canary>,
+    /// What the `op` is registered as in an `OpRegistry`, if known.
+    op_name: Option<String>,
+    /// The `config` the `op` was built from, if it is a `StructOp`.
+    op_config: Option<serde_json::Value>,
+    /// What builds the `op` from another `config`, if it is a `StructOp`, see `RunOptions::overlay_config`.
+    op_factory: Option<OpFactory>,
+    /// What middleware wrapped the `op` in, in order, so an `op` built from another `config` is wrapped the same way.
+    wrappers: Vec<Rc<dyn Fn(Op) -> Op>>,
+    version: Option<Version>,
+    description: Option<String>,
+    /// The middleware a `GraphSpec` applied to this `Node` alone.
+    middleware: Vec<MiddlewareSpec>,
+    /// Whether the `op` may have no value.
+    optional: bool,
+    /// Whether the `Node` declared it needs a value on every input, see `Graph::require_some`.
+    requires_some: bool,
+    /// The `op` of a `Node` staged with `stage_node_accepting_none`, which is called in place of `op`.
+    lenient: Option<OpOn<Vec<Option<String>>>>,
+    output_schema: Option<Rc<OutputSchema>>,
+}
+
+/// An alternative `op` that gets `percent` of a `Node`s executions, spread evenly.
+struct Canary {
+    op: Op,
+    percent: u8,
+    executions: Cell<u64>,
+}
+
+impl Canary {
+    fn pick(&self) -> bool {
+        let n = self.executions.get();
+        self.executions.set(n.wrapping_add(1));
+        (n * u64::from(self.percent)) % 100 < u64::from(self.percent)
+    }
 }
 
 impl Node {
-    pub fn new(name: String, inputs: Vec<String>, op: OpFn, sender: Sender<String>) -> Self {
+    pub fn new(name: String, inputs: Vec<String>, op: OpFn) -> Self {
+        Self::with_op(name, inputs, op_from_fn(op))
+    }
+
+    fn with_op(name: String, inputs: Vec<String>, op: Op) -> Self {
         Self {
             name,
             inputs,
             op,
-            sender,
+            cache: None,
+            tags: BTreeSet::new(),
+            settings: NodeSettings::default(),
+            signature: None,
+            classifications: BTreeSet::new(),
+            destination: None,
+            canary: None,
+            op_name: None,
+            op_config: None,
+            op_factory: None,
+            wrappers: vec![],
+            version: None,
+            description: None,
+            middleware: vec![],
+            optional: false,
+            requires_some: false,
+            lenient: None,
+            output_schema: None,
         }
     }
 }
 
-async fn run_node(node: &Rc<RefCell<Node>>, receivers: Vec<Receiver<String>>) {
-    let mut inputs: Vec<String> = vec![];
-    for mut r in receivers {
-        if let Ok(i) = r.recv().await {
-            inputs.push(i);
+/// How an `op` gets its turn to run: the run it is part of, the `Graph`s `Limiter`, how urgent the `Node` is, and the
+/// `LatencyProfile` to record how long it took in.
+#[derive(Clone, Default)]
+struct Dispatch {
+    run_id: RunId,
+    limiter: Option<Rc<Limiter>>,
+    urgency: Duration,
+    profile: Option<Rc<RefCell<LatencyProfile>>>,
+}
+
+/// Calls a `Node`s `op` as its `NodeSettings` say, retrying attempts that time out. The execution counts against the
+/// `Node`s `Quota` once, and each attempt first waits for its `RateLimit` and for a slot if the `Graph` has a
+/// `ConcurrencyLimit`.
+async fn execute<I: Clone + Default>(
+    node: &Rc<RefCell<Node>>,
+    op: OpOn<I>,
+    mut inputs: I,
+    dispatch: Dispatch,
+    options: &RunOptions,
+) -> Result<Option<String>, RunError> {
+    let settings = node.borrow().settings.clone();
+    let attempts = settings.retries + 1;
+    if let Some(quota) = &settings.quota {
+        if let Err(retry_after) = quota.acquire().await {
+            return Err(RunError::QuotaExceeded {
+                node: node.borrow().name.clone(),
+                retry_after,
+            });
+        }
+    }
+    for attempt in 1..=attempts {
+        if let Some(rate_limit) = &settings.rate_limit {
+            rate_limit.acquire().await;
+        }
+        let mut permit = match &dispatch.limiter {
+            Some(limiter) => {
+                let tenant = options.tenant.as_deref().unwrap_or_default();
+                Some(
+                    limiter
+                        .acquire(options.priority, tenant, dispatch.urgency)
+                        .await,
+                )
+            }
+            None => None,
+        };
+        let args = if attempt == attempts {
+            std::mem::take(&mut inputs)
         } else {
-            unreachable!();
+            inputs.clone()
+        };
+        let started = Instant::now();
+        let call = dispatch.run_id.scope(async { op(args).await });
+        let result = match settings.timeout {
+            None => Some(call.await),
+            Some(timeout) => tokio::time::timeout(timeout, call).await.ok(),
+        };
+        if let (None | Some(Err(_)), Some(permit)) = (&result, &mut permit) {
+            permit.fail();
+        }
+        if let Some(result) = result {
+            if let (Ok(_), Some(profile)) = (&result, &dispatch.profile) {
+                profile
+                    .borrow_mut()
+                    .record(&node.borrow().name, started.elapsed());
+            }
+            return result;
+        }
+    }
+    Err(RunError::Timeout {
+        node: node.borrow().name.clone(),
+        attempts,
+    })
+}
+
+/// What every `Node` of a single run shares.
+struct RunState<'a> {
+    options: &'a RunOptions,
+    run_id: RunId,
+    memory: Option<RunMemory>,
+    started: Instant,
+    trace: Option<&'a RefCell<Vec<NodeTrace>>>,
+    /// Each `Node`s expected critical path, when the `Graph` has a `LatencyProfile`.
+    urgency: Rc<Urgency>,
+    /// The `op`s built for this run from `RunOptions::config_overlays`, which replace those of their `Node`s.
+    overlaid: HashMap<String, Op>,
+}
+
+impl RunState<'_> {
+    fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    fn produced(&self, node: &str, value: Option<&str>) -> Result<(), RunError> {
+        match (&self.memory, value) {
+            (Some(memory), Some(value)) => memory.produced(node, value.len()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Works out the value of one `Node` from its `inputs` and records how that went. A `Node` started without
+/// `inputs` has been overridden before all of them arrived, and only waits for its override.
+async fn run_node(
+    graph: &Graph,
+    node: &Rc<RefCell<Node>>,
+    producers: &[Option<usize>],
+    inputs: Option<Vec<Option<String>>>,
+    run: &RunState<'_>,
+) -> Result<Option<String>, RunError> {
+    let name = node.borrow().name.clone();
+    let producers: Vec<&str> = producers.iter().map(|p| graph.name_at(*p)).collect();
+    let work = async {
+        let Some(inputs) = inputs else {
+            return future::pending().await;
+        };
+        let traced = run.trace.map(|_| {
+            let present: Vec<String> = inputs.iter().flatten().cloned().collect();
+            (present, run.elapsed())
+        });
+        let (source, result) = produce(graph, node, &name, &producers, inputs, run).await;
+        (traced, source, result)
+    };
+    let overridden = async {
+        match &run.options.control {
+            Some(control) => control.overridden(&name).await,
+            None => future::pending().await,
+        }
+    };
+    futures::pin_mut!(work, overridden);
+    let (traced, source, result) = match future::select(work, overridden).await {
+        Either::Left((done, _)) => done,
+        Either::Right((value, _)) => {
+            let traced = run.trace.map(|_| (vec![], run.elapsed()));
+            (traced, Source::Override, Ok(Some(value)))
+        }
+    };
+    if let Some(control) = &run.options.control {
+        let state = match &result {
+            Ok(_) => NodeState::Done,
+            Err(e) => NodeState::Failed {
+                error: e.to_string(),
+            },
+        };
+        control.set_state(&name, state);
+    }
+    if let (Some(trace), Some((inputs, started))) = (run.trace, traced) {
+        trace.borrow_mut().push(NodeTrace {
+            node: name.clone(),
+            inputs,
+            output: result.as_ref().map_err(ToString::to_string).cloned(),
+            source,
+            started,
+            finished: run.elapsed(),
+        });
+    }
+    let result = result?;
+    run.produced(&name, result.as_deref())?;
+    Ok(result)
+}
+
+/// `execute`s `op` on `inputs`, and then, while the value doesn't fit the `Node`s `OutputSchema`, again with a repair
+/// prompt appended to them and set as the `current_repair`, as many times as the schema allows.
+async fn conform(
+    node: &Rc<RefCell<Node>>,
+    op: Op,
+    inputs: Vec<String>,
+    dispatch: Dispatch,
+    options: &RunOptions,
+) -> Result<Option<String>, RunError> {
+    let Some(schema) = node.borrow().output_schema.clone() else {
+        return execute(node, op, inputs, dispatch, options).await;
+    };
+    let mut result = execute(node, op.clone(), inputs.clone(), dispatch.clone(), options).await;
+    for repair in 0..=schema.repairs() {
+        let Ok(Some(value)) = &result else {
+            break;
+        };
+        let problems = schema.check(value);
+        if problems.is_empty() {
+            break;
+        }
+        if repair == schema.repairs() {
+            return Err(RunError::SchemaMismatch {
+                node: node.borrow().name.clone(),
+                problems,
+            });
+        }
+        let prompt = schema.repair_prompt(value, &problems);
+        let mut inputs = inputs.clone();
+        inputs.push(prompt.clone());
+        let attempt = execute(node, op.clone(), inputs, dispatch.clone(), options);
+        result = schema::repairing(prompt, attempt).await;
+    }
+    result
+}
+
+/// Works out the value of a `Node` from its `inputs`, and where it came from.
+async fn produce(
+    graph: &Graph,
+    node: &Rc<RefCell<Node>>,
+    name: &str,
+    producers: &[&str],
+    mut inputs: Vec<Option<String>>,
+    run: &RunState<'_>,
+) -> (Source, Result<Option<String>, RunError>) {
+    let options = run.options;
+    let disabled = {
+        let node = node.borrow();
+        let disabled_by_run = node
+            .tags
+            .iter()
+            .any(|tag| options.disabled_tags.contains(tag));
+        (!node.settings.enabled || disabled_by_run).then(|| node.settings.disabled_output.clone())
+    };
+    if let Some(output) = disabled {
+        return (Source::Disabled, Ok(Some(output)));
+    }
+    let lenient = node.borrow().lenient.clone();
+    if lenient.is_none() && inputs.iter().any(Option::is_none) {
+        return (Source::Skipped, Ok(None));
+    }
+    if let Some(guard) = &graph.guard {
+        if node.borrow().tags.contains(guard.tool_tag()) {
+            for (input, producer) in inputs.iter_mut().zip(producers) {
+                let Some(input) = input else {
+                    continue;
+                };
+                let untrusted = graph
+                    .graph
+                    .get(*producer)
+                    .is_some_and(|p| p.borrow().tags.contains(guard.untrusted_tag()));
+                if untrusted {
+                    if let Err(e) = guard.screen(name, producer, input) {
+                        return (Source::Op, Err(e));
+                    }
+                }
+            }
+        }
+    }
+    if let Some(redactor) = &graph.redactor {
+        if node.borrow().tags.contains(redactor.external_tag()) {
+            for input in inputs.iter_mut().flatten() {
+                *input = redactor.redact(input);
+            }
+        }
+    }
+    let dispatch = Dispatch {
+        run_id: run.run_id,
+        limiter: graph.limiter.clone(),
+        urgency: run.urgency.get(name).copied().unwrap_or_default(),
+        profile: graph.profile.clone(),
+    };
+    // `Node`s that take missing inputs are called as they are, without a cache, canary or sampling.
+    if let Some(lenient) = lenient {
+        return (
+            Source::Op,
+            execute(node, lenient, inputs, dispatch, options).await,
+        );
+    }
+    let inputs: Vec<String> = inputs.into_iter().flatten().collect();
+    // Overlaid `op`s are called as they are, since the cache and canary belong to the `Node`s own `config`. They
+    // aren't sampled either, so a dataset only holds what the `Node`s own `config` produced.
+    if let Some(op) = run.overlaid.get(name) {
+        return (
+            Source::Op,
+            conform(node, op.clone(), inputs, dispatch, options).await,
+        );
+    }
+    let sampled_inputs = graph
+        .sampler
+        .as_ref()
+        .filter(|sampler| sampler.borrow_mut().should_sample())
+        .map(|_| inputs.clone());
+    let canary = {
+        let node = node.borrow();
+        node.canary
+            .as_ref()
+            .filter(|canary| canary.pick())
+            .map(|canary| canary.op.clone())
+    };
+    // Canary executions skip the cache, so they neither hide behind nor overwrite the primary `op`s values.
+    let lookup = match canary {
+        Some(_) => None,
+        None => node
+            .borrow_mut()
+            .cache
+            .as_mut()
+            .map(|cache| cache.lookup(&inputs)),
+    };
+    let op = node.borrow().op.clone();
+    let (source, result) = match (canary, lookup) {
+        (Some(canary), _) => (
+            Source::Canary,
+            conform(node, canary, inputs, dispatch, options).await,
+        ),
+        (None, None) => (
+            Source::Op,
+            conform(node, op, inputs, dispatch, options).await,
+        ),
+        (None, Some(Lookup::Hit(value))) => (Source::Cache, Ok(Some(value))),
+        (None, Some(Lookup::Stale(value))) => {
+            graph.revalidations.borrow_mut().push(Box::pin(refresh_node(
+                node.clone(),
+                inputs,
+                dispatch,
+            )));
+            (Source::Cache, Ok(Some(value)))
+        }
+        (None, Some(Lookup::Miss)) => {
+            let result = conform(node, op, inputs.clone(), dispatch, options).await;
+            if let (Ok(Some(value)), Some(cache)) = (&result, node.borrow_mut().cache.as_mut()) {
+                cache.store(inputs, value.clone());
+            }
+            (Source::Op, result)
+        }
+    };
+    let Ok(Some(result)) = result else {
+        return (source, result);
+    };
+    if let (Some(sampler), Some(inputs)) = (&graph.sampler, sampled_inputs) {
+        match &graph.redactor {
+            Some(redactor) => {
+                let inputs: Vec<String> = inputs.iter().map(|i| redactor.redact(i)).collect();
+                sampler
+                    .borrow_mut()
+                    .record(run.run_id, name, &inputs, &redactor.redact(&result));
+            }
+            None => sampler
+                .borrow_mut()
+                .record(run.run_id, name, &inputs, &result),
+        }
+    }
+    (source, Ok(Some(result)))
+}
+
+/// Re-runs a `Node`s `op` for a stale cache entry and stores the new value. This is only ever driven from
+/// `Graph::run` or `Graph::revalidate`, and never delays the run that noticed the entry was stale. Refreshes wait
+/// for a concurrency slot at `Priority::Batch`.
+async fn refresh_node(node: Rc<RefCell<Node>>, inputs: Vec<String>, dispatch: Dispatch) {
+    let options = RunOptions::default().with_priority(Priority::Batch);
+    let op = node.borrow().op.clone();
+    let result = execute(&node, op, inputs.clone(), dispatch, &options).await;
+    if let Some(cache) = node.borrow_mut().cache.as_mut() {
+        match result {
+            Ok(Some(value)) => cache.store(inputs, value),
+            _ => cache.abandon_refresh(&inputs),
         }
     }
-    let t = (node.clone().borrow().op)(inputs);
-    let result = t.await;
-    let _ = node.borrow().sender.send(result);
 }
 
 /// A `Graph` stores a bunch of `Node`s (added with `stage_node`). It also has the `run` method, which will
@@ -53,11 +521,29 @@ async fn run_node(node: &Rc<RefCell<Node>>, receivers: Vec<Receiver<String>>) {
 /// from a `Node` by referencing it with `output_name`.
 #[derive(Default)]
 pub struct Graph {
-    graph: HashMap<String, Rc<RefCell<Node>>>,
-    channels: HashMap<String, Sender<String>>,
+    /// Kept in staging order, so `Node`s are always scheduled in the same order.
+    graph: IndexMap<String, Rc<RefCell<Node>>>,
+    revalidations: RefCell<FuturesUnordered<BoxedFuture<()>>>,
+    limiter: Option<Rc<Limiter>>,
+    admission: Option<(Rc<Limiter>, usize)>,
+    tenant_weights: HashMap<String, u32>,
+    memory_limit: Option<usize>,
+    /// How many `Node`s may finish before the run yields to the runtime, see `set_yield_budget`.
+    yield_budget: Option<usize>,
+    guard: Option<InjectionGuard>,
+    redactor: Option<Redactor>,
+    residency: ResidencyPolicy,
+    sampler: Option<RefCell<Sampler>>,
+    profile: Option<Rc<RefCell<LatencyProfile>>>,
+    /// The middleware a `GraphSpec` applied to each tag.
+    group_middleware: BTreeMap<String, Vec<MiddlewareSpec>>,
+    wiring: RefCell<Option<Rc<Wiring>>>,
+    /// Each `Node`s critical path in the `LatencyProfile`, and when it was worked out, see `urgency`.
+    urgency: RefCell<Option<(Instant, Rc<Urgency>)>>,
+    defaults: NodeDefaults,
 }
 
-impl<'a> Graph {
+impl Graph {
     /// `stage_node` lets you add a `Node` to the graph by providing the `name`, a list of other `Node`s (referenced by their `name`)
     /// that will be input to this `Node`s `op`, and finally the `op`. The simplest way to specify an `op` is to have an
     /// `async fn(Vec<String>) -> String` and wrap it with the `wrap!` macro.
@@ -65,69 +551,1129 @@ impl<'a> Graph {
     /// *At least one of the nodes needs to have only a single input named `entrypoint` which is where the rest of the inference graph
     /// will start.*
     pub fn stage_node(&mut self, name: String, inputs: Vec<String>, op: OpFn) {
-        let (tx, _) = channel(1);
-        let node = Rc::new(RefCell::new(Node::new(
-            name.clone(),
-            inputs,
-            op,
-            tx.clone(),
-        )));
-        self.graph.insert(name.clone(), node);
-        self.channels.insert(name, tx);
+        self.stage_op(name, inputs, op_from_fn(op));
     }
 
-    /// `run` lets you pass in a `String` that will be sent to any nodes referencing `entrypoint` in their inputs. You must also pass in
-    /// the `output_name` to reference the `Node` of that name as the final step in this run of the graph. Once that node has a value
-    /// from its `op`, it will be returned to you in the `Result`.
-    pub async fn run(
+    /// `stage_metric_node` adds a `Node` that scores another `Node`s output with `metric`, for graphs that evaluate
+    /// themselves. `inputs` names the `Node` holding the original input followed by the `Node` whose output should
+    /// be scored, and optionally a third `Node` holding the expected output. The `Node` outputs the score, or an
+    /// empty string when the metric doesn't produce one.
+    pub fn stage_metric_node(
         &mut self,
-        entrypoint_value: String,
-        output_name: String,
-    ) -> Result<String, Box<dyn Error>> {
-        let (entrypoint_tx, _) = channel(1);
+        name: String,
+        inputs: Vec<String>,
+        metric: impl Metric + 'static,
+    ) {
+        assert!(
+            matches!(inputs.len(), 2 | 3),
+            "Metric node {name} needs 2 or 3 inputs, got {}",
+            inputs.len()
+        );
+        let metric = Rc::new(metric);
+        let op: Op = Rc::new(move |x: Vec<String>| {
+            let metric = metric.clone();
+            Box::pin(async move {
+                let score = metric
+                    .score(&x[0], &x[1], x.get(2).map(String::as_str))
+                    .await;
+                Ok(Some(
+                    score.map(|score| score.to_string()).unwrap_or_default(),
+                ))
+            })
+        });
+        self.stage_op(name, inputs, op);
+    }
 
-        self.channels
-            .insert("entrypoint".into(), entrypoint_tx.clone());
+    /// `stage_json_node` adds a `Node` whose `op` works on typed values rather than strings. Every input is decoded
+    /// from JSON into an `I` before `op` is called, and the `O` it returns is encoded back into JSON for the `Node`s
+    /// downstream. An input that doesn't decode fails the run with a `RunError::Decode` naming the edge it came in on.
+    /// ```
+    /// # use inference_graph::graph::Graph;
+    /// async fn total(prices: Vec<Vec<u32>>) -> u32 {
+    ///     prices.into_iter().flatten().sum()
+    /// }
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let mut graph = Graph::default();
+    /// graph.stage_json_node("total".into(), vec!["entrypoint".into()], total);
+    /// let output = graph.run("[3, 4]".into(), "total".into()).await;
+    /// assert_eq!(output.unwrap(), "7");
+    /// # }
+    /// ```
+    pub fn stage_json_node<I, O, F, Fut>(&mut self, name: String, inputs: Vec<String>, op: F)
+    where
+        I: DeserializeOwned + 'static,
+        O: Serialize + 'static,
+        F: Fn(Vec<I>) -> Fut + 'static,
+        Fut: Future<Output = O> + 'static,
+    {
+        let node_name = name.clone();
+        let edges = inputs.clone();
+        let op = Rc::new(op);
+        let typed: Op = Rc::new(move |x: Vec<String>| {
+            let decoded: Result<Vec<I>, RunError> = x
+                .iter()
+                .zip(&edges)
+                .map(|(value, input)| {
+                    serde_json::from_str(value).map_err(|e| RunError::Decode {
+                        node: node_name.clone(),
+                        input: input.clone(),
+                        message: e.to_string(),
+                    })
+                })
+                .collect();
+            let (op, node) = (op.clone(), node_name.clone());
+            Box::pin(async move {
+                let output = op(decoded?).await;
+                let encoded = serde_json::to_string(&output).map_err(|e| RunError::Encode {
+                    node,
+                    message: e.to_string(),
+                })?;
+                Ok(Some(encoded))
+            })
+        });
+        self.stage_op(name, inputs, typed);
+    }
 
-        let mut tasks = FuturesUnordered::new();
+    /// `stage_multi_output_node` adds a `Node` whose `op` returns several named outputs, like an answer and how
+    /// confident it is, as a JSON object. Any `Node` whose output is a JSON object has outputs like this: a consumer
+    /// subscribes to one by listing `name.key` as its input, and gets the field as it is if it is a string, or as
+    /// JSON otherwise. Consumers listing just `name` get the whole object. A run fails with
+    /// `RunError::MissingOutput` if a field that is subscribed to is missing.
+    /// ```
+    /// # use inference_graph::graph::Graph;
+    /// # use inference_graph::wrap;
+    /// # use std::collections::BTreeMap;
+    /// async fn answer(x: Vec<String>) -> BTreeMap<String, String> {
+    ///     BTreeMap::from([
+    ///         ("answer".to_string(), x.concat()),
+    ///         ("confidence".to_string(), "0.9".to_string()),
+    ///     ])
+    /// }
+    ///
+    /// async fn concat(x: Vec<String>) -> String {
+    ///     x.join(" ")
+    /// }
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let mut graph = Graph::default();
+    /// graph.stage_multi_output_node("A".into(), vec!["entrypoint".into()], answer);
+    /// graph.stage_node("B".into(), vec!["A.answer".into(), "A.confidence".into()], wrap!(concat));
+    /// let output = graph.run("42".into(), "B".into()).await;
+    /// assert_eq!(output.unwrap(), "42 0.9");
+    /// # }
+    /// ```
+    pub fn stage_multi_output_node<F, Fut>(&mut self, name: String, inputs: Vec<String>, op: F)
+    where
+        F: Fn(Vec<String>) -> Fut + 'static,
+        Fut: Future<Output = BTreeMap<String, String>> + 'static,
+    {
+        let op = Rc::new(op);
+        let outputs: Op = Rc::new(move |x: Vec<String>| {
+            let value = op(x);
+            Box::pin(async move { Ok(Some(serde_json::json!(value.await).to_string())) })
+        });
+        self.stage_op(name, inputs, outputs);
+    }
 
-        let mut my_receiver = self
-            .channels
-            .get(&output_name)
-            .unwrap_or_else(|| panic!("Output node of name {output_name} does not exist"))
-            .subscribe();
+    /// `stage_pooled_node` adds a `Node` whose `op` needs an instance of something expensive to build, like a client
+    /// or a model. Each execution checks an instance out of `pool` and hands it to `op` along with the inputs; it goes
+    /// back into the pool once `op` is done with it, so concurrent runs share the pool's warm instances.
+    pub fn stage_pooled_node<T, F, Fut>(
+        &mut self,
+        name: String,
+        inputs: Vec<String>,
+        pool: Pool<T>,
+        op: F,
+    ) where
+        T: 'static,
+        F: Fn(Pooled<T>, Vec<String>) -> Fut + 'static,
+        Fut: Future<Output = String> + 'static,
+    {
+        let op = Rc::new(op);
+        let pooled: Op = Rc::new(move |x: Vec<String>| {
+            let (op, pool) = (op.clone(), pool.clone());
+            Box::pin(async move {
+                let instance = pool.checkout().await;
+                Ok(Some(op(instance, x).await))
+            })
+        });
+        self.stage_op(name, inputs, pooled);
+    }
+
+    /// `stage_threaded_node` adds a `Node` whose `op` runs on `thread` rather than in the run, for ops that call into
+    /// a library that isn't `Send` or blocks, such as a native model. `op` gets the state of the thread along with the
+    /// inputs. The run waits for the answer without blocking, and fails with `RunError::ThreadStopped` if the thread
+    /// has stopped. A timed out attempt can't be interrupted on the thread, so later calls queue up behind it.
+    pub fn stage_threaded_node<T, F>(
+        &mut self,
+        name: String,
+        inputs: Vec<String>,
+        thread: DedicatedThread<T>,
+        op: F,
+    ) where
+        T: 'static,
+        F: Fn(&mut T, Vec<String>) -> String + Send + Sync + 'static,
+    {
+        let node = name.clone();
+        let op = std::sync::Arc::new(op);
+        let threaded: Op = Rc::new(move |x: Vec<String>| {
+            let (op, thread, node) = (op.clone(), thread.clone(), node.clone());
+            Box::pin(async move {
+                match thread.call(move |state| op(state, x)).await {
+                    Some(value) => Ok(Some(value)),
+                    None => Err(RunError::ThreadStopped { node }),
+                }
+            })
+        });
+        self.stage_op(name, inputs, threaded);
+    }
+
+    /// `stage_optional_node` adds a `Node` whose `op` may have no value, like one that extracts a citation only if the
+    /// text has one. Every `Node` taking it as an input must say what it does without that value: with `require_some`
+    /// it is skipped and has no value either, and one staged with `stage_node_accepting_none` gets `None` in its
+    /// place. `validate` reports `Node`s that say neither, and a run whose output `Node` has no value fails with
+    /// `RunError::NoValue`.
+    pub fn stage_optional_node<F, Fut>(&mut self, name: String, inputs: Vec<String>, op: F)
+    where
+        F: Fn(Vec<String>) -> Fut + 'static,
+        Fut: Future<Output = Option<String>> + 'static,
+    {
+        let op = Rc::new(op);
+        let optional: Op = Rc::new(move |x: Vec<String>| {
+            let value = op(x);
+            Box::pin(async move { Ok(value.await) })
+        });
+        self.stage_op(name.clone(), inputs, optional);
+        self.set_optional(&name);
+    }
+
+    /// `stage_node_accepting_none` adds a `Node` that runs even when some of its inputs have no value, getting `None`
+    /// for those. Its executions are never cached, sampled or sent to a canary.
+    pub fn stage_node_accepting_none<F, Fut>(&mut self, name: String, inputs: Vec<String>, op: F)
+    where
+        F: Fn(Vec<Option<String>>) -> Fut + 'static,
+        Fut: Future<Output = String> + 'static,
+    {
+        let op = Rc::new(op);
+        let lenient: OpOn<Vec<Option<String>>> = Rc::new(move |x: Vec<Option<String>>| {
+            let value = op(x);
+            Box::pin(async move { Ok(Some(value.await)) })
+        });
+        self.stage_lenient_op(name, inputs, lenient);
+    }
+
+    /// Like `stage_node_accepting_none`, for ops that aren't a plain async function.
+    pub(crate) fn stage_lenient_op(
+        &mut self,
+        name: String,
+        inputs: Vec<String>,
+        lenient: OpOn<Vec<Option<String>>>,
+    ) {
+        let strict = lenient.clone();
+        let strict: Op = Rc::new(move |x: Vec<String>| strict(x.into_iter().map(Some).collect()));
+        self.stage_op(name.clone(), inputs, strict);
+        self.node(&name).borrow_mut().lenient = Some(lenient);
+    }
+
+    /// Declares that the `op` of the `Node` called `name` may have no value, as one staged with `stage_optional_node`.
+    pub(crate) fn set_optional(&mut self, name: &str) {
+        self.node(name).borrow_mut().optional = true;
+    }
+
+    /// `require_some` declares that the `Node` called `name` needs a value on every input, so it is skipped, and has
+    /// no value itself, when one of them has none.
+    pub fn require_some(&mut self, name: &str) {
+        self.node(name).borrow_mut().requires_some = true;
+    }
+
+    /// Like `stage_node`, for ops that aren't a plain `OpFn`.
+    pub(crate) fn stage_op(&mut self, name: String, inputs: Vec<String>, op: Op) {
+        let mut node = Node::with_op(name.clone(), inputs, op);
+        node.settings = self.defaults.settings.clone();
+        node.cache = self.defaults.cache.map(NodeCache::new);
+        self.graph.insert(name, Rc::new(RefCell::new(node)));
+        *self.wiring.get_mut() = None;
+        *self.urgency.get_mut() = None;
+    }
+
+    /// `cache_node` makes the `Node` called `name` remember its output for each distinct set of inputs according to
+    /// `policy`. When an entry is stale but within the policy's `stale_while_revalidate` window, the cached value is
+    /// used right away and a refresh is queued. Queued refreshes are driven in the background of later calls to `run`,
+    /// or can be awaited directly with `revalidate`.
+    pub fn cache_node(&mut self, name: &str, policy: CachePolicy) {
+        self.node(name).borrow_mut().cache = Some(NodeCache::new(policy));
+    }
+
+    /// `describe_node` sets a human-readable account of what the `Node` called `name` is for, shown by
+    /// `describe_markdown`.
+    pub fn describe_node(&mut self, name: &str, description: &str) {
+        self.node(name).borrow_mut().description = Some(description.to_string());
+    }
+
+    /// `describe_markdown` documents the pipeline as Markdown, with a section per `Node` in staging order giving its
+    /// description, inputs, op and how it is configured. It is generated from the graph itself, so docs built from it
+    /// can't drift from what actually runs.
+    pub fn describe_markdown(&self) -> String {
+        let code = |names: &mut dyn Iterator<Item = &String>| {
+            let names: Vec<String> = names.map(|name| format!("`{name}`")).collect();
+            if names.is_empty() {
+                "none".to_string()
+            } else {
+                names.join(", ")
+            }
+        };
+        let mut doc = format!("# Graph\n\n{} nodes.\n", self.graph.len());
+        for node in self.graph.values() {
+            let node = node.borrow();
+            let _ = write!(doc, "\n## {}\n\n", node.name);
+            if let Some(description) = &node.description {
+                let _ = write!(doc, "{description}\n\n");
+            }
+            let _ = writeln!(doc, "- Inputs: {}", code(&mut node.inputs.iter()));
+            let op = node
+                .op_name
+                .as_ref()
+                .map_or("unnamed".to_string(), |op| format!("`{op}`"));
+            let _ = writeln!(doc, "- Op: {op}");
+            if let Some(version) = node.version {
+                let _ = writeln!(doc, "- Version: {version}");
+            }
+            let settings = &node.settings;
+            let timeout = settings
+                .timeout
+                .map_or("none".to_string(), |t| format!("{t:?}"));
+            let _ = writeln!(doc, "- Timeout: {timeout}");
+            let _ = writeln!(doc, "- Retries: {}", settings.retries);
+            if settings.rate_limit.is_some() {
+                let _ = writeln!(doc, "- Rate limited");
+            }
+            if settings.quota.is_some() {
+                let _ = writeln!(doc, "- Quota");
+            }
+            if node.cache.is_some() {
+                let _ = writeln!(doc, "- Cached");
+            }
+            if !settings.enabled {
+                let _ = writeln!(doc, "- Disabled");
+            }
+            if !node.tags.is_empty() {
+                let _ = writeln!(doc, "- Tags: {}", code(&mut node.tags.iter()));
+            }
+        }
+        doc
+    }
+
+    /// `tag_node` adds the `Node` called `name` to the group `tag`. A `Node` can carry any number of tags.
+    pub fn tag_node(&mut self, name: &str, tag: &str) {
+        self.node(name).borrow_mut().tags.insert(tag.to_string());
+    }
+
+    /// `nodes_tagged` lists the names of every `Node` in the group `tag`, sorted.
+    pub fn nodes_tagged(&self, tag: &str) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .graph
+            .values()
+            .filter(|node| node.borrow().tags.contains(tag))
+            .map(|node| node.borrow().name.clone())
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// `set_node_defaults` sets the `NodeDefaults` that every `Node` staged from now on starts with. `Node`s that are
+    /// already staged keep what they have, so set them before staging.
+    /// ```
+    /// use inference_graph::cache::CachePolicy;
+    /// use inference_graph::graph::Graph;
+    /// use inference_graph::policy::NodeDefaults;
+    /// use std::time::Duration;
+    ///
+    /// let mut graph = Graph::default();
+    /// let mut defaults = NodeDefaults::default();
+    /// defaults.settings.timeout = Some(Duration::from_secs(10));
+    /// defaults.settings.retries = 2;
+    /// defaults.cache = Some(CachePolicy::new(Duration::from_secs(60)));
+    /// graph.set_node_defaults(defaults);
+    /// ```
+    pub fn set_node_defaults(&mut self, defaults: NodeDefaults) {
+        self.defaults = defaults;
+    }
+
+    /// `configure_node` changes the `NodeSettings` of the `Node` called `name`.
+    pub fn configure_node(&mut self, name: &str, configure: impl FnOnce(&mut NodeSettings)) {
+        configure(&mut self.node(name).borrow_mut().settings);
+    }
 
+    /// `configure_group` changes the `NodeSettings` of every `Node` tagged with `tag` at once, e.g. to give all
+    /// retrieval nodes the same timeout, or to disable them all:
+    /// ```
+    /// # use inference_graph::{graph::Graph, wrap};
+    /// # use std::time::Duration;
+    /// # async fn fetch(x: Vec<String>) -> String { x.concat() }
+    /// let mut graph = Graph::default();
+    /// for name in ["docs", "wiki", "web"] {
+    ///     graph.stage_node(name.into(), vec!["entrypoint".into()], wrap!(fetch));
+    ///     graph.tag_node(name, "retrieval");
+    /// }
+    /// graph.configure_group("retrieval", |settings| {
+    ///     settings.timeout = Some(Duration::from_secs(2));
+    ///     settings.retries = 1;
+    /// });
+    /// ```
+    pub fn configure_group(&mut self, tag: &str, mut configure: impl FnMut(&mut NodeSettings)) {
         for node in self.graph.values() {
-            let parent_node_name = node.borrow().name.clone();
-            let senders: Vec<Sender<String>> = node
-                .borrow()
+            let mut node = node.borrow_mut();
+            if node.tags.contains(tag) {
+                configure(&mut node.settings);
+            }
+        }
+    }
+
+    /// `set_canary` sends `percent` of the executions of the `Node` called `name` to `op` instead of its own, spread
+    /// evenly, so a new version of a single `Node` can be tried on live traffic. Canary executions bypass the
+    /// `Node`s cache and show up in a `RunTrace` with `Source::Canary`.
+    pub fn set_canary(&mut self, name: &str, op: OpFn, percent: u8) {
+        self.node(name).borrow_mut().canary = Some(Canary {
+            op: op_from_fn(op),
+            percent: percent.min(100),
+            executions: Cell::new(0),
+        });
+    }
+
+    /// `set_signature` declares what the `op` of the `Node` called `name` expects, for `validate` to check.
+    pub fn set_signature(&mut self, name: &str, signature: OpSignature) {
+        self.node(name).borrow_mut().signature = Some(signature);
+    }
+
+    /// `set_op_name` records that the `op` of the `Node` called `name` is registered as `op` in an `OpRegistry`, so
+    /// `topology` can describe it. Graphs built from a `GraphSpec` have this set for every `Node`.
+    pub fn set_op_name(&mut self, name: &str, op: &str) {
+        self.node(name).borrow_mut().op_name = Some(op.to_string());
+    }
+
+    pub(crate) fn set_op_config(&mut self, name: &str, config: serde_json::Value) {
+        self.node(name).borrow_mut().op_config = Some(config);
+    }
+
+    pub(crate) fn set_op_factory(&mut self, name: &str, factory: OpFactory) {
+        self.node(name).borrow_mut().op_factory = Some(factory);
+    }
+
+    pub(crate) fn set_node_middleware(&mut self, name: &str, middleware: Vec<MiddlewareSpec>) {
+        self.node(name).borrow_mut().middleware = middleware;
+    }
+
+    pub(crate) fn set_group_middleware(&mut self, tag: &str, middleware: Vec<MiddlewareSpec>) {
+        self.group_middleware.insert(tag.to_string(), middleware);
+    }
+
+    /// Replaces the `op` of the `Node` called `name` with what `wrap` makes of it.
+    pub(crate) fn wrap_op(&mut self, name: &str, wrap: impl Fn(Op) -> Op + 'static) {
+        let mut node = self.node(name).borrow_mut();
+        node.op = wrap(node.op.clone());
+        node.wrappers.push(Rc::new(wrap));
+    }
+
+    /// The `op`s of the `Node`s `options` overlay the `config` of, built from their `config` with the overlay's fields
+    /// in place of their own.
+    fn overlaid_ops(&self, options: &RunOptions) -> Result<HashMap<String, Op>, RunError> {
+        let mut ops = HashMap::new();
+        for (name, overlay) in &options.config_overlays {
+            let failed = |message: &str| RunError::Overlay {
+                node: name.clone(),
+                message: message.to_string(),
+            };
+            let node = self
+                .graph
+                .get(name)
+                .ok_or_else(|| failed("there is no such node"))?
+                .borrow();
+            let factory = node
+                .op_factory
+                .as_ref()
+                .ok_or_else(|| failed("its op takes no config"))?;
+            let serde_json::Value::Object(fields) = overlay else {
+                return Err(failed("the overlay is not an object"));
+            };
+            let mut config = match &node.op_config {
+                Some(serde_json::Value::Object(config)) => config.clone(),
+                _ => serde_json::Map::new(),
+            };
+            config.extend(fields.clone());
+            let op = factory(&serde_json::Value::Object(config)).map_err(|e| failed(&e))?;
+            let op = node.wrappers.iter().fold(op, |op, wrap| wrap(op));
+            ops.insert(name.clone(), op);
+        }
+        Ok(ops)
+    }
+
+    /// `set_output_schema` declares the `OutputSchema` the values of the `Node` called `name` must follow, usually
+    /// the output `Node` of the graph when it is asked for JSON. A value that doesn't fit is repaired by calling the
+    /// `op` again as the schema allows, before it is cached or handed on.
+    pub fn set_output_schema(&mut self, name: &str, schema: OutputSchema) {
+        self.node(name).borrow_mut().output_schema = Some(Rc::new(schema));
+    }
+
+    /// `set_node_version` pins the `Node` called `name` to `version`. Versions are saved with the `topology` and with
+    /// every `RunTrace`, so a `Migration` can tell which revision of a `Node` saved data came from.
+    pub fn set_node_version(&mut self, name: &str, version: Version) {
+        self.node(name).borrow_mut().version = Some(version);
+    }
+
+    pub fn node_version(&self, name: &str) -> Option<Version> {
+        self.graph.get(name).and_then(|node| node.borrow().version)
+    }
+
+    pub fn contains_node(&self, name: &str) -> bool {
+        self.graph.contains_key(name)
+    }
+
+    /// `topology` describes the `Node`s of the graph, in staging order, as a `GraphSpec`, which can be saved, diffed
+    /// or sent to another process and rebuilt there with `GraphSpec::build`. It fails for a `Node` whose op name isn't
+    /// known; see `set_op_name`. Middleware is listed as the spec the graph was built from declared it, while caching,
+    /// settings and everything else outside the spec are left out.
+    pub fn topology(&self) -> Result<GraphSpec, Vec<SpecError>> {
+        let mut errors = vec![];
+        let mut spec = GraphSpec {
+            middleware: self.group_middleware.clone(),
+            ..GraphSpec::default()
+        };
+        for (i, node) in self.graph.values().enumerate() {
+            let node = node.borrow();
+            match &node.op_name {
+                Some(op) => spec.nodes.push(NodeSpec {
+                    name: node.name.clone(),
+                    inputs: node.inputs.clone(),
+                    op: op.clone(),
+                    config: node.op_config.clone(),
+                    version: node.version,
+                    tags: node.tags.clone(),
+                    middleware: node.middleware.clone(),
+                }),
+                None => errors.push(SpecError::new(
+                    format!("/nodes/{i}/op"),
+                    format!("the op of node `{}` has no registered name", node.name),
+                )),
+            }
+        }
+        if errors.is_empty() {
+            Ok(spec)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// `classify_node` marks the output of the `Node` called `name` as `classification` data, e.g. `pii` or `health`.
+    /// Every `Node` downstream of it is taken to see that data too.
+    pub fn classify_node(&mut self, name: &str, classification: &str) {
+        self.node(name)
+            .borrow_mut()
+            .classifications
+            .insert(classification.to_string());
+    }
+
+    /// `set_destination` records that the `Node` called `name` sends what it gets to `region`, such as the region
+    /// of the hosted model it calls.
+    pub fn set_destination(&mut self, name: &str, region: &str) {
+        self.node(name).borrow_mut().destination = Some(region.to_string());
+    }
+
+    /// `set_residency_policy` sets which regions each data classification may be sent to, for `validate` to check.
+    /// Without one, classified data may not be sent anywhere.
+    pub fn set_residency_policy(&mut self, policy: ResidencyPolicy) {
+        self.residency = policy;
+    }
+
+    /// `validate` checks how the `Node`s are wired together without running anything. It reports inputs that name
+    /// no `Node`, `Node`s whose `OpSignature` disagrees with the number of inputs they get or the `ContentType`
+    /// their inputs produce, inputs subscribing to an output of a `Node` that isn't declared to produce JSON, and
+    /// classified data flowing to a destination the `ResidencyPolicy` doesn't approve. Problems are sorted by `Node`
+    /// name.
+    pub fn validate(&self) -> Vec<ValidationError> {
+        let mut nodes: Vec<Ref<Node>> = self.graph.values().map(|node| node.borrow()).collect();
+        nodes.sort_by(|a, b| a.name.cmp(&b.name));
+        let infos: Vec<NodeInfo> = nodes
+            .iter()
+            .map(|node| NodeInfo {
+                name: &node.name,
+                inputs: &node.inputs,
+                signature: node.signature.as_ref(),
+                classifications: &node.classifications,
+                destination: node.destination.as_deref(),
+                optional: node.optional,
+                requires_some: node.requires_some,
+                accepts_none: node.lenient.is_some(),
+            })
+            .collect();
+        validate::check(&infos, &self.residency)
+    }
+
+    /// `to_dot` draws the graph in Graphviz DOT, with an edge from each input to the `Node` reading it. With
+    /// `annotations`, `Node`s are labelled with their recorded latency and cost and coloured by how slow they are.
+    pub fn to_dot(&self, annotations: Option<&Annotations>) -> String {
+        let edges = self.edges();
+        let topology: Vec<(&str, &[String])> = edges
+            .iter()
+            .map(|(name, producers)| (name.as_str(), producers.as_slice()))
+            .collect();
+        export::to_dot(&topology, annotations)
+    }
+
+    /// `to_mermaid` is `to_dot` for a Mermaid flowchart, which renders straight in Markdown on most code hosts.
+    pub fn to_mermaid(&self, annotations: Option<&Annotations>) -> String {
+        let edges = self.edges();
+        let topology: Vec<(&str, &[String])> = edges
+            .iter()
+            .map(|(name, producers)| (name.as_str(), producers.as_slice()))
+            .collect();
+        export::to_mermaid(&topology, annotations)
+    }
+
+    /// `lint` checks every `Node` against the rules of `linter`, such as `Node`s tagged `llm` without a timeout, so
+    /// pipeline hygiene can be enforced in CI. Unlike `validate`, it is about how the graph is configured rather
+    /// than whether it is wired correctly. Diagnostics are sorted by `Node` name.
+    pub fn lint(&self, linter: &Linter) -> Vec<Diagnostic> {
+        let mut nodes: Vec<Ref<Node>> = self.graph.values().map(|node| node.borrow()).collect();
+        nodes.sort_by(|a, b| a.name.cmp(&b.name));
+        let mut consumers: HashMap<&str, Vec<String>> = HashMap::new();
+        for node in &nodes {
+            let producers: BTreeSet<&str> = node
                 .inputs
                 .iter()
-                .map(|name| {
-                    self.channels
-                        .get(name)
-                        .unwrap_or_else(|| {
-                            panic!("Node {parent_node_name} does not have {name} as an input")
-                        })
-                        .clone()
-                })
-                .collect();
-            let receivers: Vec<Receiver<String>> = senders
-                .iter()
-                .map(tokio::sync::broadcast::Sender::subscribe)
+                .map(|input| self.producer(input).0)
                 .collect();
+            for producer in producers {
+                consumers
+                    .entry(producer)
+                    .or_default()
+                    .push(node.name.clone());
+            }
+        }
+        let infos: Vec<LintNode> = nodes
+            .iter()
+            .map(|node| LintNode {
+                name: &node.name,
+                inputs: &node.inputs,
+                consumers: consumers.get(node.name.as_str()).map_or(&[], Vec::as_slice),
+                tags: &node.tags,
+                settings: &node.settings,
+            })
+            .collect();
+        linter.check(&infos)
+    }
+
+    /// The `Node` an input comes from, and the key of the output it subscribes to, see `split_input`.
+    fn producer<'a>(&self, input: &'a str) -> (&'a str, Option<&'a str>) {
+        split_input(input, |name| self.graph.contains_key(name))
+    }
 
-            let task = run_node(node, receivers);
-            tasks.push(task);
+    /// Every `Node` with the names of the `Node`s its inputs come from, for the analyses that only care which
+    /// `Node`s are connected and not which of their outputs travel along the edge.
+    fn edges(&self) -> Vec<(String, Vec<String>)> {
+        self.graph
+            .values()
+            .map(|node| {
+                let node = node.borrow();
+                let producers = node.inputs.iter().map(|input| self.producer(input).0);
+                (node.name.clone(), producers.map(str::to_string).collect())
+            })
+            .collect()
+    }
+
+    /// The name of the `Node` at `node` in staging order, or `entrypoint` for `None`.
+    fn name_at(&self, node: Option<usize>) -> &str {
+        match node {
+            Some(node) => self.graph.get_index(node).expect("a staged node").0,
+            None => "entrypoint",
         }
-        entrypoint_tx.send(entrypoint_value)?;
+    }
+
+    /// How the `Node`s are connected, worked out again only after a `Node` has been staged.
+    fn wiring(&self) -> Rc<Wiring> {
+        let mut wiring = self.wiring.borrow_mut();
+        let wiring = wiring.get_or_insert_with(|| {
+            let inputs = self.graph.values().map(|node| {
+                let node = node.borrow();
+                let inputs = node.inputs.iter().map(|input| match self.producer(input) {
+                    ("entrypoint", key) => (None, key.map(str::to_string)),
+                    (producer, key) => {
+                        let producer = self.graph.get_index_of(producer).unwrap_or_else(|| {
+                            panic!("Node {} does not have {input} as an input", node.name)
+                        });
+                        (Some(producer), key.map(str::to_string))
+                    }
+                });
+                inputs.collect()
+            });
+            Rc::new(Wiring::new(inputs.collect()))
+        });
+        wiring.clone()
+    }
 
-        while let Some(()) = tasks.next().await {}
-        let result = my_receiver
-            .recv()
+    fn node(&self, name: &str) -> &Rc<RefCell<Node>> {
+        self.graph
+            .get(name)
+            .unwrap_or_else(|| panic!("Node of name {name} does not exist"))
+    }
+
+    /// `set_concurrency_limit` caps how many `op`s may be in flight at once during `run`. With
+    /// `ConcurrencyLimit::Adaptive` the cap is tuned from observed `op` latency, which suits graphs backed by
+    /// rate-limited APIs whose right limit isn't known up front. It panics for `ConcurrencyLimit::Fixed(0)`, which
+    /// would never let an `op` run.
+    pub fn set_concurrency_limit(&mut self, limit: ConcurrencyLimit) {
+        assert!(
+            limit != ConcurrencyLimit::Fixed(0),
+            "A concurrency limit of 0 would never let an op run"
+        );
+        let limiter = Limiter::new(limit);
+        for (tenant, weight) in &self.tenant_weights {
+            limiter.set_weight(tenant, *weight);
+        }
+        self.limiter = Some(limiter);
+    }
+
+    /// `set_tenant_weight` gives `tenant` (see `RunOptions::with_tenant`) `weight` shares of the concurrency limit
+    /// whenever several tenants are waiting for it. Tenants default to a weight of 1.
+    pub fn set_tenant_weight(&mut self, tenant: &str, weight: u32) {
+        self.tenant_weights.insert(tenant.to_string(), weight);
+        let admission = self.admission.as_ref().map(|(limiter, _)| limiter);
+        for limiter in self.limiter.iter().chain(admission) {
+            limiter.set_weight(tenant, weight);
+        }
+    }
+
+    /// `in_flight_limit` returns the current cap set by `set_concurrency_limit`, if there is one.
+    pub fn in_flight_limit(&self) -> Option<usize> {
+        self.limiter.as_ref().map(|limiter| limiter.limit())
+    }
+
+    /// `set_admission_limit` bounds how many runs may be in flight and waiting at once. Runs over the limit fail fast
+    /// with `RunError::Overloaded`, so the graph degrades predictably under traffic spikes instead of piling up
+    /// memory. Waiting runs are admitted by `Priority` and tenant weight, like `op`s under a `ConcurrencyLimit`. It
+    /// panics if `max_running` is 0, which would never let a run start.
+    pub fn set_admission_limit(&mut self, limit: AdmissionLimit) {
+        assert!(
+            limit.max_running > 0,
+            "An admission limit of 0 running would never let a run start"
+        );
+        let limiter = Limiter::new(ConcurrencyLimit::Fixed(limit.max_running));
+        for (tenant, weight) in &self.tenant_weights {
+            limiter.set_weight(tenant, *weight);
+        }
+        self.admission = Some((limiter, limit.max_queued));
+    }
+
+    /// `set_memory_limit` caps the approximate number of payload bytes a single run may hold at once. A value counts
+    /// from when its `Node` produces it until every `Node` that takes it as an input has started, or can no longer
+    /// start because another of its inputs failed. A run that would go over the cap fails with
+    /// `RunError::MemoryLimit`, protecting the process from a `Node` that unexpectedly emits a huge value. Values are
+    /// never spilled out of memory instead: a `Node` that makes large values on purpose should put them in an
+    /// `ArtifactStore` and pass on an `ArtifactHandle`.
+    pub fn set_memory_limit(&mut self, bytes: usize) {
+        self.memory_limit = Some(bytes);
+    }
+
+    /// `set_yield_budget` makes a run hand control back to the runtime every time `ops` of its `Node`s have finished,
+    /// which defaults to 64. A graph of thousands of trivial `op`s can otherwise keep finishing `Node`s without ever
+    /// waiting, starving the other tasks on its thread. A smaller budget is fairer to them, a larger one has less
+    /// overhead.
+    pub fn set_yield_budget(&mut self, ops: usize) {
+        self.yield_budget = Some(ops.max(1));
+    }
+
+    /// `set_injection_guard` screens the values passed from untrusted `Node`s to tool `Node`s with `guard`, before the
+    /// tool `Node`s run. Which `Node`s are which is decided by their tags.
+    pub fn set_injection_guard(&mut self, guard: InjectionGuard) {
+        self.guard = Some(guard);
+    }
+
+    /// `set_redactor` strips personal data from the inputs of `Node`s tagged `external` before they run, and from
+    /// every record the `Sampler` writes. `Node`s without the tag still see the original values.
+    pub fn set_redactor(&mut self, redactor: Redactor) {
+        self.redactor = Some(redactor);
+    }
+
+    /// `set_sampler` records a fraction of this graph's `Node` executions (inputs and output) with `sampler`, to build
+    /// evaluation datasets out of real traffic.
+    pub fn set_sampler(&mut self, sampler: Sampler) {
+        self.sampler = Some(RefCell::new(sampler));
+    }
+
+    /// `sampler` gives access to the `Sampler` set with `set_sampler`, e.g. to check how many records it wrote.
+    pub fn sampler(&self) -> Option<Ref<'_, Sampler>> {
+        self.sampler.as_ref().map(RefCell::borrow)
+    }
+
+    /// `set_latency_profile` keeps `profile` up to date with how long each `Node`s `op` takes, and uses it to decide
+    /// which waiting `Node` gets the next slot under a `ConcurrencyLimit`: the one with the longest expected path to
+    /// the end of the `Graph`, so the slowest chain of a diamond isn't left waiting behind its quick siblings. Pass a
+    /// profile loaded from an earlier process, or `LatencyProfile::new()` to start from scratch.
+    pub fn set_latency_profile(&mut self, profile: LatencyProfile) {
+        self.profile = Some(Rc::new(RefCell::new(profile)));
+        *self.urgency.get_mut() = None;
+    }
+
+    /// `latency_profile` gives access to the profile set with `set_latency_profile`, e.g. to `save` it.
+    pub fn latency_profile(&self) -> Option<Ref<'_, LatencyProfile>> {
+        self.profile.as_ref().map(|profile| profile.borrow())
+    }
+
+    /// `revalidate` waits for every queued stale-while-revalidate refresh to finish.
+    pub async fn revalidate(&self) {
+        let mut pending = std::mem::take(&mut *self.revalidations.borrow_mut());
+        while let Some(()) = pending.next().await {}
+    }
+
+    /// `prime_cache` runs the graph up to `output_name` on each of `inputs`, such as a service's most common queries,
+    /// so the caches of its `Node`s are warm before real traffic arrives. At most `concurrency` of these runs are in
+    /// flight at once, and they run at `Priority::Batch`, so traffic that arrives meanwhile gets ahead of them for a
+    /// `ConcurrencyLimit`. A failed run doesn't stop the others, and is listed in the `PrimeReport`.
+    pub async fn prime_cache(
+        &self,
+        inputs: impl IntoIterator<Item = String>,
+        output_name: &str,
+        concurrency: usize,
+    ) -> PrimeReport {
+        let options = RunOptions::default().with_priority(Priority::Batch);
+        let runs = inputs.into_iter().map(|input| {
+            let options = &options;
+            async move {
+                let outcome = self
+                    .run_with_options(input.clone(), output_name.to_string(), options)
+                    .await;
+                (input, outcome.into_result())
+            }
+        });
+        let mut runs = futures::stream::iter(runs).buffer_unordered(concurrency.max(1));
+        let mut report = PrimeReport::default();
+        while let Some((input, result)) = runs.next().await {
+            report.runs += 1;
+            if let Err(e) = result {
+                report.failures.push((input, e.to_string()));
+            }
+        }
+        report
+    }
+
+    /// `run` lets you pass in a `String` that will be sent to any nodes referencing `entrypoint` in their inputs. You must also pass in
+    /// the `output_name` to reference the `Node` of that name as the final step in this run of the graph. Once that node has a value
+    /// from its `op`, it will be returned to you in the `RunOutcome`. If a `Node` the output depends on fails, the run
+    /// stops and fails with its `RunError`. Other `Node`s failing doesn't stop it, and the run ends with the output
+    /// and their errors as `RunOutcome::PartialWithErrors`.
+    ///
+    /// Every call to `run` keeps its own values in flight between `Node`s, so several runs of the same graph can be in
+    /// flight at once (for example with `futures::future::join_all`). A `Node` is only started once all of its inputs
+    /// have arrived, so a run of a very large graph holds on to the `Node`s that are running or partly fed rather
+    /// than to every `Node` at once.
+    pub async fn run(&self, entrypoint_value: String, output_name: String) -> RunOutcome {
+        self.run_with_options(entrypoint_value, output_name, &RunOptions::default())
+            .await
+    }
+
+    /// `run_with_options` is `run` with `RunOptions` that only apply to this run, such as tags to skip.
+    pub async fn run_with_options(
+        &self,
+        entrypoint_value: String,
+        output_name: String,
+        options: &RunOptions,
+    ) -> RunOutcome {
+        self.run_inner(entrypoint_value, output_name, options, None)
             .await
-            .expect("Could not receive anything on the output channel");
-        Ok(result)
+    }
+
+    /// `run_traced` is `run_with_options` that also returns a `RunTrace` of every `Node` that finished, whether the
+    /// run succeeded or not.
+    pub async fn run_traced(
+        &self,
+        entrypoint_value: String,
+        output_name: String,
+        options: &RunOptions,
+    ) -> (RunOutcome, RunTrace) {
+        // The id is settled here rather than in `run_inner`, so the trace records the one the run used.
+        let run_id = options.run_id.unwrap_or_default();
+        let options = &RunOptions {
+            run_id: Some(run_id),
+            ..options.clone()
+        };
+        let nodes = RefCell::new(vec![]);
+        let result = self
+            .run_inner(
+                entrypoint_value.clone(),
+                output_name.clone(),
+                options,
+                Some(&nodes),
+            )
+            .await;
+        let trace = RunTrace {
+            run_id,
+            input: entrypoint_value,
+            output_node: output_name,
+            nodes: nodes.into_inner(),
+            graph: self
+                .graph
+                .values()
+                .map(|node| {
+                    let node = node.borrow();
+                    (node.name.clone(), node.inputs.clone())
+                })
+                .collect(),
+            versions: self
+                .graph
+                .values()
+                .filter_map(|node| {
+                    let node = node.borrow();
+                    Some((node.name.clone(), node.version?))
+                })
+                .collect(),
+        };
+        (result, trace)
+    }
+
+    /// Each `Node`s critical path in the `LatencyProfile`, worked out again at most once every `URGENCY_REFRESH`, and
+    /// after a `Node` is staged or the profile is replaced.
+    fn urgency(&self) -> Rc<Urgency> {
+        let Some(profile) = &self.profile else {
+            return Rc::default();
+        };
+        let mut urgency = self.urgency.borrow_mut();
+        if let Some((ranked, urgency)) = &*urgency {
+            if ranked.elapsed() < URGENCY_REFRESH {
+                return urgency.clone();
+            }
+        }
+        let edges = self.edges();
+        let nodes: Vec<(&str, &[String])> = edges
+            .iter()
+            .map(|(name, producers)| (name.as_str(), producers.as_slice()))
+            .collect();
+        let paths = profile.borrow().critical_paths(&nodes);
+        let paths: Rc<Urgency> = Rc::new(
+            paths
+                .into_iter()
+                .map(|(name, path)| (name.to_string(), path))
+                .collect(),
+        );
+        *urgency = Some((Instant::now(), paths.clone()));
+        paths
+    }
+
+    async fn run_inner(
+        &self,
+        entrypoint_value: String,
+        output_name: String,
+        options: &RunOptions,
+        trace: Option<&RefCell<Vec<NodeTrace>>>,
+    ) -> RunOutcome {
+        let result = self
+            .try_run(entrypoint_value, &output_name, options, trace)
+            .await;
+        match result {
+            Ok((Some(output), errors)) if errors.is_empty() => RunOutcome::Complete(output),
+            Ok((Some(output), errors)) => RunOutcome::PartialWithErrors { output, errors },
+            Ok((None, _)) => RunOutcome::SkippedOutput { node: output_name },
+            Err(RunError::Cancelled) => RunOutcome::Cancelled,
+            Err(e) => RunOutcome::Failed(e),
+        }
+    }
+
+    /// Runs the graph, returning the value of the output, if it has one, and the errors of the `Node`s it doesn't
+    /// depend on.
+    async fn try_run(
+        &self,
+        entrypoint_value: String,
+        output_name: &str,
+        options: &RunOptions,
+        trace: Option<&RefCell<Vec<NodeTrace>>>,
+    ) -> Result<(Option<String>, Vec<RunError>), RunError> {
+        let _admitted = match &self.admission {
+            Some((limiter, max_queued)) => {
+                if limiter.queue().is_some_and(|queued| queued >= *max_queued) {
+                    return Err(RunError::Overloaded);
+                }
+                let tenant = options.tenant.as_deref().unwrap_or_default();
+                Some(
+                    limiter
+                        .acquire(options.priority, tenant, Duration::ZERO)
+                        .await,
+                )
+            }
+            None => None,
+        };
+
+        let run = RunState {
+            options,
+            run_id: options.run_id.unwrap_or_default(),
+            memory: self.memory_limit.map(|limit| {
+                let nodes: Vec<_> = self.graph.values().map(|node| node.borrow()).collect();
+                let edges = nodes.iter().flat_map(|node| node.inputs.iter());
+                RunMemory::new(
+                    limit,
+                    edges
+                        .map(String::as_str)
+                        .chain([output_name])
+                        .map(|input| self.producer(input).0),
+                )
+            }),
+            started: Instant::now(),
+            trace,
+            urgency: self.urgency(),
+            overlaid: self.overlaid_ops(options)?,
+        };
+        run.produced("entrypoint", Some(&entrypoint_value))?;
+        if let Some(control) = &options.control {
+            let nodes: Vec<_> = self.graph.values().map(|node| node.borrow()).collect();
+            control.begin(
+                run.run_id,
+                nodes
+                    .iter()
+                    .map(|node| (node.name.as_str(), node.inputs.as_slice())),
+            );
+        }
+
+        let wiring = self.wiring();
+        let (output_node, output_key) = self.producer(output_name);
+        let output_index = match output_node {
+            "entrypoint" => None,
+            node => Some(
+                self.graph
+                    .get_index_of(node)
+                    .unwrap_or_else(|| panic!("Output node of name {output_name} does not exist")),
+            ),
+        };
+        let unreachable = || RunError::Unreachable {
+            node: output_node.to_string(),
+        };
+        if output_index.is_some_and(|node| !wiring.runnable(node)) {
+            return Err(unreachable());
+        }
+        // Starts the `Node` at `node` on `inputs`, or to wait for its override if it doesn't have all of them.
+        let launch = |tasks: &mut FuturesUnordered<_>,
+                      node: usize,
+                      inputs: Vec<Option<String>>,
+                      complete: bool| {
+            let producers = wiring.producers(node);
+            if let Some(memory) = &run.memory {
+                for (producer, _) in producers.iter().zip(&inputs).filter(|(_, i)| i.is_some()) {
+                    memory.consumed(self.name_at(*producer));
+                }
+            }
+            if let Some(control) = &options.control {
+                control.set_state(self.name_at(Some(node)), NodeState::Running);
+            }
+            let future = run_node(
+                self,
+                &self.graph[node],
+                producers,
+                complete.then_some(inputs),
+                &run,
+            );
+            tasks.push(async move { (node, future.await) });
+        };
+
+        // Refreshes queued by earlier runs make progress alongside this one, but are not waited on.
+        let mut pending = std::mem::take(&mut *self.revalidations.borrow_mut());
+        let outcome = {
+            // `Node`s are only started once all of their inputs have arrived, so a run holds futures for the `Node`s
+            // that are running and values for those that are partly fed, rather than for the whole `Graph`.
+            let drain = async {
+                let mut frontier = Frontier::new(&wiring);
+                let mut tasks = FuturesUnordered::new();
+                let mut output = None;
+                let mut errors = vec![];
+                let feeding = wiring.feeding(output_index);
+                let mut finished = |frontier: &mut Frontier,
+                                    tasks: &mut FuturesUnordered<_>,
+                                    producer: Option<usize>,
+                                    value: Option<String>| {
+                    let name = self.name_at(producer);
+                    if producer == output_index {
+                        output = Some(schedule::output(name, value.clone(), output_key)?);
+                    }
+                    let received = |consumer: usize, slot: usize| {
+                        if let Some(control) = &options.control {
+                            let node = self.graph[consumer].borrow();
+                            control.received(&node.name, &node.inputs[slot]);
+                        }
+                    };
+                    let dropped = || {
+                        if let Some(memory) = &run.memory {
+                            memory.consumed(name);
+                        }
+                    };
+                    let ready = frontier.deliver(producer, name, value, received, dropped)?;
+                    for (node, inputs) in ready {
+                        launch(tasks, node, inputs, true);
+                    }
+                    Ok::<(), RunError>(())
+                };
+                for node in wiring.sources() {
+                    launch(&mut tasks, node, vec![], true);
+                }
+                finished(&mut frontier, &mut tasks, None, Some(entrypoint_value))?;
+                let budget = self.yield_budget.unwrap_or(DEFAULT_YIELD_BUDGET);
+                let mut done = 0;
+                loop {
+                    if let Some(control) = &options.control {
+                        if control.is_cancelled() {
+                            return Err(RunError::Cancelled);
+                        }
+                        for name in control.waiting_overrides() {
+                            if let Some(node) = self.graph.get_index_of(&name) {
+                                let held = frontier.start_early(node);
+                                launch(&mut tasks, node, held, false);
+                            }
+                        }
+                    }
+                    let overridden = async {
+                        match &options.control {
+                            Some(control) => control.changed().await,
+                            None => future::pending().await,
+                        }
+                    };
+                    futures::pin_mut!(overridden);
+                    let next = match future::select(tasks.next(), overridden).await {
+                        Either::Left((next, _)) => next,
+                        Either::Right(_) => continue,
+                    };
+                    let Some((node, result)) = next else {
+                        break;
+                    };
+                    match result {
+                        Ok(value) => finished(&mut frontier, &mut tasks, Some(node), value)?,
+                        // The `Node`s waiting on one that failed never start, and the output doesn't need them.
+                        Err(e) if Some(node) != output_index && !feeding[node] => {
+                            for producer in frontier.abandon(node) {
+                                if let Some(memory) = &run.memory {
+                                    memory.consumed(self.name_at(producer));
+                                }
+                            }
+                            errors.push(e);
+                        }
+                        Err(e) => return Err(e),
+                    }
+                    done += 1;
+                    if done % budget == 0 {
+                        tokio::task::yield_now().await;
+                    }
+                }
+                Ok::<_, RunError>((output, errors))
+            };
+            let drive = async {
+                while let Some(()) = pending.next().await {}
+                future::pending::<()>().await;
+            };
+            futures::pin_mut!(drain, drive);
+            match future::select(drain, drive).await {
+                Either::Left((outcome, _)) => outcome,
+                Either::Right(_) => unreachable!(),
+            }
+        };
+        self.revalidations.borrow_mut().extend(pending);
+        let (output, errors) = outcome?;
+        Ok((output.ok_or_else(unreachable)?, errors))
     }
 }
 
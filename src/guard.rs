@@ -0,0 +1,144 @@
+use crate::error::RunError;
+
+/// An `InjectionDetector` decides whether a value looks like an attempt to smuggle instructions into a prompt.
+pub trait InjectionDetector {
+    /// Returns why `value` looks like an injection attempt, or `None` if it looks fine.
+    fn detect(&self, value: &str) -> Option<String>;
+
+    /// Returns `value` made safe to hand on, for a guard set to `GuardAction::Sanitize`.
+    fn sanitize(&self, value: &str) -> String;
+}
+
+/// Flags values containing any of a list of phrases, ignoring case. `PhraseDetector::default()` knows a handful of
+/// common ones, like "ignore previous instructions". Sanitizing cuts every phrase out of the value.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PhraseDetector {
+    phrases: Vec<String>,
+}
+
+impl PhraseDetector {
+    pub fn new(phrases: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        Self {
+            phrases: phrases
+                .into_iter()
+                .map(|p| p.into().to_lowercase())
+                .collect(),
+        }
+    }
+}
+
+impl Default for PhraseDetector {
+    fn default() -> Self {
+        Self::new([
+            "ignore previous instructions",
+            "ignore all previous instructions",
+            "ignore the above",
+            "disregard previous instructions",
+            "disregard the above",
+            "forget your instructions",
+            "you are now",
+            "new instructions:",
+            "system prompt",
+        ])
+    }
+}
+
+impl InjectionDetector for PhraseDetector {
+    fn detect(&self, value: &str) -> Option<String> {
+        let lower = value.to_lowercase();
+        self.phrases
+            .iter()
+            .find(|phrase| lower.contains(phrase.as_str()))
+            .map(|phrase| format!("contains \"{phrase}\""))
+    }
+
+    fn sanitize(&self, value: &str) -> String {
+        let mut value = value.to_string();
+        for phrase in &self.phrases {
+            // Lowercasing can change byte lengths, so only cut where the match lines up with the original.
+            while let Some(start) = value.to_lowercase().find(phrase.as_str()) {
+                match value.get(start..start + phrase.len()) {
+                    Some(found) if found.to_lowercase() == *phrase => {
+                        value.replace_range(start..start + phrase.len(), "")
+                    }
+                    _ => break,
+                }
+            }
+        }
+        value
+    }
+}
+
+/// What an `InjectionGuard` does with a value its detector flags.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GuardAction {
+    /// Fail the run with `RunError::InjectionBlocked`.
+    Block,
+    /// Pass on `InjectionDetector::sanitize`d value instead.
+    Sanitize,
+}
+
+/// An `InjectionGuard` screens the values flowing from `Node`s tagged `untrusted` (retrieval, user content) into
+/// `Node`s tagged `tool` (anything that acts on the world) with an `InjectionDetector`. Values between any other
+/// `Node`s pass untouched. Install one with `Graph::set_injection_guard`.
+pub struct InjectionGuard {
+    detector: Box<dyn InjectionDetector>,
+    action: GuardAction,
+    untrusted_tag: String,
+    tool_tag: String,
+}
+
+impl InjectionGuard {
+    /// Creates a guard that blocks whatever `detector` flags.
+    pub fn new(detector: impl InjectionDetector + 'static) -> Self {
+        Self {
+            detector: Box::new(detector),
+            action: GuardAction::Block,
+            untrusted_tag: "untrusted".into(),
+            tool_tag: "tool".into(),
+        }
+    }
+
+    pub fn with_action(mut self, action: GuardAction) -> Self {
+        self.action = action;
+        self
+    }
+
+    /// Changes the tags that mark untrusted and tool `Node`s.
+    pub fn with_tags(mut self, untrusted: &str, tool: &str) -> Self {
+        self.untrusted_tag = untrusted.to_string();
+        self.tool_tag = tool.to_string();
+        self
+    }
+
+    pub(crate) fn untrusted_tag(&self) -> &str {
+        &self.untrusted_tag
+    }
+
+    pub(crate) fn tool_tag(&self) -> &str {
+        &self.tool_tag
+    }
+
+    /// Screens `value`, which `node` got from the untrusted `Node` called `input`.
+    pub(crate) fn screen(
+        &self,
+        node: &str,
+        input: &str,
+        value: &mut String,
+    ) -> Result<(), RunError> {
+        let Some(reason) = self.detector.detect(value) else {
+            return Ok(());
+        };
+        match self.action {
+            GuardAction::Block => Err(RunError::InjectionBlocked {
+                node: node.to_string(),
+                input: input.to_string(),
+                reason,
+            }),
+            GuardAction::Sanitize => {
+                *value = self.detector.sanitize(value);
+                Ok(())
+            }
+        }
+    }
+}
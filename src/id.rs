@@ -0,0 +1,137 @@
+use std::collections::hash_map::RandomState;
+use std::error::Error;
+use std::fmt;
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Crockford's base32 alphabet, which leaves out I, L, O and U so ids are easy to read out and type.
+const ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+static GENERATED: AtomicU64 = AtomicU64::new(0);
+
+tokio::task_local! {
+    static CURRENT: RunId;
+}
+
+/// A `RunId` names one run, so everything the run touches can be correlated: its `RunTrace`, the records a
+/// `Sampler` writes for it, and its `RunSnapshot`. It is a ULID: 48 bits of milliseconds since the Unix epoch
+/// followed by 80 random bits, written as 26 characters of Crockford base32 that sort by creation time. Every run
+/// gets a new one unless the caller supplies its own with `RunOptions::with_run_id`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RunId(u128);
+
+impl RunId {
+    pub fn new() -> Self {
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_millis() as u64);
+        // `RandomState` is seeded randomly per process; hashing a counter with it gives bits that differ for every
+        // id without pulling in a random number generator.
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u64(GENERATED.fetch_add(1, Ordering::Relaxed));
+        hasher.write_u64(millis);
+        let high = hasher.finish();
+        hasher.write_u64(high);
+        let low = hasher.finish();
+        let random = (u128::from(high & 0xffff) << 64) | u128::from(low);
+        Self::from_parts(millis, random)
+    }
+
+    /// A `RunId` from a timestamp in milliseconds (only the lowest 48 bits are used) and 80 bits of randomness.
+    pub fn from_parts(millis: u64, random: u128) -> Self {
+        let millis = u128::from(millis) & ((1 << 48) - 1);
+        Self((millis << 80) | (random & ((1 << 80) - 1)))
+    }
+
+    /// When the id was made, in milliseconds since the Unix epoch.
+    pub fn millis(&self) -> u64 {
+        (self.0 >> 80) as u64
+    }
+
+    /// The id of the run whose `op` is being polled, so an `op` or the middleware around it can tag what it reports
+    /// with it. It is `None` outside of an `op` called by a run.
+    pub fn current() -> Option<Self> {
+        CURRENT.try_with(|id| *id).ok()
+    }
+
+    /// Polls `future` as part of this run, so `current` returns this id within it.
+    pub(crate) async fn scope<F: Future>(self, future: F) -> F::Output {
+        CURRENT.scope(self, future).await
+    }
+}
+
+impl Default for RunId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Display for RunId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text: String = (0..26)
+            .rev()
+            .map(|i| ALPHABET[((self.0 >> (i * 5)) & 0x1f) as usize] as char)
+            .collect();
+        f.write_str(&text)
+    }
+}
+
+/// A string that isn't a ULID.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseRunIdError {
+    pub input: String,
+}
+
+impl fmt::Display for ParseRunIdError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "`{}` is not a ULID", self.input)
+    }
+}
+
+impl Error for ParseRunIdError {}
+
+/// Reads a ULID such as `01ARZ3NDEKTSV4RRFFQ69G5FAV`, ignoring case.
+impl FromStr for RunId {
+    type Err = ParseRunIdError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || ParseRunIdError {
+            input: s.to_string(),
+        };
+        // 26 characters hold 130 bits, so the first may only carry the top 3.
+        if s.len() != 26 || !s.starts_with(|c: char| ('0'..='7').contains(&c)) {
+            return Err(error());
+        }
+        let mut value = 0u128;
+        for c in s.bytes() {
+            let digit = ALPHABET
+                .iter()
+                .position(|&a| a == c.to_ascii_uppercase())
+                .ok_or_else(error)?;
+            value = (value << 5) | digit as u128;
+        }
+        Ok(Self(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_and_sorts_by_time() {
+        let id: RunId = "01ARZ3NDEKTSV4RRFFQ69G5FAV".parse().unwrap();
+        assert_eq!(id.to_string(), "01ARZ3NDEKTSV4RRFFQ69G5FAV");
+        assert_eq!(id.millis(), 1469922850259);
+        assert_eq!("01arz3ndektsv4rrffq69g5fav".parse(), Ok(id));
+        assert!("81ARZ3NDEKTSV4RRFFQ69G5FAV".parse::<RunId>().is_err());
+        assert!("01ARZ3NDEKTSV4RRFFQ69G5FAU".parse::<RunId>().is_err());
+
+        let (a, b) = (RunId::new(), RunId::new());
+        assert_ne!(a, b);
+        assert!(RunId::from_parts(1, u128::MAX) < RunId::from_parts(2, 0));
+    }
+}
@@ -0,0 +1,27 @@
+use serde_json::Value;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// The error for a file that doesn't hold what it should.
+pub(crate) fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+/// `read` calls `record` with each line of the JSONL file at `path` that isn't blank, and fails with the number of
+/// the first line that isn't JSON or that `record` rejects.
+pub(crate) fn read(
+    path: impl AsRef<Path>,
+    mut record: impl FnMut(Value) -> Result<(), String>,
+) -> io::Result<()> {
+    for (i, line) in fs::read_to_string(path)?.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        serde_json::from_str(line)
+            .map_err(|e| e.to_string())
+            .and_then(&mut record)
+            .map_err(|message| invalid(format!("line {}: {message}", i + 1)))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,226 @@
+use crate::policy::NodeSettings;
+use std::collections::BTreeSet;
+use std::fmt;
+
+/// What a `LintRule` sees of one `Node`: how it is wired, what it is tagged with and how it is configured.
+pub struct LintNode<'a> {
+    pub name: &'a str,
+    pub inputs: &'a [String],
+    /// The `Node`s that take this one as an input, each listed once.
+    pub consumers: &'a [String],
+    pub tags: &'a BTreeSet<String>,
+    pub settings: &'a NodeSettings,
+}
+
+/// A `LintRule` is one hygiene check on the `Node`s of a `Graph`. Implement it for checks specific to a team's
+/// pipelines; `TimeoutRequired`, `RetriesRequired` and `MaxFanOut` come built in.
+pub trait LintRule {
+    /// A short name for the rule, reported with every `Diagnostic` it raises.
+    fn name(&self) -> &str;
+
+    /// What is wrong with `node`, if anything.
+    fn check(&self, node: &LintNode) -> Option<String>;
+}
+
+/// How bad breaking a rule is. A CI job would typically fail on any `Error`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+/// A rule that the `Node` called `node` breaks.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub node: String,
+    pub rule: String,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        write!(
+            f,
+            "{severity}[{}] {}: {}",
+            self.rule, self.node, self.message
+        )
+    }
+}
+
+/// Flags `Node`s carrying `tag` (`llm` by default) that have no timeout, so one hung call can't stall a run.
+pub struct TimeoutRequired {
+    tag: String,
+}
+
+impl TimeoutRequired {
+    pub fn new(tag: &str) -> Self {
+        Self {
+            tag: tag.to_string(),
+        }
+    }
+}
+
+impl LintRule for TimeoutRequired {
+    fn name(&self) -> &str {
+        "timeout-required"
+    }
+
+    fn check(&self, node: &LintNode) -> Option<String> {
+        (node.tags.contains(&self.tag) && node.settings.timeout.is_none())
+            .then(|| format!("is tagged `{}` but has no timeout", self.tag))
+    }
+}
+
+/// Flags `Node`s carrying `tag` (`external` by default) that never retry, since calls to an external API fail
+/// now and then.
+pub struct RetriesRequired {
+    tag: String,
+}
+
+impl RetriesRequired {
+    pub fn new(tag: &str) -> Self {
+        Self {
+            tag: tag.to_string(),
+        }
+    }
+}
+
+impl LintRule for RetriesRequired {
+    fn name(&self) -> &str {
+        "retries-required"
+    }
+
+    fn check(&self, node: &LintNode) -> Option<String> {
+        (node.tags.contains(&self.tag) && node.settings.retries == 0)
+            .then(|| format!("is tagged `{}` but never retries", self.tag))
+    }
+}
+
+/// Flags `Node`s whose output feeds more than `max` other `Node`s.
+pub struct MaxFanOut {
+    max: usize,
+}
+
+impl MaxFanOut {
+    pub fn new(max: usize) -> Self {
+        Self { max }
+    }
+}
+
+impl LintRule for MaxFanOut {
+    fn name(&self) -> &str {
+        "max-fan-out"
+    }
+
+    fn check(&self, node: &LintNode) -> Option<String> {
+        let fan_out = node.consumers.len();
+        (fan_out > self.max).then(|| format!("feeds {fan_out} nodes, more than {}", self.max))
+    }
+}
+
+/// A `Linter` holds the rules `Graph::lint` checks, each with the `Severity` of breaking it. `Linter::default()`
+/// has `TimeoutRequired` for `llm` as an error, and `RetriesRequired` for `external` and `MaxFanOut` of 32 as
+/// warnings.
+pub struct Linter {
+    rules: Vec<(Box<dyn LintRule>, Severity)>,
+}
+
+impl Linter {
+    /// A `Linter` without any rules.
+    pub fn new() -> Self {
+        Self { rules: vec![] }
+    }
+
+    pub fn with_rule(mut self, rule: impl LintRule + 'static, severity: Severity) -> Self {
+        self.rules.push((Box::new(rule), severity));
+        self
+    }
+
+    /// Checks every rule against every one of `nodes`, in order.
+    pub(crate) fn check(&self, nodes: &[LintNode]) -> Vec<Diagnostic> {
+        let mut diagnostics = vec![];
+        for node in nodes {
+            for (rule, severity) in &self.rules {
+                if let Some(message) = rule.check(node) {
+                    diagnostics.push(Diagnostic {
+                        node: node.name.to_string(),
+                        rule: rule.name().to_string(),
+                        severity: *severity,
+                        message,
+                    });
+                }
+            }
+        }
+        diagnostics
+    }
+}
+
+impl Default for Linter {
+    fn default() -> Self {
+        Self::new()
+            .with_rule(TimeoutRequired::new("llm"), Severity::Error)
+            .with_rule(RetriesRequired::new("external"), Severity::Warning)
+            .with_rule(MaxFanOut::new(32), Severity::Warning)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::Graph;
+    use crate::wrap;
+    use std::time::Duration;
+
+    async fn concat(x: Vec<String>) -> String {
+        x.concat()
+    }
+
+    struct NoShouting;
+
+    impl LintRule for NoShouting {
+        fn name(&self) -> &str {
+            "no-shouting"
+        }
+
+        fn check(&self, node: &LintNode) -> Option<String> {
+            (node.name.to_uppercase() == node.name).then(|| "name is all caps".to_string())
+        }
+    }
+
+    #[test]
+    fn reports_broken_rules_by_node() {
+        let mut graph = Graph::default();
+        graph.stage_node("answer".into(), vec!["entrypoint".into()], wrap!(concat));
+        graph.stage_node("search".into(), vec!["entrypoint".into()], wrap!(concat));
+        let inputs = vec!["answer".into(), "search".into(), "search".into()];
+        graph.stage_node("JOIN".into(), inputs, wrap!(concat));
+        graph.stage_node("log".into(), vec!["search".into()], wrap!(concat));
+        graph.tag_node("answer", "llm");
+        graph.tag_node("search", "external");
+        graph.tag_node("search", "llm");
+        graph.configure_node("search", |s| s.timeout = Some(Duration::from_secs(5)));
+
+        let linter = Linter::default()
+            .with_rule(MaxFanOut::new(1), Severity::Warning)
+            .with_rule(NoShouting, Severity::Error);
+        let diagnostics: Vec<String> = graph
+            .lint(&linter)
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            diagnostics,
+            vec![
+                "error[no-shouting] JOIN: name is all caps",
+                "error[timeout-required] answer: is tagged `llm` but has no timeout",
+                "warning[retries-required] search: is tagged `external` but never retries",
+                "warning[max-fan-out] search: feeds 2 nodes, more than 1",
+            ]
+        );
+    }
+}
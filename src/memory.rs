@@ -0,0 +1,67 @@
+use crate::error::RunError;
+use std::cell::Cell;
+use std::collections::HashMap;
+
+/// Approximate accounting of the payload bytes a single run is holding on to. A `Node`s output counts from the moment
+/// it is produced until every consumer has started (or, for the output `Node`, until the run is over).
+pub(crate) struct RunMemory {
+    limit: usize,
+    used: Cell<usize>,
+    producers: HashMap<String, Held>,
+}
+
+/// What `RunMemory` knows about one producing `Node`s value. Consumers subscribing to one of its outputs only get a
+/// part of the value, so the size that is released is the one recorded when it was produced.
+#[derive(Default)]
+struct Held {
+    remaining_consumers: Cell<usize>,
+    bytes: Cell<usize>,
+}
+
+impl RunMemory {
+    /// `consumers` lists every input edge of the run by the name of the producing `Node`.
+    pub(crate) fn new<'a>(limit: usize, consumers: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut producers: HashMap<String, Held> = HashMap::new();
+        for producer in consumers {
+            let count = &producers
+                .entry(producer.to_string())
+                .or_default()
+                .remaining_consumers;
+            count.set(count.get() + 1);
+        }
+        Self {
+            limit,
+            used: Cell::new(0),
+            producers,
+        }
+    }
+
+    pub(crate) fn produced(&self, node: &str, bytes: usize) -> Result<(), RunError> {
+        let Some(held) = self.producers.get(node) else {
+            return Ok(());
+        };
+        let used = self.used.get() + bytes;
+        if used > self.limit {
+            return Err(RunError::MemoryLimit {
+                node: node.to_string(),
+                bytes: used,
+                limit: self.limit,
+            });
+        }
+        self.used.set(used);
+        held.bytes.set(bytes);
+        Ok(())
+    }
+
+    pub(crate) fn consumed(&self, producer: &str) {
+        let Some(held) = self.producers.get(producer) else {
+            return;
+        };
+        let remaining = &held.remaining_consumers;
+        remaining.set(remaining.get().saturating_sub(1));
+        if remaining.get() == 0 {
+            self.used
+                .set(self.used.get().saturating_sub(held.bytes.get()));
+        }
+    }
+}
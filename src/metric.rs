@@ -0,0 +1,155 @@
+use crate::graph::OpFn;
+use futures::Future;
+use std::pin::Pin;
+
+/// The future returned by `Metric::score`.
+pub type ScoreFuture<'a> = Pin<Box<dyn Future<Output = Option<f64>> + 'a>>;
+
+/// A `Metric` scores one output of a graph, given the input that produced it and, when known, the expected output.
+/// Returning `None` means the metric has nothing to say about this case (e.g. `ExactMatch` without an expected value).
+/// A case whose run failed has no output to score, and gets `failure_score` from every `Metric` instead.
+///
+/// The same `Metric` can be handed to an `Evaluator` or staged into a graph with `Graph::stage_metric_node`, so
+/// scoring logic only has to be written once.
+pub trait Metric {
+    fn name(&self) -> &str;
+
+    fn score<'a>(
+        &'a self,
+        input: &'a str,
+        output: &'a str,
+        expected: Option<&'a str>,
+    ) -> ScoreFuture<'a>;
+
+    /// The score of a case whose run failed, `0.0` unless the `Metric` has nothing to say about the case.
+    fn failure_score(&self, _input: &str, _expected: Option<&str>) -> Option<f64> {
+        Some(0.0)
+    }
+}
+
+/// Scores `1.0` when the output equals the expected value and `0.0` otherwise.
+pub struct ExactMatch;
+
+impl Metric for ExactMatch {
+    fn name(&self) -> &str {
+        "exact_match"
+    }
+
+    fn score<'a>(
+        &'a self,
+        _input: &'a str,
+        output: &'a str,
+        expected: Option<&'a str>,
+    ) -> ScoreFuture<'a> {
+        let score = expected.map(|expected| f64::from(u8::from(output == expected)));
+        Box::pin(async move { score })
+    }
+
+    fn failure_score(&self, _input: &str, expected: Option<&str>) -> Option<f64> {
+        expected.map(|_| 0.0)
+    }
+}
+
+/// An LLM-as-judge `Metric`. The `rubric` is a prompt template where `{input}`, `{output}` and `{expected}` are
+/// replaced by the case being scored, in one pass so text of the case that looks like a placeholder is left alone; the
+/// filled in prompt is passed as the only input to `op`. The first number in
+/// the answer is the score.
+pub struct LlmJudge {
+    name: String,
+    op: OpFn,
+    rubric: String,
+}
+
+impl LlmJudge {
+    pub fn new(op: OpFn, rubric: String) -> Self {
+        Self {
+            name: "judge".into(),
+            op,
+            rubric,
+        }
+    }
+
+    pub fn with_name(mut self, name: String) -> Self {
+        self.name = name;
+        self
+    }
+
+    fn prompt(&self, input: &str, output: &str, expected: Option<&str>) -> String {
+        let mut prompt = String::with_capacity(self.rubric.len());
+        let mut rest = self.rubric.as_str();
+        while let Some(start) = rest.find('{') {
+            prompt.push_str(&rest[..start]);
+            rest = &rest[start..];
+            let placeholders = [
+                ("{input}", input),
+                ("{output}", output),
+                ("{expected}", expected.unwrap_or("")),
+            ];
+            match placeholders.iter().find(|(p, _)| rest.starts_with(p)) {
+                Some((placeholder, value)) => {
+                    prompt.push_str(value);
+                    rest = &rest[placeholder.len()..];
+                }
+                None => {
+                    prompt.push('{');
+                    rest = &rest[1..];
+                }
+            }
+        }
+        prompt.push_str(rest);
+        prompt
+    }
+}
+
+impl Metric for LlmJudge {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn score<'a>(
+        &'a self,
+        input: &'a str,
+        output: &'a str,
+        expected: Option<&'a str>,
+    ) -> ScoreFuture<'a> {
+        let answer = (self.op)(vec![self.prompt(input, output, expected)]);
+        Box::pin(async move { first_number(&answer.await) })
+    }
+}
+
+fn first_number(text: &str) -> Option<f64> {
+    text.split(|c: char| !(c.is_ascii_digit() || c == '.' || c == '-'))
+        .find_map(|word| word.parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wrap;
+
+    async fn fake_llm(x: Vec<String>) -> String {
+        if x[0].contains("Answer: 4") {
+            "Score: 0.9 - correct".into()
+        } else {
+            "I'd give this 0".into()
+        }
+    }
+
+    #[tokio::test]
+    async fn llm_judge_fills_rubric_and_parses_score() {
+        let judge = LlmJudge::new(
+            wrap!(fake_llm),
+            "Question: {input}\nAnswer: {output}\nRate 0-1.".into(),
+        );
+        assert_eq!(judge.score("2+2", "4", None).await, Some(0.9));
+        assert_eq!(judge.score("2+2", "5", None).await, Some(0.0));
+        assert_eq!(
+            judge.prompt("{output}", "{expected}", Some("4")),
+            "Question: {output}\nAnswer: {expected}\nRate 0-1."
+        );
+        assert_eq!(judge.failure_score("2+2", None), Some(0.0));
+        assert_eq!(ExactMatch.failure_score("2+2", None), None);
+        assert_eq!(ExactMatch.score("2+2", "4", Some("4")).await, Some(1.0));
+        assert_eq!(ExactMatch.score("2+2", "4", None).await, None);
+    }
+}
@@ -0,0 +1,392 @@
+use crate::cache::CachePolicy;
+use crate::graph::{Graph, Op};
+use crate::id::RunId;
+use crate::policy::{Quota, RateLimit};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+/// A `Middleware` is one layer of resilience policy, such as retries or caching, that a `GraphSpec` can declare for a
+/// `Node` or a tag instead of it being set up in code. `apply` gets every `Node` the declaration covers at once, so
+/// they can share state such as a `RateLimit`. Plain functions and closures taking `(&mut Graph, &[String])` are
+/// `Middleware` too.
+pub trait Middleware {
+    fn apply(&self, graph: &mut Graph, nodes: &[String]);
+}
+
+impl<F: Fn(&mut Graph, &[String])> Middleware for F {
+    fn apply(&self, graph: &mut Graph, nodes: &[String]) {
+        self(graph, nodes)
+    }
+}
+
+/// Builds a `Middleware` from its `config`.
+type MiddlewareFactory = Rc<dyn Fn(&Value) -> Result<Box<dyn Middleware>, String>>;
+
+/// What the `logging` middleware reports each time the `op` of one of its `Node`s finishes: the run it was part of
+/// (`None` if it was called outside of one), how long it took, and the message of the error it failed with, if any.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OpLog {
+    pub run_id: Option<RunId>,
+    pub node: String,
+    pub elapsed: Duration,
+    pub error: Option<String>,
+}
+
+impl fmt::Display for OpLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(run_id) = self.run_id {
+            write!(f, "run {run_id} ")?;
+        }
+        match &self.error {
+            None => write!(f, "node {} finished in {:?}", self.node, self.elapsed),
+            Some(e) => write!(f, "node {} failed after {:?}: {e}", self.node, self.elapsed),
+        }
+    }
+}
+
+/// Where the `logging` middleware sends each `OpLog`, see `MiddlewareRegistry::set_log_sink`.
+type LogSink = Rc<dyn Fn(&OpLog)>;
+
+/// A `MiddlewareRegistry` maps names to the `Middleware` a `GraphSpec` can refer to. `MiddlewareRegistry::default()`
+/// comes with these, each configured with durations in milliseconds:
+///
+/// - `retry`: `{"retries": 2, "timeout_ms": 5000}` sets `NodeSettings::retries`, and the timeout if given.
+/// - `timeout`: `{"timeout_ms": 5000}` sets `NodeSettings::timeout`.
+/// - `cache`: `{"ttl_ms": 60000, "stale_while_revalidate_ms": 0, "capacity": 1000}` caches the `Node` with a
+///   `CachePolicy`; only `ttl_ms` is required.
+/// - `rate_limit`: `{"calls": 10, "per_ms": 1000}` gives the `Node`s one shared `RateLimit`.
+/// - `quota`: `{"runs": 10, "per_ms": 3600000, "fail_fast": false}` gives the `Node`s one shared `Quota`.
+/// - `logging`: takes no config, and reports an `OpLog` whenever the `op` of one of the `Node`s finishes, written to
+///   stderr unless another sink is set with `set_log_sink`.
+#[derive(Clone)]
+pub struct MiddlewareRegistry {
+    factories: HashMap<String, MiddlewareFactory>,
+}
+
+impl MiddlewareRegistry {
+    /// A `MiddlewareRegistry` without any middleware.
+    pub fn new() -> Self {
+        Self {
+            factories: HashMap::new(),
+        }
+    }
+
+    /// `register` adds the middleware `build` makes from its `config` (or from `{}` when the spec gives none) under
+    /// `name`, replacing whatever was registered under that name before. `build` fails with a message for a config
+    /// it can't use.
+    pub fn register<M: Middleware + 'static>(
+        &mut self,
+        name: &str,
+        build: impl Fn(&Value) -> Result<M, String> + 'static,
+    ) {
+        let factory: MiddlewareFactory = Rc::new(move |config: &Value| {
+            build(config).map(|middleware| Box::new(middleware) as Box<dyn Middleware>)
+        });
+        self.factories.insert(name.to_string(), factory);
+    }
+
+    /// `set_log_sink` makes the `logging` middleware built from now on send its `OpLog`s to `sink`, e.g. to hand
+    /// them to the application's logger.
+    pub fn set_log_sink(&mut self, sink: impl Fn(&OpLog) + 'static) {
+        self.register_logging(Rc::new(sink));
+    }
+
+    fn register_logging(&mut self, sink: LogSink) {
+        self.register("logging", move |config: &Value| {
+            fields(config, &[])?;
+            let sink = sink.clone();
+            Ok(move |graph: &mut Graph, nodes: &[String]| {
+                for node in nodes {
+                    let (name, sink) = (node.clone(), sink.clone());
+                    graph.wrap_op(node, move |op| logged(name.clone(), sink.clone(), op));
+                }
+            })
+        });
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.factories.contains_key(name)
+    }
+
+    /// Builds the middleware registered as `name`, failing with a message if `config` doesn't suit it.
+    pub(crate) fn build(
+        &self,
+        name: &str,
+        config: Option<&Value>,
+    ) -> Option<Result<Box<dyn Middleware>, String>> {
+        let factory = self.factories.get(name)?;
+        let empty = Value::Object(Map::new());
+        Some(factory(config.unwrap_or(&empty)))
+    }
+}
+
+impl Default for MiddlewareRegistry {
+    fn default() -> Self {
+        let mut registry = Self::new();
+        registry.register("retry", |config: &Value| {
+            let config = fields(config, &["retries", "timeout_ms"])?;
+            let retries = required(config, "retries")?;
+            let retries = u32::try_from(retries).map_err(|_| "`retries` is too large")?;
+            let timeout = millis(config, "timeout_ms")?;
+            Ok(move |graph: &mut Graph, nodes: &[String]| {
+                for node in nodes {
+                    graph.configure_node(node, |settings| {
+                        settings.retries = retries;
+                        if timeout.is_some() {
+                            settings.timeout = timeout;
+                        }
+                    });
+                }
+            })
+        });
+        registry.register("timeout", |config: &Value| {
+            let config = fields(config, &["timeout_ms"])?;
+            let timeout = Duration::from_millis(required(config, "timeout_ms")?);
+            Ok(move |graph: &mut Graph, nodes: &[String]| {
+                for node in nodes {
+                    graph.configure_node(node, |settings| settings.timeout = Some(timeout));
+                }
+            })
+        });
+        registry.register("cache", |config: &Value| {
+            let config = fields(config, &["ttl_ms", "stale_while_revalidate_ms", "capacity"])?;
+            let mut policy = CachePolicy::new(Duration::from_millis(required(config, "ttl_ms")?));
+            if let Some(window) = millis(config, "stale_while_revalidate_ms")? {
+                policy = policy.with_stale_while_revalidate(window);
+            }
+            if let Some(capacity) = number(config, "capacity")? {
+                policy = policy.with_capacity(usize::try_from(capacity).unwrap_or(usize::MAX));
+            }
+            Ok(move |graph: &mut Graph, nodes: &[String]| {
+                for node in nodes {
+                    graph.cache_node(node, policy);
+                }
+            })
+        });
+        registry.register("rate_limit", |config: &Value| {
+            let config = fields(config, &["calls", "per_ms"])?;
+            let calls = usize::try_from(required(config, "calls")?).unwrap_or(usize::MAX);
+            let per = Duration::from_millis(required(config, "per_ms")?);
+            Ok(move |graph: &mut Graph, nodes: &[String]| {
+                let limit = RateLimit::new(calls, per);
+                for node in nodes {
+                    graph
+                        .configure_node(node, |settings| settings.rate_limit = Some(limit.clone()));
+                }
+            })
+        });
+        registry.register("quota", |config: &Value| {
+            let config = fields(config, &["runs", "per_ms", "fail_fast"])?;
+            let runs = usize::try_from(required(config, "runs")?).unwrap_or(usize::MAX);
+            let per = Duration::from_millis(required(config, "per_ms")?);
+            let fail_fast = match config.get("fail_fast") {
+                None => false,
+                Some(Value::Bool(fail_fast)) => *fail_fast,
+                Some(_) => return Err("`fail_fast` must be a boolean".to_string()),
+            };
+            Ok(move |graph: &mut Graph, nodes: &[String]| {
+                let mut quota = Quota::new(runs, per);
+                if fail_fast {
+                    quota = quota.fail_fast();
+                }
+                for node in nodes {
+                    graph.configure_node(node, |settings| settings.quota = Some(quota.clone()));
+                }
+            })
+        });
+        registry.register_logging(Rc::new(|log| eprintln!("{log}")));
+        registry
+    }
+}
+
+fn logged(node: String, sink: LogSink, op: Op) -> Op {
+    Rc::new(move |inputs: Vec<String>| {
+        let (node, sink) = (node.clone(), sink.clone());
+        let value = op(inputs);
+        Box::pin(async move {
+            let started = Instant::now();
+            let result = value.await;
+            sink(&OpLog {
+                run_id: RunId::current(),
+                node,
+                elapsed: started.elapsed(),
+                error: result.as_ref().err().map(ToString::to_string),
+            });
+            result
+        })
+    })
+}
+
+/// The fields of a built-in middleware's `config`, which may only be `allowed` ones.
+fn fields<'a>(config: &'a Value, allowed: &[&str]) -> Result<&'a Map<String, Value>, String> {
+    let Value::Object(fields) = config else {
+        return Err("expected an object".to_string());
+    };
+    if let Some(key) = fields.keys().find(|key| !allowed.contains(&key.as_str())) {
+        return Err(match allowed {
+            [] => format!("unknown field `{key}`, there are no fields"),
+            _ => format!(
+                "unknown field `{key}`, expected one of: {}",
+                allowed.join(", ")
+            ),
+        });
+    }
+    Ok(fields)
+}
+
+fn number(fields: &Map<String, Value>, key: &str) -> Result<Option<u64>, String> {
+    match fields.get(key) {
+        None => Ok(None),
+        Some(value) => value
+            .as_u64()
+            .map(Some)
+            .ok_or_else(|| format!("`{key}` must be a whole number")),
+    }
+}
+
+fn required(fields: &Map<String, Value>, key: &str) -> Result<u64, String> {
+    number(fields, key)?.ok_or_else(|| format!("missing field `{key}`"))
+}
+
+fn millis(fields: &Map<String, Value>, key: &str) -> Result<Option<Duration>, String> {
+    Ok(number(fields, key)?.map(Duration::from_millis))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::RunError;
+    use crate::options::RunOptions;
+    use crate::registry::OpRegistry;
+    use crate::spec::GraphSpec;
+    use crate::trace::Source;
+    use crate::wrap;
+
+    async fn concat(x: Vec<String>) -> String {
+        x.concat()
+    }
+
+    async fn hang(_: Vec<String>) -> String {
+        futures::future::pending().await
+    }
+
+    #[tokio::test]
+    async fn applies_middleware_declared_per_tag_and_per_node() {
+        let mut ops = OpRegistry::default();
+        ops.register("concat", wrap!(concat));
+        ops.register("hang", wrap!(hang));
+        let mut middleware = MiddlewareRegistry::default();
+        middleware.register("disable", |config: &Value| {
+            let output = config["output"].as_str().unwrap_or_default().to_string();
+            Ok(move |graph: &mut Graph, nodes: &[String]| {
+                for node in nodes {
+                    graph.configure_node(node, |s| {
+                        s.enabled = false;
+                        s.disabled_output = output.clone();
+                    });
+                }
+            })
+        });
+        let spec = GraphSpec::from_json(
+            r#"{
+                "nodes": [
+                    {"name": "A", "inputs": ["entrypoint"], "op": "concat",
+                     "middleware": [{"name": "cache", "config": {"ttl_ms": 60000}}, {"name": "logging"}]},
+                    {"name": "B", "inputs": ["A"], "op": "hang", "tags": ["llm"]},
+                    {"name": "C", "inputs": ["A"], "op": "hang", "tags": ["beta"]}
+                ],
+                "middleware": {
+                    "beta": [{"name": "disable", "config": {"output": "off"}}],
+                    "llm": [{"name": "retry", "config": {"retries": 1, "timeout_ms": 5}}]
+                }
+            }"#,
+        )
+        .unwrap();
+        let graph = spec.build_with_middleware(&ops, &middleware).unwrap();
+        assert_eq!(graph.topology().unwrap(), spec);
+
+        let (_, first) = graph
+            .run_traced("x".into(), "B".into(), &RunOptions::default())
+            .await;
+        let c = first.node("C").unwrap();
+        assert_eq!(
+            (c.source, c.output.clone()),
+            (Source::Disabled, Ok(Some("off".into())))
+        );
+        let (error, second) = graph
+            .run_traced("x".into(), "B".into(), &RunOptions::default())
+            .await;
+        assert_eq!(second.node("A").unwrap().source, Source::Cache);
+        assert_eq!(
+            error.unwrap_err().downcast_ref::<RunError>(),
+            Some(&RunError::Timeout {
+                node: "B".into(),
+                attempts: 2
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn logs_with_the_run_id_to_the_sink() {
+        let mut ops = OpRegistry::default();
+        ops.register("concat", wrap!(concat));
+        let logs = Rc::new(std::cell::RefCell::new(vec![]));
+        let mut middleware = MiddlewareRegistry::default();
+        let sink = logs.clone();
+        middleware.set_log_sink(move |log: &OpLog| sink.borrow_mut().push(log.clone()));
+        let spec = GraphSpec::from_json(
+            r#"{"nodes": [{"name": "A", "inputs": ["entrypoint"], "op": "concat",
+                           "middleware": [{"name": "logging"}]}]}"#,
+        )
+        .unwrap();
+        let graph = spec.build_with_middleware(&ops, &middleware).unwrap();
+
+        let run_id: RunId = "01ARZ3NDEKTSV4RRFFQ69G5FAV".parse().unwrap();
+        let options = RunOptions::default().with_run_id(run_id);
+        let output = graph
+            .run_with_options("x".into(), "A".into(), &options)
+            .await;
+        assert_eq!(output.unwrap(), "x");
+        let logs = logs.borrow();
+        assert_eq!(logs.len(), 1);
+        assert_eq!(
+            (logs[0].run_id, logs[0].error.as_ref()),
+            (Some(run_id), None)
+        );
+        assert!(logs[0]
+            .to_string()
+            .starts_with("run 01ARZ3NDEKTSV4RRFFQ69G5FAV node A finished in"));
+    }
+
+    #[test]
+    fn reports_unknown_middleware_and_bad_config() {
+        let mut ops = OpRegistry::default();
+        ops.register("concat", wrap!(concat));
+        let spec = GraphSpec::from_json(
+            r#"{
+                "nodes": [{"name": "A", "inputs": ["entrypoint"], "op": "concat",
+                           "middleware": [{"name": "cache", "config": {"ttl": 5}}]}],
+                "middleware": {"llm": [{"name": "retry"}, {"name": "circuit_breaker"}]}
+            }"#,
+        )
+        .unwrap();
+        let errors: Vec<String> = spec
+            .build(&ops)
+            .err()
+            .unwrap()
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                "/middleware/llm/0/config: missing field `retries`",
+                "/middleware/llm/1/name: no middleware is registered as `circuit_breaker`",
+                "/nodes/0/middleware/0/config: unknown field `ttl`, expected one of: ttl_ms, stale_while_revalidate_ms, capacity",
+            ]
+        );
+    }
+}
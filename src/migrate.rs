@@ -0,0 +1,283 @@
+use crate::graph::{split_input, Graph};
+use crate::spec::{GraphSpec, SpecError};
+use crate::trace::RunTrace;
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+/// The semantic version of a `Node`, set with `Graph::set_node_version` and written as `major.minor.patch`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Version {
+    pub major: u64,
+    pub minor: u64,
+    pub patch: u64,
+}
+
+impl Version {
+    pub fn new(major: u64, minor: u64, patch: u64) -> Self {
+        Self {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    /// Whether a `Node` at this version can take over data saved by `saved`: it may not be older, and may only move
+    /// past `saved` in ways semantic versioning calls compatible (same major version, or the same minor version
+    /// while the major version is 0).
+    pub fn accepts(&self, saved: &Version) -> bool {
+        let same_line = match self.major {
+            0 => saved.major == 0 && self.minor == saved.minor,
+            major => major == saved.major,
+        };
+        same_line && self >= saved
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// A string that isn't a `major.minor.patch` version.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseVersionError {
+    pub input: String,
+}
+
+impl fmt::Display for ParseVersionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "`{}` is not a version like 1.2.0", self.input)
+    }
+}
+
+impl Error for ParseVersionError {}
+
+impl FromStr for Version {
+    type Err = ParseVersionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || ParseVersionError {
+            input: s.to_string(),
+        };
+        let parts: Vec<u64> = s
+            .split('.')
+            .map(|part| {
+                let digits = !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit());
+                digits
+                    .then(|| part.parse().ok())
+                    .flatten()
+                    .ok_or_else(error)
+            })
+            .collect::<Result<_, _>>()?;
+        match parts[..] {
+            [major, minor, patch] => Ok(Self::new(major, minor, patch)),
+            _ => Err(error()),
+        }
+    }
+}
+
+/// A `Migration` maps the `Node` names of saved data, such as a `GraphSpec` or a `RunTrace` written before a
+/// refactor, onto the names the current `Graph` uses. Rules apply in the order they were added, so a `Node` renamed
+/// twice is mapped through both.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Migration {
+    rules: Vec<Rule>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Rule {
+    from: String,
+    below: Option<Version>,
+    to: String,
+}
+
+impl Migration {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Maps the `Node` saved as `from` onto `to`, whatever version it was saved at.
+    pub fn rename(mut self, from: &str, to: &str) -> Self {
+        self.rules.push(Rule {
+            from: from.to_string(),
+            below: None,
+            to: to.to_string(),
+        });
+        self
+    }
+
+    /// Maps the `Node` saved as `from` onto `to` only if it was saved at a version below `below`, or without a
+    /// version at all. This is for a `Node` whose old behaviour moved elsewhere when it was bumped to `below`.
+    pub fn rename_below(mut self, from: &str, below: Version, to: &str) -> Self {
+        self.rules.push(Rule {
+            from: from.to_string(),
+            below: Some(below),
+            to: to.to_string(),
+        });
+        self
+    }
+
+    /// What a `Node` saved as `name` at `version` is called now.
+    pub fn resolve(&self, name: &str, version: Option<&Version>) -> String {
+        let mut name = name.to_string();
+        for rule in &self.rules {
+            let applies = match (&rule.below, version) {
+                (Some(below), Some(version)) => version < below,
+                _ => true,
+            };
+            if applies && rule.from == name {
+                name = rule.to.clone();
+            }
+        }
+        name
+    }
+
+    /// `migrate_spec` renames the `Node`s of `saved`, and the inputs that refer to them, onto `current`. It fails for
+    /// every `Node` that `current` doesn't have, or whose version there doesn't `accept` the saved one. The migrated
+    /// spec carries the versions of `current`.
+    pub fn migrate_spec(
+        &self,
+        saved: &GraphSpec,
+        current: &Graph,
+    ) -> Result<GraphSpec, Vec<SpecError>> {
+        let resolved: Vec<String> = saved
+            .nodes
+            .iter()
+            .map(|node| self.resolve(&node.name, node.version.as_ref()))
+            .collect();
+        let position = |name: &str| saved.nodes.iter().position(|n| n.name == name);
+        let rename = |input: &String| {
+            let (producer, key) = split_input(input, |name| position(name).is_some());
+            match (position(producer), key) {
+                (Some(i), Some(key)) => format!("{}.{key}", resolved[i]),
+                (Some(i), None) => resolved[i].clone(),
+                (None, _) => input.clone(),
+            }
+        };
+        let mut errors = vec![];
+        let mut migrated = GraphSpec {
+            middleware: saved.middleware.clone(),
+            ..GraphSpec::default()
+        };
+        for (i, (node, name)) in saved.nodes.iter().zip(&resolved).enumerate() {
+            if !current.contains_node(name) {
+                errors.push(SpecError::new(
+                    format!("/nodes/{i}/name"),
+                    format!(
+                        "node `{}` maps onto `{name}`, which the graph doesn't have",
+                        node.name
+                    ),
+                ));
+                continue;
+            }
+            let version = current.node_version(name);
+            if let (Some(saved), Some(version)) = (&node.version, &version) {
+                if !version.accepts(saved) {
+                    errors.push(SpecError::new(
+                        format!("/nodes/{i}/version"),
+                        format!("node `{name}` is at {version}, which can't take over {saved}"),
+                    ));
+                }
+            }
+            let mut node = node.clone();
+            node.name = name.clone();
+            node.inputs = node.inputs.iter().map(rename).collect();
+            node.version = version;
+            migrated.nodes.push(node);
+        }
+        if errors.is_empty() {
+            Ok(migrated)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// `migrate_trace` renames the `Node`s of a saved `RunTrace`, using the versions it recorded.
+    pub fn migrate_trace(&self, saved: &RunTrace) -> RunTrace {
+        let rename = |name: &str| self.resolve(name, saved.versions.get(name));
+        let mut trace = saved.clone();
+        trace.output_node = rename(&saved.output_node);
+        for node in &mut trace.nodes {
+            node.node = rename(&node.node);
+        }
+        trace.graph = saved
+            .graph
+            .iter()
+            .map(|(name, inputs)| {
+                let inputs = inputs
+                    .iter()
+                    .map(
+                        |input| match split_input(input, |name| saved.graph.contains_key(name)) {
+                            (producer, Some(key)) => format!("{}.{key}", rename(producer)),
+                            (producer, None) => rename(producer),
+                        },
+                    )
+                    .collect();
+                (rename(name), inputs)
+            })
+            .collect();
+        trace.versions = saved
+            .versions
+            .iter()
+            .map(|(name, version)| (rename(name), *version))
+            .collect();
+        trace
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::OpRegistry;
+    use crate::wrap;
+
+    async fn concat(x: Vec<String>) -> String {
+        x.concat()
+    }
+
+    #[test]
+    fn maps_saved_spec_onto_refactored_graph() {
+        let v = |s: &str| s.parse::<Version>().unwrap();
+        assert!(v("1.4.0").accepts(&v("1.2.9")));
+        assert!(!v("2.0.0").accepts(&v("1.2.9")));
+        assert!(!v("0.3.0").accepts(&v("0.2.0")));
+        assert!("1.2".parse::<Version>().is_err());
+
+        let saved = GraphSpec::from_json(
+            r#"{"nodes": [
+                {"name": "summarize", "inputs": ["entrypoint"], "op": "concat", "version": "1.0.0"},
+                {"name": "answer", "inputs": ["summarize"], "op": "concat", "version": "1.1.0"}
+            ]}"#,
+        )
+        .unwrap();
+        let mut registry = OpRegistry::default();
+        registry.register("concat", wrap!(concat));
+        let mut current = GraphSpec::from_json(
+            r#"{"nodes": [
+                {"name": "summary", "inputs": ["entrypoint"], "op": "concat", "version": "1.2.0"},
+                {"name": "answer", "inputs": ["summary"], "op": "concat", "version": "2.0.0"}
+            ]}"#,
+        )
+        .unwrap()
+        .build(&registry)
+        .unwrap();
+
+        let migration = Migration::new()
+            .rename("summarize", "summary")
+            .rename_below("answer", v("1.0.0"), "legacy_answer");
+        let errors = migration.migrate_spec(&saved, &current).unwrap_err();
+        assert_eq!(
+            errors[0].to_string(),
+            "/nodes/1/version: node `answer` is at 2.0.0, which can't take over 1.1.0"
+        );
+
+        current.set_node_version("answer", v("1.3.0"));
+        let migrated = migration.migrate_spec(&saved, &current).unwrap();
+        assert_eq!(migrated.nodes[0].name, "summary");
+        assert_eq!(migrated.nodes[1].inputs, vec!["summary"]);
+        assert_eq!(migrated.nodes[1].version, Some(v("1.3.0")));
+        assert_eq!(migration.resolve("answer", None), "legacy_answer");
+    }
+}
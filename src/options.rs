@@ -0,0 +1,74 @@
+use crate::control::RunControl;
+use crate::id::RunId;
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// How urgent a run is. When a `Graph` has a `ConcurrencyLimit`, waiting `op`s of higher priority runs get the next
+/// free slot before those of lower priority ones, so background batch jobs can't starve user-facing requests.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    Batch,
+    #[default]
+    Normal,
+    Interactive,
+}
+
+/// `RunOptions` adjust a single call to `Graph::run_with_options` without changing the `Graph` itself.
+#[derive(Clone, Debug, Default)]
+pub struct RunOptions {
+    pub disabled_tags: BTreeSet<String>,
+    pub priority: Priority,
+    /// Who this run is for. Runs of different tenants share a `ConcurrencyLimit` according to
+    /// `Graph::set_tenant_weight`, so one noisy tenant can't monopolize it.
+    pub tenant: Option<String>,
+    /// Lets the caller reach into the run while it is in flight, see `RunControl`.
+    pub control: Option<RunControl>,
+    /// The id for this run, e.g. one passed along by an upstream service. A new one is generated when it is `None`.
+    pub run_id: Option<RunId>,
+    /// Fields to change in the `config` of `Node`s whose `op` is a `StructOp`, by `Node` name, see `overlay_config`.
+    pub config_overlays: BTreeMap<String, Value>,
+}
+
+impl RunOptions {
+    /// Skips every `Node` tagged with `tag` for this run. Skipped `Node`s output their
+    /// `NodeSettings::disabled_output` instead of calling their `op`, so expensive branches can be turned off for
+    /// low-priority traffic.
+    pub fn disable_tag(mut self, tag: &str) -> Self {
+        self.disabled_tags.insert(tag.to_string());
+        self
+    }
+
+    pub fn with_priority(mut self, priority: Priority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    pub fn with_tenant(mut self, tenant: &str) -> Self {
+        self.tenant = Some(tenant.to_string());
+        self
+    }
+
+    pub fn with_control(mut self, control: RunControl) -> Self {
+        self.control = Some(control);
+        self
+    }
+
+    pub fn with_run_id(mut self, run_id: RunId) -> Self {
+        self.run_id = Some(run_id);
+        self
+    }
+
+    /// Runs the `Node` called `node` with the fields of the JSON object `fields` in place of those of its `config`,
+    /// e.g. another model or temperature for one user, without restaging the `Graph`. The `Node` must come from a
+    /// `GraphSpec` and use a `StructOp`, and overlaid executions skip its cache and canary. The run fails with
+    /// `RunError::Overlay` if the overlay can't be applied. Overlaying the same `Node` again merges the fields in.
+    pub fn overlay_config(mut self, node: &str, fields: Value) -> Self {
+        match (self.config_overlays.get_mut(node), fields) {
+            (Some(Value::Object(overlay)), Value::Object(fields)) => overlay.extend(fields),
+            (_, fields) => {
+                self.config_overlays.insert(node.to_string(), fields);
+            }
+        }
+        self
+    }
+}
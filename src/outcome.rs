@@ -0,0 +1,57 @@
+use crate::error::RunError;
+use std::error::Error;
+
+/// How a run of a `Graph` ended, as returned by `Graph::run`. Callers that only care whether there is an output can
+/// turn it into a `Result` with `into_result`, or `unwrap` it like one.
+#[derive(Debug)]
+pub enum RunOutcome {
+    /// Every `Node` that ran succeeded, and the output `Node` produced this value.
+    Complete(String),
+    /// The output `Node` produced `output`, but `Node`s it doesn't depend on failed with `errors`.
+    PartialWithErrors {
+        output: String,
+        errors: Vec<RunError>,
+    },
+    /// The run was stopped with `RunControl::cancel` before the output was known.
+    Cancelled,
+    /// The output `Node` called `node` has no value, because its `op` returned none or an input it requires had none.
+    SkippedOutput { node: String },
+    /// The run failed before the output was known, e.g. because a `Node` the output depends on failed.
+    Failed(RunError),
+}
+
+impl RunOutcome {
+    /// The output of the run, if it has one.
+    pub fn output(&self) -> Option<&str> {
+        match self {
+            Self::Complete(output) | Self::PartialWithErrors { output, .. } => Some(output),
+            _ => None,
+        }
+    }
+
+    /// `into_result` gives the output of the run, failing with the error that stopped it, `RunError::Cancelled` or
+    /// `RunError::NoValue` if there is none. Errors of a partial run are dropped.
+    pub fn into_result(self) -> Result<String, Box<dyn Error>> {
+        match self {
+            Self::Complete(output) | Self::PartialWithErrors { output, .. } => Ok(output),
+            Self::Cancelled => Err(RunError::Cancelled.into()),
+            Self::SkippedOutput { node } => Err(RunError::NoValue { node }.into()),
+            Self::Failed(e) => Err(e.into()),
+        }
+    }
+
+    /// `unwrap` gives the output of the run, and panics if there is none.
+    pub fn unwrap(self) -> String {
+        self.into_result().unwrap_or_else(|e| {
+            panic!("called `RunOutcome::unwrap` on a run without an output: {e}")
+        })
+    }
+
+    /// `unwrap_err` gives the error `into_result` would fail with, and panics if the run has an output.
+    pub fn unwrap_err(self) -> Box<dyn Error> {
+        match self.into_result() {
+            Ok(output) => panic!("called `RunOutcome::unwrap_err` on a run with output {output:?}"),
+            Err(e) => e,
+        }
+    }
+}
@@ -0,0 +1,161 @@
+use regex::Regex;
+use std::ops::Range;
+
+/// One piece of personal data found in a value: where it is, in bytes, and what kind it is (e.g. `EMAIL`).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PiiMatch {
+    pub range: Range<usize>,
+    pub kind: String,
+}
+
+/// A `PiiDetector` finds personal data in a value. `PatternDetector` covers the kinds a regular expression can spot;
+/// a named-entity recognizer for names or addresses can be plugged in by implementing this trait.
+pub trait PiiDetector {
+    fn find(&self, value: &str) -> Vec<PiiMatch>;
+}
+
+/// Finds personal data with regular expressions. `PatternDetector::with_defaults()`, which is also its `Default`,
+/// finds emails, phone numbers, credit card numbers and US social security numbers; `PatternDetector::empty()` finds
+/// only the patterns added to it with `with_pattern`.
+pub struct PatternDetector {
+    patterns: Vec<(String, Regex)>,
+}
+
+impl PatternDetector {
+    pub fn empty() -> Self {
+        Self { patterns: vec![] }
+    }
+
+    pub fn with_defaults() -> Self {
+        Self::empty()
+            .with_pattern("EMAIL", r"[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}")
+            .with_pattern("CARD", r"\b(?:\d[ -]?){12,15}\d\b")
+            .with_pattern("SSN", r"\b\d{3}-\d{2}-\d{4}\b")
+            .with_pattern(
+                "PHONE",
+                r"(?:\+\d{1,3}[ .-]?)?(?:\(\d{3}\)|\b\d{3})[ .-]?\d{3}[ .-]?\d{4}\b",
+            )
+    }
+
+    /// Adds `pattern`, labeling what it matches as `kind`. Panics if `pattern` isn't a valid regular expression.
+    pub fn with_pattern(mut self, kind: &str, pattern: &str) -> Self {
+        let regex = Regex::new(pattern)
+            .unwrap_or_else(|e| panic!("PII pattern for {kind} is invalid: {e}"));
+        self.patterns.push((kind.to_string(), regex));
+        self
+    }
+}
+
+impl Default for PatternDetector {
+    fn default() -> Self {
+        Self::with_defaults()
+    }
+}
+
+impl PiiDetector for PatternDetector {
+    fn find(&self, value: &str) -> Vec<PiiMatch> {
+        self.patterns
+            .iter()
+            .flat_map(|(kind, regex)| {
+                regex.find_iter(value).map(|m| PiiMatch {
+                    range: m.range(),
+                    kind: kind.clone(),
+                })
+            })
+            .collect()
+    }
+}
+
+/// A `Redactor` replaces the personal data its detectors find with a placeholder such as `[EMAIL]`. Installed with
+/// `Graph::set_redactor`, it cleans the inputs of `Node`s tagged `external` (ops that call out to a hosted model or
+/// API) before they run, and everything a `Sampler` writes.
+pub struct Redactor {
+    detectors: Vec<Box<dyn PiiDetector>>,
+    external_tag: String,
+}
+
+impl Redactor {
+    /// Creates a `Redactor` using `PatternDetector::with_defaults()`.
+    pub fn new() -> Self {
+        Self {
+            detectors: vec![Box::new(PatternDetector::with_defaults())],
+            external_tag: "external".into(),
+        }
+    }
+
+    pub fn with_detector(mut self, detector: impl PiiDetector + 'static) -> Self {
+        self.detectors.push(Box::new(detector));
+        self
+    }
+
+    /// Changes the tag that marks the `Node`s whose inputs get redacted.
+    pub fn with_external_tag(mut self, tag: &str) -> Self {
+        self.external_tag = tag.to_string();
+        self
+    }
+
+    pub(crate) fn external_tag(&self) -> &str {
+        &self.external_tag
+    }
+
+    /// Returns `value` with every match replaced. Where matches overlap, the one starting first wins.
+    pub fn redact(&self, value: &str) -> String {
+        let mut matches: Vec<PiiMatch> = self
+            .detectors
+            .iter()
+            .flat_map(|detector| detector.find(value))
+            .collect();
+        matches.sort_by_key(|m| (m.range.start, std::cmp::Reverse(m.range.end)));
+        let mut redacted = String::with_capacity(value.len());
+        let mut at = 0;
+        for m in matches {
+            if m.range.start < at || value.get(m.range.clone()).is_none() {
+                continue;
+            }
+            redacted.push_str(&value[at..m.range.start]);
+            redacted.push_str(&format!("[{}]", m.kind));
+            at = m.range.end;
+        }
+        redacted.push_str(&value[at..]);
+        redacted
+    }
+}
+
+impl Default for Redactor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Names;
+
+    impl PiiDetector for Names {
+        fn find(&self, value: &str) -> Vec<PiiMatch> {
+            value
+                .match_indices("Ada Lovelace")
+                .map(|(start, name)| PiiMatch {
+                    range: start..start + name.len(),
+                    kind: "NAME".into(),
+                })
+                .collect()
+        }
+    }
+
+    #[test]
+    fn redacts_patterns_and_plugged_in_detectors() {
+        let redactor = Redactor::new().with_detector(Names);
+        assert_eq!(
+            redactor.redact("Ada Lovelace <ada@example.com>, (555) 123-4567, SSN 123-45-6789"),
+            "[NAME] <[EMAIL]>, [PHONE], SSN [SSN]"
+        );
+        assert_eq!(PatternDetector::empty().find("ada@example.com"), vec![]);
+        assert_eq!(
+            PatternDetector::default().find("ada@example.com"),
+            PatternDetector::with_defaults().find("ada@example.com")
+        );
+    }
+}
@@ -0,0 +1,644 @@
+use crate::graph::{split_input, Graph};
+use crate::middleware::{Middleware, MiddlewareRegistry};
+use crate::migrate::Version;
+use crate::registry::OpRegistry;
+use crate::spec::{apply_middleware, GraphSpec, MiddlewareSpec, NodeSpec, SpecError};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::error::Error;
+use std::fmt;
+
+const MAGIC: &[u8; 4] = b"IGPL";
+const VERSION: u16 = 3;
+const ENTRYPOINT: u32 = u32::MAX;
+
+/// One step of an `ExecutionPlan`. `inputs` are indices of earlier steps, or `None` for `entrypoint`, and `keys`
+/// holds, for each of them, the key of the output it subscribes to, if any. The rest is kept as in the `NodeSpec`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PlanStep {
+    pub name: String,
+    pub op: String,
+    pub inputs: Vec<Option<usize>>,
+    pub keys: Vec<Option<String>>,
+    pub config: Option<Value>,
+    pub version: Option<Version>,
+    pub tags: BTreeSet<String>,
+    pub middleware: Vec<MiddlewareSpec>,
+}
+
+/// An `ExecutionPlan` is a `GraphSpec` that has already been validated and put in dependency order, in a compact
+/// binary form. Compile it once at build time with `ExecutionPlan::compile` and `to_bytes`; at startup
+/// `from_bytes` and `load` only have to decode it and look up ops and middleware, skipping validation of the spec.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExecutionPlan {
+    pub steps: Vec<PlanStep>,
+    /// The `Middleware` stacks of the spec, by tag.
+    pub middleware: BTreeMap<String, Vec<MiddlewareSpec>>,
+}
+
+/// Why an `ExecutionPlan` could not be decoded or loaded.
+#[derive(Debug, PartialEq, Eq)]
+pub enum PlanError {
+    BadMagic,
+    UnsupportedVersion(u16),
+    Truncated,
+    InvalidUtf8,
+    InvalidJson,
+    /// A step refers to an input that isn't an earlier step.
+    BadInput {
+        step: String,
+        index: u32,
+    },
+    UnknownOp {
+        step: String,
+        op: String,
+    },
+    /// The op of a step can't be built from its config.
+    BadConfig {
+        step: String,
+        message: String,
+    },
+    /// A `Middleware` isn't registered, or can't be built from its config. `at` is `step <name>` or `tag <tag>`.
+    BadMiddleware {
+        at: String,
+        middleware: String,
+        message: String,
+    },
+}
+
+impl fmt::Display for PlanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadMagic => write!(f, "not an execution plan"),
+            Self::UnsupportedVersion(v) => write!(f, "unsupported execution plan version {v}"),
+            Self::Truncated => write!(f, "execution plan is truncated"),
+            Self::InvalidUtf8 => write!(f, "execution plan contains a name that isn't UTF-8"),
+            Self::InvalidJson => write!(f, "execution plan contains a config that isn't JSON"),
+            Self::BadInput { step, index } => {
+                write!(
+                    f,
+                    "step {step} refers to input {index}, which isn't an earlier step"
+                )
+            }
+            Self::UnknownOp { step, op } => {
+                write!(f, "step {step} uses op {op}, which isn't registered")
+            }
+            Self::BadConfig { step, message } => {
+                write!(f, "step {step} has a bad config: {message}")
+            }
+            Self::BadMiddleware {
+                at,
+                middleware,
+                message,
+            } => write!(f, "{at} uses middleware {middleware}: {message}"),
+        }
+    }
+}
+
+impl Error for PlanError {}
+
+impl ExecutionPlan {
+    /// `compile` orders the `Node`s of `spec` so every step comes after its inputs. It fails on the same problems
+    /// `GraphSpec::build` reports (minus unregistered ops and middleware, which are only checked by `load`), and on
+    /// cycles.
+    pub fn compile(spec: &GraphSpec) -> Result<Self, Vec<SpecError>> {
+        let mut by_name = HashMap::new();
+        let mut errors = vec![];
+        for (i, node) in spec.nodes.iter().enumerate() {
+            if by_name.insert(node.name.as_str(), i).is_some() {
+                errors.push(SpecError::new(
+                    format!("/nodes/{i}/name"),
+                    format!("node `{}` is defined more than once", node.name),
+                ));
+            }
+        }
+        for (i, node) in spec.nodes.iter().enumerate() {
+            for (j, input) in node.inputs.iter().enumerate() {
+                let (producer, _) = split_input(input, |name| by_name.contains_key(name));
+                if producer != "entrypoint" && !by_name.contains_key(producer) {
+                    errors.push(SpecError::new(
+                        format!("/nodes/{i}/inputs/{j}"),
+                        format!("no node is named `{input}`"),
+                    ));
+                }
+            }
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        // Kahn's algorithm, emitting a node once all of its inputs have been emitted.
+        let inputs: Vec<Vec<(&str, Option<&str>)>> = spec
+            .nodes
+            .iter()
+            .map(|node| {
+                node.inputs
+                    .iter()
+                    .map(|input| split_input(input, |name| by_name.contains_key(name)))
+                    .collect()
+            })
+            .collect();
+        let mut waiting = vec![0; spec.nodes.len()];
+        let mut consumers = vec![vec![]; spec.nodes.len()];
+        for (i, inputs) in inputs.iter().enumerate() {
+            for (producer, _) in inputs.iter().filter(|(p, _)| *p != "entrypoint") {
+                waiting[i] += 1;
+                consumers[by_name[producer]].push(i);
+            }
+        }
+        let mut ready: VecDeque<usize> =
+            (0..spec.nodes.len()).filter(|&i| waiting[i] == 0).collect();
+        let mut position = vec![None; spec.nodes.len()];
+        let mut steps = vec![];
+        while let Some(i) = ready.pop_front() {
+            let node = &spec.nodes[i];
+            position[i] = Some(steps.len());
+            steps.push(PlanStep {
+                name: node.name.clone(),
+                op: node.op.clone(),
+                inputs: inputs[i]
+                    .iter()
+                    .map(|(producer, _)| by_name.get(producer).and_then(|&p| position[p]))
+                    .collect(),
+                keys: inputs[i]
+                    .iter()
+                    .map(|(_, key)| key.map(str::to_string))
+                    .collect(),
+                config: node.config.clone(),
+                version: node.version,
+                tags: node.tags.clone(),
+                middleware: node.middleware.clone(),
+            });
+            for &consumer in &consumers[i] {
+                waiting[consumer] -= 1;
+                if waiting[consumer] == 0 {
+                    ready.push_back(consumer);
+                }
+            }
+        }
+        if steps.len() < spec.nodes.len() {
+            return Err(cycles(spec, &position, &consumers));
+        }
+        Ok(Self {
+            steps,
+            middleware: spec.middleware.clone(),
+        })
+    }
+
+    /// Encodes the plan. All integers are little endian, and strings are prefixed with their length as a `u32`. Each
+    /// input is followed by a byte saying whether it subscribes to an output, and if so that output's key. Configs
+    /// are written as JSON strings, after a byte saying whether there is one, and the tag stacks follow the steps.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(VERSION.to_le_bytes());
+        put_u32(&mut bytes, self.steps.len());
+        for step in &self.steps {
+            put_str(&mut bytes, &step.name);
+            put_str(&mut bytes, &step.op);
+            put_u32(&mut bytes, step.inputs.len());
+            for (input, key) in step.inputs.iter().zip(&step.keys) {
+                let index = input.map_or(ENTRYPOINT, |i| i as u32);
+                bytes.extend(index.to_le_bytes());
+                match key {
+                    Some(key) => {
+                        bytes.push(1);
+                        put_str(&mut bytes, key);
+                    }
+                    None => bytes.push(0),
+                }
+            }
+            put_json(&mut bytes, step.config.as_ref());
+            match step.version {
+                Some(version) => {
+                    bytes.push(1);
+                    for n in [version.major, version.minor, version.patch] {
+                        bytes.extend(n.to_le_bytes());
+                    }
+                }
+                None => bytes.push(0),
+            }
+            put_u32(&mut bytes, step.tags.len());
+            for tag in &step.tags {
+                put_str(&mut bytes, tag);
+            }
+            put_stack(&mut bytes, &step.middleware);
+        }
+        put_u32(&mut bytes, self.middleware.len());
+        for (tag, stack) in &self.middleware {
+            put_str(&mut bytes, tag);
+            put_stack(&mut bytes, stack);
+        }
+        bytes
+    }
+
+    /// Decodes a plan encoded by `to_bytes`, or by an earlier version: the first has no output keys, and neither has
+    /// configs, versions, tags or middleware.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, PlanError> {
+        let mut reader = Reader { bytes };
+        if reader.take(4)? != MAGIC {
+            return Err(PlanError::BadMagic);
+        }
+        let version = u16::from_le_bytes(reader.take(2)?.try_into().expect("took 2 bytes"));
+        if !(1..=VERSION).contains(&version) {
+            return Err(PlanError::UnsupportedVersion(version));
+        }
+        let count = reader.u32()? as usize;
+        let mut steps: Vec<PlanStep> = vec![];
+        for _ in 0..count {
+            let name = reader.str()?;
+            let op = reader.str()?;
+            let input_count = reader.u32()? as usize;
+            let (mut inputs, mut keys) = (vec![], vec![]);
+            for _ in 0..input_count {
+                match reader.u32()? {
+                    ENTRYPOINT => inputs.push(None),
+                    index if (index as usize) < steps.len() => inputs.push(Some(index as usize)),
+                    index => return Err(PlanError::BadInput { step: name, index }),
+                }
+                let keyed = version > 1 && reader.take(1)? == [1];
+                keys.push(if keyed { Some(reader.str()?) } else { None });
+            }
+            let mut step = PlanStep {
+                name,
+                op,
+                inputs,
+                keys,
+                config: None,
+                version: None,
+                tags: BTreeSet::new(),
+                middleware: vec![],
+            };
+            if version > 2 {
+                step.config = reader.json()?;
+                if reader.take(1)? == [1] {
+                    let [major, minor, patch] = [reader.u64()?, reader.u64()?, reader.u64()?];
+                    step.version = Some(Version::new(major, minor, patch));
+                }
+                for _ in 0..reader.u32()? {
+                    step.tags.insert(reader.str()?);
+                }
+                step.middleware = reader.stack()?;
+            }
+            steps.push(step);
+        }
+        let mut middleware = BTreeMap::new();
+        if version > 2 {
+            for _ in 0..reader.u32()? {
+                middleware.insert(reader.str()?, reader.stack()?);
+            }
+        }
+        Ok(Self { steps, middleware })
+    }
+
+    /// `load` stages every step into a new `Graph`, looking up ops in `registry` and middleware in
+    /// `MiddlewareRegistry::default()`, the same as `GraphSpec::build` would.
+    pub fn load(&self, registry: &OpRegistry) -> Result<Graph, PlanError> {
+        self.load_with_middleware(registry, &MiddlewareRegistry::default())
+    }
+
+    /// `load` with the middleware registered in `middleware`, as `GraphSpec::build_with_middleware`.
+    pub fn load_with_middleware(
+        &self,
+        registry: &OpRegistry,
+        middleware: &MiddlewareRegistry,
+    ) -> Result<Graph, PlanError> {
+        let nodes: Vec<NodeSpec> = self
+            .steps
+            .iter()
+            .map(|step| NodeSpec {
+                name: step.name.clone(),
+                inputs: step
+                    .inputs
+                    .iter()
+                    .zip(&step.keys)
+                    .map(|(input, key)| {
+                        let producer = match input {
+                            Some(i) => self.steps[*i].name.as_str(),
+                            None => "entrypoint",
+                        };
+                        match key {
+                            Some(key) => format!("{producer}.{key}"),
+                            None => producer.to_string(),
+                        }
+                    })
+                    .collect(),
+                op: step.op.clone(),
+                config: step.config.clone(),
+                version: step.version,
+                tags: step.tags.clone(),
+                middleware: step.middleware.clone(),
+            })
+            .collect();
+        let mut ops = vec![];
+        for node in &nodes {
+            ops.push(match registry.build(&node.op, node.config.as_ref()) {
+                Some(Ok(op)) => op,
+                Some(Err(message)) => {
+                    return Err(PlanError::BadConfig {
+                        step: node.name.clone(),
+                        message,
+                    })
+                }
+                None => {
+                    return Err(PlanError::UnknownOp {
+                        step: node.name.clone(),
+                        op: node.op.clone(),
+                    })
+                }
+            });
+        }
+        let tag_stacks = self
+            .middleware
+            .iter()
+            .map(|(tag, stack)| build_stack(middleware, stack, &format!("tag {tag}")))
+            .collect::<Result<_, _>>()?;
+        let node_stacks = nodes
+            .iter()
+            .map(|node| build_stack(middleware, &node.middleware, &format!("step {}", node.name)))
+            .collect::<Result<_, _>>()?;
+
+        let mut graph = Graph::default();
+        for (node, op) in nodes.iter().zip(ops) {
+            node.stage(&mut graph, registry, op);
+        }
+        apply_middleware(
+            &mut graph,
+            &self.middleware,
+            tag_stacks,
+            &nodes,
+            node_stacks,
+        );
+        Ok(graph)
+    }
+}
+
+/// The errors for the `Node`s `compile` couldn't order: those on a cycle, found by also peeling off, from the
+/// other end, every `Node` left over only because it is downstream of one.
+fn cycles(
+    spec: &GraphSpec,
+    position: &[Option<usize>],
+    consumers: &[Vec<usize>],
+) -> Vec<SpecError> {
+    let mut left: Vec<bool> = position.iter().map(Option::is_none).collect();
+    let mut feeding: Vec<usize> = consumers
+        .iter()
+        .map(|consumers| consumers.iter().filter(|&&c| left[c]).count())
+        .collect();
+    let mut producers = vec![vec![]; consumers.len()];
+    for (i, consumers) in consumers.iter().enumerate() {
+        for &consumer in consumers {
+            producers[consumer].push(i);
+        }
+    }
+    let mut sinks: Vec<usize> = (0..left.len())
+        .filter(|&i| left[i] && feeding[i] == 0)
+        .collect();
+    while let Some(i) = sinks.pop() {
+        left[i] = false;
+        for &producer in &producers[i] {
+            if left[producer] {
+                feeding[producer] -= 1;
+                if feeding[producer] == 0 {
+                    sinks.push(producer);
+                }
+            }
+        }
+    }
+    (0..left.len())
+        .filter(|&i| left[i])
+        .map(|i| {
+            SpecError::new(
+                format!("/nodes/{i}"),
+                format!("node `{}` depends on itself", spec.nodes[i].name),
+            )
+        })
+        .collect()
+}
+
+fn build_stack(
+    registry: &MiddlewareRegistry,
+    stack: &[MiddlewareSpec],
+    at: &str,
+) -> Result<Vec<Box<dyn Middleware>>, PlanError> {
+    let failed = |middleware: &MiddlewareSpec, message: String| PlanError::BadMiddleware {
+        at: at.to_string(),
+        middleware: middleware.name.clone(),
+        message,
+    };
+    stack
+        .iter()
+        .map(
+            |spec| match registry.build(&spec.name, spec.config.as_ref()) {
+                Some(Ok(layer)) => Ok(layer),
+                Some(Err(message)) => Err(failed(spec, message)),
+                None => Err(failed(spec, "it isn't registered".to_string())),
+            },
+        )
+        .collect()
+}
+
+fn put_u32(bytes: &mut Vec<u8>, n: usize) {
+    let n = u32::try_from(n).expect("execution plans are limited to u32::MAX items");
+    bytes.extend(n.to_le_bytes());
+}
+
+fn put_str(bytes: &mut Vec<u8>, s: &str) {
+    put_u32(bytes, s.len());
+    bytes.extend(s.as_bytes());
+}
+
+fn put_json(bytes: &mut Vec<u8>, value: Option<&Value>) {
+    match value {
+        Some(value) => {
+            bytes.push(1);
+            put_str(bytes, &value.to_string());
+        }
+        None => bytes.push(0),
+    }
+}
+
+fn put_stack(bytes: &mut Vec<u8>, stack: &[MiddlewareSpec]) {
+    put_u32(bytes, stack.len());
+    for middleware in stack {
+        put_str(bytes, &middleware.name);
+        put_json(bytes, middleware.config.as_ref());
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], PlanError> {
+        if self.bytes.len() < n {
+            return Err(PlanError::Truncated);
+        }
+        let (taken, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u32(&mut self) -> Result<u32, PlanError> {
+        Ok(u32::from_le_bytes(
+            self.take(4)?.try_into().expect("took 4 bytes"),
+        ))
+    }
+
+    fn u64(&mut self) -> Result<u64, PlanError> {
+        Ok(u64::from_le_bytes(
+            self.take(8)?.try_into().expect("took 8 bytes"),
+        ))
+    }
+
+    fn str(&mut self) -> Result<String, PlanError> {
+        let len = self.u32()? as usize;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|_| PlanError::InvalidUtf8)
+    }
+
+    fn json(&mut self) -> Result<Option<Value>, PlanError> {
+        if self.take(1)? != [1] {
+            return Ok(None);
+        }
+        serde_json::from_str(&self.str()?)
+            .map(Some)
+            .map_err(|_| PlanError::InvalidJson)
+    }
+
+    fn stack(&mut self) -> Result<Vec<MiddlewareSpec>, PlanError> {
+        (0..self.u32()?)
+            .map(|_| {
+                Ok(MiddlewareSpec {
+                    name: self.str()?,
+                    config: self.json()?,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wrap;
+
+    async fn concat(x: Vec<String>) -> String {
+        x.concat()
+    }
+
+    #[tokio::test]
+    async fn round_trips_and_runs() {
+        let spec = GraphSpec::from_json(
+            r#"{"nodes": [
+                {"name": "C", "inputs": ["A.x", "B"], "op": "concat"},
+                {"name": "A", "inputs": ["entrypoint"], "op": "concat"},
+                {"name": "B", "inputs": ["entrypoint"], "op": "concat"}
+            ]}"#,
+        )
+        .unwrap();
+        let plan = ExecutionPlan::compile(&spec).unwrap();
+        let names: Vec<&str> = plan.steps.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, vec!["A", "B", "C"]);
+        assert_eq!(plan.steps[2].keys, vec![Some("x".to_string()), None]);
+
+        let bytes = plan.to_bytes();
+        let decoded = ExecutionPlan::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded, plan);
+        assert_eq!(
+            ExecutionPlan::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(PlanError::Truncated)
+        );
+
+        let mut registry = OpRegistry::default();
+        registry.register("concat", wrap!(concat));
+        let graph = decoded.load(&registry).unwrap();
+        let output = graph.run(r#"{"x":"hubba"}"#.into(), "C".into()).await;
+        assert_eq!(output.unwrap(), r#"hubba{"x":"hubba"}"#.to_string());
+    }
+
+    #[derive(serde::Deserialize, crate::Op)]
+    struct Exclaim {
+        marks: usize,
+    }
+
+    impl Exclaim {
+        async fn call(&self, x: Vec<String>) -> String {
+            x.concat() + &"!".repeat(self.marks)
+        }
+    }
+
+    async fn slow(x: Vec<String>) -> String {
+        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+        x.concat()
+    }
+
+    #[tokio::test]
+    async fn loads_the_graph_the_spec_builds() {
+        let spec = GraphSpec::from_json(
+            r#"{"nodes": [
+                {"name": "A", "inputs": ["entrypoint"], "op": "exclaim", "config": {"marks": 2},
+                 "version": "1.2.0", "tags": ["llm"],
+                 "middleware": [{"name": "cache", "config": {"ttl_ms": 60000}}]},
+                {"name": "B", "inputs": ["A"], "op": "slow",
+                 "middleware": [{"name": "timeout", "config": {"timeout_ms": 5}}]}
+            ], "middleware": {"llm": [{"name": "retry", "config": {"retries": 1}}]}}"#,
+        )
+        .unwrap();
+        let mut registry = OpRegistry::default();
+        registry.register_struct::<Exclaim>();
+        registry.register("slow", wrap!(slow));
+        let plan = ExecutionPlan::compile(&spec).unwrap();
+        let decoded = ExecutionPlan::from_bytes(&plan.to_bytes()).unwrap();
+        assert_eq!(decoded, plan);
+
+        let graph = decoded.load(&registry).unwrap();
+        assert_eq!(
+            graph.topology().unwrap(),
+            spec.build(&registry).unwrap().topology().unwrap()
+        );
+        assert_eq!(graph.topology().unwrap(), spec);
+        let output = graph.run("hi".into(), "A".into()).await;
+        assert_eq!(output.unwrap(), "hi!!");
+        let error = graph.run("hi".into(), "B".into()).await.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(crate::error::RunError::Timeout { .. })
+        ));
+    }
+
+    #[test]
+    fn compiles_long_chains() {
+        let nodes: Vec<String> = (0..100_000)
+            .map(|i| {
+                let input = match i {
+                    0 => "entrypoint".to_string(),
+                    i => format!("n{}", i - 1),
+                };
+                format!(r#"{{"name": "n{i}", "inputs": ["{input}"], "op": "concat"}}"#)
+            })
+            .rev()
+            .collect();
+        let spec = GraphSpec::from_json(&format!(r#"{{"nodes": [{}]}}"#, nodes.join(","))).unwrap();
+        let plan = ExecutionPlan::compile(&spec).unwrap();
+        assert_eq!(plan.steps[99_999].name, "n99999");
+        assert_eq!(plan.steps[99_999].inputs, vec![Some(99_998)]);
+    }
+
+    #[test]
+    fn rejects_cycles() {
+        let spec = GraphSpec::from_json(
+            r#"{"nodes": [
+                {"name": "A", "inputs": ["B"], "op": "concat"},
+                {"name": "B", "inputs": ["A"], "op": "concat"},
+                {"name": "C", "inputs": ["B"], "op": "concat"}
+            ]}"#,
+        )
+        .unwrap();
+        let errors = ExecutionPlan::compile(&spec).unwrap_err();
+        let messages: Vec<&str> = errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec!["node `A` depends on itself", "node `B` depends on itself"]
+        );
+    }
+}
@@ -0,0 +1,196 @@
+use crate::cache::CachePolicy;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+/// `NodeSettings` control how a `Node`s `op` is executed. They can be changed for one `Node` with
+/// `Graph::configure_node`, or for every `Node` carrying a tag with `Graph::configure_group`.
+///
+/// - `timeout`: how long a single attempt may take before it is abandoned.
+/// - `retries`: how many more attempts are made after one times out.
+/// - `rate_limit`: a shared budget of calls, see `RateLimit`.
+/// - `quota`: a cap on how many times the `Node` runs per window, see `Quota`.
+/// - `enabled`: a disabled `Node` doesn't call its `op` at all and outputs `disabled_output` instead.
+/// - `disabled_output`: what the `Node` outputs when it is disabled, here or for a single run with
+///   `RunOptions::disable_tag`. Empty by default.
+#[derive(Clone, Debug)]
+pub struct NodeSettings {
+    pub timeout: Option<Duration>,
+    pub retries: u32,
+    pub rate_limit: Option<RateLimit>,
+    pub quota: Option<Quota>,
+    pub enabled: bool,
+    pub disabled_output: String,
+}
+
+impl Default for NodeSettings {
+    fn default() -> Self {
+        Self {
+            timeout: None,
+            retries: 0,
+            rate_limit: None,
+            quota: None,
+            enabled: true,
+            disabled_output: String::new(),
+        }
+    }
+}
+
+/// `NodeDefaults` are what every `Node` staged into a `Graph` after `Graph::set_node_defaults` starts out with, so
+/// timeouts, retries, rate limits and caching are set once rather than for every `Node`. A `Node` overrides them with
+/// `Graph::configure_node` or `Graph::cache_node` like any other setting. A `RateLimit` or `Quota` in `settings` is
+/// shared by every `Node` that starts with it, while each `Node` gets a cache of its own.
+#[derive(Clone, Debug, Default)]
+pub struct NodeDefaults {
+    pub settings: NodeSettings,
+    pub cache: Option<CachePolicy>,
+}
+
+/// A `RateLimit` allows at most `calls` `op` calls in any window of length `per`; calls over budget wait until the
+/// window has room. Clones share the same budget, so handing one `RateLimit` to a whole group of `Node`s limits
+/// the group as a whole.
+#[derive(Clone, Debug)]
+pub struct RateLimit {
+    window: Rc<RefCell<Window>>,
+}
+
+#[derive(Debug)]
+struct Window {
+    calls: usize,
+    per: Duration,
+    recent: VecDeque<Instant>,
+}
+
+impl RateLimit {
+    pub fn new(calls: usize, per: Duration) -> Self {
+        Self {
+            window: Rc::new(RefCell::new(Window {
+                calls: calls.max(1),
+                per,
+                recent: VecDeque::new(),
+            })),
+        }
+    }
+
+    /// Waits until a call fits in the budget and counts it.
+    pub(crate) async fn acquire(&self) {
+        loop {
+            let wait = {
+                let mut window = self.window.borrow_mut();
+                let now = Instant::now();
+                while let Some(&oldest) = window.recent.front() {
+                    if now.duration_since(oldest) < window.per {
+                        break;
+                    }
+                    window.recent.pop_front();
+                }
+                if window.recent.len() < window.calls {
+                    window.recent.push_back(now);
+                    return;
+                }
+                window.recent[0] + window.per - now
+            };
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+/// A `Quota` allows at most `runs` executions in each window of length `per`, e.g. an expensive report that may be
+/// generated ten times an hour. Unlike a `RateLimit`, which smooths out bursts of calls, a `Quota` counts whole
+/// executions, retries included only once, and its windows are fixed: the count starts over once `per` has passed
+/// since the window opened. Executions over quota wait for the next window, or with `fail_fast` fail with
+/// `RunError::QuotaExceeded` straight away. Clones share the same count.
+#[derive(Clone, Debug)]
+pub struct Quota {
+    state: Rc<RefCell<QuotaState>>,
+    fail_fast: bool,
+}
+
+#[derive(Debug)]
+struct QuotaState {
+    runs: usize,
+    per: Duration,
+    opened: Option<Instant>,
+    used: usize,
+}
+
+impl Quota {
+    pub fn new(runs: usize, per: Duration) -> Self {
+        Self {
+            state: Rc::new(RefCell::new(QuotaState {
+                runs,
+                per,
+                opened: None,
+                used: 0,
+            })),
+            fail_fast: false,
+        }
+    }
+
+    /// Makes executions over quota fail instead of waiting for the window to reset.
+    pub fn fail_fast(mut self) -> Self {
+        self.fail_fast = true;
+        self
+    }
+
+    /// Counts an execution, waiting for the next window if this one is used up. Fails with how long until the window
+    /// resets if the quota fails fast.
+    pub(crate) async fn acquire(&self) -> Result<(), Duration> {
+        loop {
+            let wait = {
+                let mut state = self.state.borrow_mut();
+                let now = Instant::now();
+                let opened = match state.opened {
+                    Some(opened) if now.duration_since(opened) < state.per => opened,
+                    _ => {
+                        state.opened = Some(now);
+                        state.used = 0;
+                        now
+                    }
+                };
+                if state.used < state.runs {
+                    state.used += 1;
+                    return Ok(());
+                }
+                opened + state.per - now
+            };
+            if self.fail_fast {
+                return Err(wait);
+            }
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn rate_limit_waits_for_window() {
+        let limit = RateLimit::new(2, Duration::from_millis(30));
+        let shared = limit.clone();
+        let started = Instant::now();
+        limit.acquire().await;
+        shared.acquire().await;
+        assert!(started.elapsed() < Duration::from_millis(30));
+        limit.acquire().await;
+        assert!(started.elapsed() >= Duration::from_millis(30));
+    }
+
+    #[tokio::test]
+    async fn quota_resets_with_the_window() {
+        let quota = Quota::new(2, Duration::from_millis(30));
+        let strict = Quota::new(1, Duration::from_secs(60)).fail_fast();
+        let started = Instant::now();
+        assert_eq!(quota.acquire().await, Ok(()));
+        assert_eq!(quota.clone().acquire().await, Ok(()));
+        assert_eq!(quota.acquire().await, Ok(()));
+        assert!(started.elapsed() >= Duration::from_millis(30));
+
+        assert_eq!(strict.acquire().await, Ok(()));
+        let wait = strict.acquire().await.unwrap_err();
+        assert!(wait > Duration::from_secs(59));
+    }
+}
@@ -0,0 +1,130 @@
+use std::cell::RefCell;
+use std::ops::{Deref, DerefMut};
+use std::rc::Rc;
+use tokio::sync::Notify;
+
+/// A `Pool` keeps warm instances of something that is expensive to build, such as an API client or a loaded model,
+/// so a `Node` staged with `Graph::stage_pooled_node` checks one out per execution instead of building its own.
+/// Every instance is built up front; when all of them are checked out, executions wait for one to come back. Clones
+/// of a `Pool` share its instances.
+pub struct Pool<T> {
+    inner: Rc<Inner<T>>,
+}
+
+struct Inner<T> {
+    idle: RefCell<Vec<T>>,
+    returned: Notify,
+}
+
+impl<T> Pool<T> {
+    /// Builds `size` instances with `build`. A `Pool` always has at least one instance.
+    pub fn new(size: usize, build: impl Fn() -> T) -> Self {
+        Self {
+            inner: Rc::new(Inner {
+                idle: RefCell::new((0..size.max(1)).map(|_| build()).collect()),
+                returned: Notify::new(),
+            }),
+        }
+    }
+
+    /// How many instances are waiting to be checked out.
+    pub fn idle(&self) -> usize {
+        self.inner.idle.borrow().len()
+    }
+
+    /// Waits for an idle instance and checks it out. It goes back into the pool when the `Pooled` is dropped.
+    pub async fn checkout(&self) -> Pooled<T> {
+        loop {
+            if let Some(instance) = self.inner.idle.borrow_mut().pop() {
+                return Pooled {
+                    instance: Some(instance),
+                    pool: self.inner.clone(),
+                };
+            }
+            self.inner.returned.notified().await;
+        }
+    }
+}
+
+impl<T> Clone for Pool<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+/// An instance checked out of a `Pool`.
+pub struct Pooled<T> {
+    instance: Option<T>,
+    pool: Rc<Inner<T>>,
+}
+
+impl<T> Deref for Pooled<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.instance
+            .as_ref()
+            .expect("instance is only taken on drop")
+    }
+}
+
+impl<T> DerefMut for Pooled<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.instance
+            .as_mut()
+            .expect("instance is only taken on drop")
+    }
+}
+
+impl<T> Drop for Pooled<T> {
+    fn drop(&mut self) {
+        if let Some(instance) = self.instance.take() {
+            self.pool.idle.borrow_mut().push(instance);
+            self.pool.returned.notify_one();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::Graph;
+    use crate::outcome::RunOutcome;
+    use std::cell::Cell;
+    use std::time::Duration;
+
+    struct Client {
+        calls: usize,
+    }
+
+    async fn call(mut client: Pooled<Client>, x: Vec<String>) -> String {
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        client.calls += 1;
+        x.concat()
+    }
+
+    #[tokio::test]
+    async fn pooled_node_shares_warm_instances_across_runs() {
+        let built = Cell::new(0);
+        let pool = Pool::new(2, || {
+            built.set(built.get() + 1);
+            Client { calls: 0 }
+        });
+        assert_eq!(built.get(), 2);
+
+        let mut graph = Graph::default();
+        graph.stage_pooled_node("A".into(), vec!["entrypoint".into()], pool.clone(), call);
+        let runs = (0..5).map(|i| graph.run(i.to_string(), "A".into()));
+        let outputs = futures::future::join_all(runs).await;
+        assert!(outputs
+            .into_iter()
+            .all(|output| matches!(output, RunOutcome::Complete(_))));
+
+        assert_eq!(pool.idle(), 2);
+        let (a, b) = (pool.checkout().await, pool.checkout().await);
+        assert_eq!(a.calls + b.calls, 5);
+        assert_eq!(pool.idle(), 0);
+    }
+}
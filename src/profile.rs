@@ -0,0 +1,188 @@
+use crate::jsonl;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+/// How much a new latency moves a `Node`s expected latency, so the profile follows an `op` that gets slower or
+/// faster without being thrown off by one outlier.
+const SMOOTHING: f64 = 0.2;
+
+/// What a `LatencyProfile` knows about one `Node`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct LatencyStats {
+    /// A moving average of how long the `Node`s `op` takes.
+    pub expected: Duration,
+    pub samples: u64,
+}
+
+/// A `LatencyProfile` remembers how long each `Node`s `op` has taken across runs. Installed with
+/// `Graph::set_latency_profile`, it is updated after every `op` and used to hand out concurrency slots to the `Node`s
+/// with the longest expected path to the end of the `Graph` first. Save it between processes with `save` and `load`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LatencyProfile {
+    nodes: BTreeMap<String, LatencyStats>,
+}
+
+impl LatencyProfile {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records that the `op` of `node` took `latency`.
+    pub fn record(&mut self, node: &str, latency: Duration) {
+        let stats = self.nodes.entry(node.to_string()).or_default();
+        stats.expected = if stats.samples == 0 {
+            latency
+        } else {
+            stats.expected.mul_f64(1.0 - SMOOTHING) + latency.mul_f64(SMOOTHING)
+        };
+        stats.samples += 1;
+    }
+
+    pub fn stats(&self, node: &str) -> Option<LatencyStats> {
+        self.nodes.get(node).copied()
+    }
+
+    /// The profile as JSON, e.g. `{"A": {"expected_us": 1500, "samples": 3}}`.
+    pub fn to_json(&self) -> Value {
+        let nodes: serde_json::Map<String, Value> = self
+            .nodes
+            .iter()
+            .map(|(name, stats)| {
+                let stats = json!({
+                    "expected_us": stats.expected.as_micros() as u64,
+                    "samples": stats.samples,
+                });
+                (name.clone(), stats)
+            })
+            .collect();
+        Value::Object(nodes)
+    }
+
+    /// Reads a profile written by `to_json`, or returns `None` if `value` isn't one.
+    pub fn from_json(value: &Value) -> Option<Self> {
+        let nodes = value
+            .as_object()?
+            .iter()
+            .map(|(name, stats)| {
+                let stats = LatencyStats {
+                    expected: Duration::from_micros(stats["expected_us"].as_u64()?),
+                    samples: stats["samples"].as_u64()?,
+                };
+                Some((name.clone(), stats))
+            })
+            .collect::<Option<_>>()?;
+        Some(Self { nodes })
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, self.to_json().to_string())
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let value: Value = serde_json::from_str(&fs::read_to_string(path)?)
+            .map_err(|e| jsonl::invalid(e.to_string()))?;
+        Self::from_json(&value).ok_or_else(|| jsonl::invalid("not a latency profile"))
+    }
+
+    /// For each of `nodes`, listed with their inputs, the expected time from when it starts until the slowest chain
+    /// of `Node`s that depends on it has finished, its own `op` included. `Node`s without any samples count as
+    /// instant. `Node`s are worked through in reverse topological order, each once every `Node` reading it is done,
+    /// so deep graphs don't recurse; `Node`s on a cycle, which never run anyway, only count their own `op`.
+    pub(crate) fn critical_paths<'a>(
+        &self,
+        nodes: &[(&'a str, &'a [String])],
+    ) -> HashMap<&'a str, Duration> {
+        let index: HashMap<&str, usize> = nodes
+            .iter()
+            .enumerate()
+            .map(|(i, (name, _))| (*name, i))
+            .collect();
+        let producers: Vec<Vec<usize>> = nodes
+            .iter()
+            .map(|(_, inputs)| {
+                inputs
+                    .iter()
+                    .filter_map(|i| index.get(i.as_str()))
+                    .copied()
+                    .collect()
+            })
+            .collect();
+        // How many edges leave each `Node` towards a consumer whose path isn't known yet.
+        let mut waiting = vec![0; nodes.len()];
+        for producer in producers.iter().flatten() {
+            waiting[*producer] += 1;
+        }
+        let own = |node: usize| {
+            self.stats(nodes[node].0)
+                .map(|s| s.expected)
+                .unwrap_or_default()
+        };
+        let mut downstream = vec![Duration::ZERO; nodes.len()];
+        let mut paths = HashMap::new();
+        let mut ready: Vec<usize> = (0..nodes.len()).filter(|&n| waiting[n] == 0).collect();
+        while let Some(node) = ready.pop() {
+            let path = own(node) + downstream[node];
+            paths.insert(nodes[node].0, path);
+            for &producer in &producers[node] {
+                downstream[producer] = downstream[producer].max(path);
+                waiting[producer] -= 1;
+                if waiting[producer] == 0 {
+                    ready.push(producer);
+                }
+            }
+        }
+        for (node, (name, _)) in nodes.iter().enumerate() {
+            paths.entry(name).or_insert_with(|| own(node));
+        }
+        paths
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_the_longest_path_and_round_trips() {
+        let mut profile = LatencyProfile::new();
+        profile.record("slow", Duration::from_millis(50));
+        profile.record("fast", Duration::from_millis(10));
+        profile.record("join", Duration::from_millis(5));
+        profile.record("tail", Duration::from_millis(5));
+        profile.record("tail", Duration::from_millis(15));
+        let tail = profile.stats("tail").unwrap();
+        assert_eq!(tail.samples, 2);
+        assert!((tail.expected.as_secs_f64() - 0.007).abs() < 1e-6);
+
+        let inputs = [
+            vec!["entrypoint".to_string()],
+            vec!["top".to_string()],
+            vec!["slow".to_string(), "fast".to_string()],
+        ];
+        let nodes = [
+            ("top", &inputs[0][..]),
+            ("slow", &inputs[1][..]),
+            ("fast", &inputs[1][..]),
+            ("join", &inputs[2][..]),
+        ];
+        let paths = profile.critical_paths(&nodes);
+        assert_eq!(paths["slow"], Duration::from_millis(55));
+        assert_eq!(paths["fast"], Duration::from_millis(15));
+        assert_eq!(paths["top"], Duration::from_millis(55));
+
+        let names: Vec<String> = (0..100_000).map(|i| i.to_string()).collect();
+        let chain: Vec<[String; 1]> = names.iter().map(|name| [name.clone()]).collect();
+        let mut nodes = vec![(names[0].as_str(), &inputs[0][..])];
+        nodes.extend((1..names.len()).map(|i| (names[i].as_str(), &chain[i - 1][..])));
+        assert_eq!(profile.critical_paths(&nodes).len(), 100_000);
+
+        let json = profile.to_json();
+        assert_eq!(json["slow"], json!({"expected_us": 50_000, "samples": 1}));
+        let loaded = LatencyProfile::from_json(&json).unwrap();
+        assert_eq!(loaded.stats("slow"), profile.stats("slow"));
+    }
+}
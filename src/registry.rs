@@ -0,0 +1,369 @@
+use crate::error::RunError;
+use crate::graph::{op_from_fn, BoxedFuture, Graph, Op, OpFn};
+use crate::validate::OpSignature;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::cell::Cell;
+use std::collections::{BTreeMap, HashMap};
+use std::rc::Rc;
+
+/// A `StructOp` is an op with configuration: a struct whose fields are read from the `config` of each `Node` of a
+/// `GraphSpec` that uses it. Rather than implementing it by hand, derive it with `#[derive(Op)]` on a struct that
+/// also derives `Deserialize` and has an `async fn call(&self, inputs: Vec<String>) -> String` method:
+/// ```
+/// use inference_graph::registry::OpRegistry;
+/// use inference_graph::spec::GraphSpec;
+/// use inference_graph::Op;
+/// use serde::Deserialize;
+///
+/// #[derive(Deserialize, Op)]
+/// struct Greet {
+///     greeting: String,
+/// }
+///
+/// impl Greet {
+///     async fn call(&self, inputs: Vec<String>) -> String {
+///         format!("{}, {}", self.greeting, inputs.concat())
+///     }
+/// }
+///
+/// # #[tokio::main]
+/// # async fn main() {
+/// let mut registry = OpRegistry::default();
+/// registry.register_struct::<Greet>();
+/// let spec = GraphSpec::from_json(
+///     r#"{"nodes": [{"name": "A", "inputs": ["entrypoint"], "op": "greet", "config": {"greeting": "Hi"}}]}"#,
+/// )
+/// .unwrap();
+/// let output = spec.build(&registry).unwrap().run("Ada".into(), "A".into()).await;
+/// assert_eq!(output.unwrap(), "Hi, Ada");
+/// # }
+/// ```
+pub trait StructOp: DeserializeOwned + 'static {
+    /// The name the op is registered under.
+    const NAME: &'static str;
+
+    fn run(self: Rc<Self>, inputs: Vec<String>) -> BoxedFuture;
+}
+
+/// Builds the op of one `Node` from its `config`.
+pub(crate) type OpFactory = Rc<dyn Fn(&Value) -> Result<Op, String>>;
+
+/// An `OpRegistry` maps names to `OpFn`s so graphs described as data (see `spec::GraphSpec`) can refer to their ops
+/// by name.
+#[derive(Default, Clone)]
+pub struct OpRegistry {
+    ops: HashMap<String, OpFn>,
+    signatures: HashMap<String, OpSignature>,
+    factories: HashMap<String, OpFactory>,
+}
+
+impl OpRegistry {
+    /// `register` adds `op` under `name`, replacing whatever was registered under that name before.
+    pub fn register(&mut self, name: &str, op: OpFn) {
+        self.ops.insert(name.to_string(), op);
+        self.signatures.remove(name);
+        self.factories.remove(name);
+    }
+
+    /// `register_struct` adds the `StructOp` `T` under `T::NAME`. Every `Node` using it gets its own `T`, read from
+    /// the `config` the spec gives that `Node`, or from `{}` when it gives none.
+    pub fn register_struct<T: StructOp>(&mut self) {
+        let factory: OpFactory = Rc::new(|config: &Value| {
+            let op = Rc::new(T::deserialize(config).map_err(|e| e.to_string())?);
+            let op: Op = Rc::new(move |x: Vec<String>| {
+                let value = op.clone().run(x);
+                Box::pin(async move { Ok::<_, RunError>(Some(value.await)) })
+            });
+            Ok(op)
+        });
+        self.ops.remove(T::NAME);
+        self.signatures.remove(T::NAME);
+        self.factories.insert(T::NAME.to_string(), factory);
+    }
+
+    /// Like `register`, also declaring the `OpSignature` of `op`. Graphs built from a spec get it on every `Node`
+    /// using the op, so `Graph::validate` can check their wiring.
+    pub fn register_with_signature(&mut self, name: &str, op: OpFn, signature: OpSignature) {
+        self.register(name, op);
+        self.signatures.insert(name.to_string(), signature);
+    }
+
+    pub fn signature(&self, name: &str) -> Option<&OpSignature> {
+        self.signatures.get(name)
+    }
+
+    pub fn get(&self, name: &str) -> Option<OpFn> {
+        self.ops.get(name).copied()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.ops.contains_key(name) || self.factories.contains_key(name)
+    }
+
+    /// Whether `name` is a `StructOp`, which takes a `config`.
+    pub fn is_configurable(&self, name: &str) -> bool {
+        self.factories.contains_key(name)
+    }
+
+    /// What builds the `StructOp` registered as `name` from a `config`, if it is one.
+    pub(crate) fn factory(&self, name: &str) -> Option<OpFactory> {
+        self.factories.get(name).cloned()
+    }
+
+    /// Builds the op registered as `name` for one `Node`, failing with a message if `config` doesn't suit it.
+    pub(crate) fn build(&self, name: &str, config: Option<&Value>) -> Option<Result<Op, String>> {
+        if let Some(factory) = self.factories.get(name) {
+            let empty = Value::Object(Default::default());
+            return Some(factory(config.unwrap_or(&empty)));
+        }
+        let op = self.get(name)?;
+        Some(match config {
+            None => Ok(op_from_fn(op)),
+            Some(_) => Err(format!("op `{name}` takes no config")),
+        })
+    }
+}
+
+/// A `GraphRegistry` holds several versions of each named `Graph` and decides which one serves the next run, so a
+/// new version can be rolled out next to the old one. `select` hands out an `Rc<Graph>`, so a run that has started
+/// keeps the version it started on even if traffic shifts or that version is retired meanwhile.
+#[derive(Default)]
+pub struct GraphRegistry {
+    graphs: HashMap<String, Deployment>,
+}
+
+struct Deployment {
+    versions: BTreeMap<String, Rc<Graph>>,
+    live: String,
+    /// The version being ramped up, and the percentage of runs it gets.
+    ramp: Option<(String, u8)>,
+    runs: Cell<u64>,
+}
+
+impl GraphRegistry {
+    /// `register` adds `graph` as `version` of `name`, replacing a graph already registered as that version. The first
+    /// version registered under a name goes live straight away; later ones only get traffic once `promote`d or
+    /// `ramp`ed.
+    pub fn register(&mut self, name: &str, version: &str, graph: Graph) {
+        let deployment = self
+            .graphs
+            .entry(name.to_string())
+            .or_insert_with(|| Deployment {
+                versions: BTreeMap::new(),
+                live: version.to_string(),
+                ramp: None,
+                runs: Cell::new(0),
+            });
+        deployment
+            .versions
+            .insert(version.to_string(), Rc::new(graph));
+    }
+
+    /// `promote` sends every run of `name` to `version` from now on, ending any ramp.
+    pub fn promote(&mut self, name: &str, version: &str) {
+        let deployment = self.deployment(name, version);
+        deployment.live = version.to_string();
+        deployment.ramp = None;
+    }
+
+    /// `ramp` sends `percent` of the runs of `name` to `version` and the rest to the live version. Raising the
+    /// percentage step by step and then calling `promote` shifts traffic gradually.
+    pub fn ramp(&mut self, name: &str, version: &str, percent: u8) {
+        let deployment = self.deployment(name, version);
+        deployment.ramp = Some((version.to_string(), percent.min(100)));
+    }
+
+    /// `retire` removes `version` of `name`. Runs already holding it finish normally. The live version can't be
+    /// retired; retiring the ramped version ends the ramp.
+    pub fn retire(&mut self, name: &str, version: &str) {
+        let deployment = self.deployment(name, version);
+        assert!(
+            deployment.live != version,
+            "Version {version} of graph {name} is live and can't be retired"
+        );
+        if deployment.ramp.as_ref().is_some_and(|(v, _)| v == version) {
+            deployment.ramp = None;
+        }
+        deployment.versions.remove(version);
+    }
+
+    /// `versions` lists the versions registered under `name`, in order.
+    pub fn versions(&self, name: &str) -> Vec<String> {
+        self.graphs
+            .get(name)
+            .map(|d| d.versions.keys().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// `select` picks the version that should serve the next run of `name`, returning it with its `Graph`. Ramped
+    /// runs are spread evenly rather than at random, so exactly `percent` of every hundred runs go to the new version.
+    pub fn select(&self, name: &str) -> Option<(String, Rc<Graph>)> {
+        let deployment = self.graphs.get(name)?;
+        let run = deployment.runs.get();
+        deployment.runs.set(run.wrapping_add(1));
+        let version = match &deployment.ramp {
+            Some((version, percent)) if (run * u64::from(*percent)) % 100 < u64::from(*percent) => {
+                version
+            }
+            _ => &deployment.live,
+        };
+        Some((version.clone(), deployment.versions[version].clone()))
+    }
+
+    fn deployment(&mut self, name: &str, version: &str) -> &mut Deployment {
+        let deployment = self
+            .graphs
+            .get_mut(name)
+            .unwrap_or_else(|| panic!("Graph of name {name} does not exist"));
+        assert!(
+            deployment.versions.contains_key(version),
+            "Graph {name} has no version {version}"
+        );
+        deployment
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wrap;
+
+    async fn old(x: Vec<String>) -> String {
+        format!("old {}", x.concat())
+    }
+
+    async fn new(x: Vec<String>) -> String {
+        format!("new {}", x.concat())
+    }
+
+    fn graph(op: OpFn) -> Graph {
+        let mut graph = Graph::default();
+        graph.stage_node("A".into(), vec!["entrypoint".into()], op);
+        graph
+    }
+
+    #[tokio::test]
+    async fn ramps_then_promotes_new_version() {
+        let mut registry = GraphRegistry::default();
+        registry.register("qa", "v1", graph(wrap!(old)));
+        registry.register("qa", "v2", graph(wrap!(new)));
+        assert_eq!(registry.select("qa").unwrap().0, "v1");
+
+        registry.ramp("qa", "v2", 25);
+        let picked: Vec<String> = (0..8).map(|_| registry.select("qa").unwrap().0).collect();
+        assert_eq!(picked.iter().filter(|v| *v == "v2").count(), 2);
+
+        let (_, in_flight) = registry.select("qa").unwrap();
+        registry.promote("qa", "v2");
+        registry.retire("qa", "v1");
+        assert_eq!(registry.versions("qa"), vec!["v2"]);
+
+        let output = in_flight.run("hi".into(), "A".into()).await;
+        assert_eq!(output.unwrap(), "old hi".to_string());
+        let (version, graph) = registry.select("qa").unwrap();
+        assert_eq!(version, "v2");
+        let output = graph.run("hi".into(), "A".into()).await;
+        assert_eq!(output.unwrap(), "new hi".to_string());
+    }
+
+    #[derive(serde::Deserialize, crate::Op)]
+    #[op(name = "repeat")]
+    struct Repeater {
+        times: usize,
+    }
+
+    impl Repeater {
+        async fn call(&self, x: Vec<String>) -> String {
+            x.concat().repeat(self.times)
+        }
+    }
+
+    #[tokio::test]
+    async fn struct_ops_read_their_config_from_the_spec() {
+        let mut registry = OpRegistry::default();
+        registry.register_struct::<Repeater>();
+        registry.register("old", wrap!(old));
+        assert!(registry.is_configurable("repeat"));
+
+        let spec = crate::spec::GraphSpec::from_json(
+            r#"{"nodes": [
+                {"name": "A", "inputs": ["entrypoint"], "op": "repeat", "config": {"times": 3}},
+                {"name": "B", "inputs": ["A"], "op": "old"}
+            ]}"#,
+        )
+        .unwrap();
+        let graph = spec.build(&registry).unwrap();
+        assert_eq!(graph.topology().unwrap(), spec);
+        let output = graph.run("ab".into(), "B".into()).await;
+        assert_eq!(output.unwrap(), "old ababab".to_string());
+
+        let spec = crate::spec::GraphSpec::from_json(
+            r#"{"nodes": [
+                {"name": "A", "inputs": ["entrypoint"], "op": "repeat"},
+                {"name": "B", "inputs": ["A"], "op": "old", "config": {}}
+            ]}"#,
+        )
+        .unwrap();
+        let errors: Vec<String> = spec
+            .build(&registry)
+            .err()
+            .unwrap()
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                "/nodes/0/config: missing field `times`",
+                "/nodes/1/config: op `old` takes no config",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn overlays_struct_op_config_for_one_run() {
+        use crate::options::RunOptions;
+
+        let mut registry = OpRegistry::default();
+        registry.register_struct::<Repeater>();
+        registry.register("old", wrap!(old));
+        let spec = crate::spec::GraphSpec::from_json(
+            r#"{"nodes": [
+                {"name": "A", "inputs": ["entrypoint"], "op": "repeat", "config": {"times": 3}},
+                {"name": "B", "inputs": ["A"], "op": "old"}
+            ]}"#,
+        )
+        .unwrap();
+        let mut graph = spec.build(&registry).unwrap();
+        graph.set_sampler(crate::sampling::Sampler::new(0.5, std::io::sink()));
+
+        let options = RunOptions::default().overlay_config("A", serde_json::json!({"times": 1}));
+        let output = graph
+            .run_with_options("ab".into(), "B".into(), &options)
+            .await;
+        assert_eq!(output.unwrap(), "old ab".to_string());
+        let output = graph.run("ab".into(), "B".into()).await;
+        assert_eq!(output.unwrap(), "old ababab".to_string());
+        // Every other execution is sampled, and the overlaid one of A doesn't take a turn.
+        assert_eq!(graph.sampler().unwrap().sampled(), 1);
+
+        let options = RunOptions::default().overlay_config("B", serde_json::json!({"times": 1}));
+        let error = graph
+            .run_with_options("ab".into(), "B".into(), &options)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Node B could not take its config overlay: its op takes no config"
+        );
+        let options = RunOptions::default().overlay_config("A", serde_json::json!({"times": "x"}));
+        let error = graph
+            .run_with_options("ab".into(), "B".into(), &options)
+            .await
+            .unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("Node A could not take its config overlay: invalid type"));
+    }
+}
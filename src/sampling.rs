@@ -0,0 +1,187 @@
+use crate::dedicated::DedicatedThread;
+use crate::id::RunId;
+use std::cell::RefCell;
+use std::fs::File;
+use std::future::Future;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// A `ScrubFn` rewrites a value before it is written to a dataset, e.g. to mask emails or account numbers.
+pub type ScrubFn = fn(&str) -> String;
+
+/// A `Sampler` records a fraction of the `Node` executions in a `Graph` as JSON lines of the form
+/// `{"run_id": ..., "node": ..., "inputs": [...], "output": ...}`, which makes it easy to grow an evaluation dataset out of real
+/// traffic. Every input and output is passed through the registered scrubbers, in order, before it is written.
+///
+/// Sampling is deterministic: with a `rate` of `0.25`, exactly one in every four executions is kept. Writing a record
+/// never fails a run; failed writes are counted in `write_errors` instead. Records are buffered by the writer, and
+/// flushed with `flush` or when the `Sampler` is dropped.
+pub struct Sampler {
+    rate: f64,
+    scrubbers: Vec<ScrubFn>,
+    writer: Writer,
+    seen: u64,
+    /// Shared with the thread writing the records, for a `Sampler` writing to a file.
+    sampled: Arc<AtomicU64>,
+    write_errors: Arc<AtomicU64>,
+}
+
+enum Writer {
+    Local(RefCell<Box<dyn Write>>),
+    /// A file, written on a thread of its own so runs don't block on disk.
+    File(DedicatedThread<BufWriter<File>>),
+}
+
+impl Sampler {
+    /// Creates a `Sampler` that keeps `rate` (between `0.0` and `1.0`) of executions and writes them to `writer`.
+    pub fn new(rate: f64, writer: impl Write + 'static) -> Self {
+        Self {
+            rate: rate.clamp(0.0, 1.0),
+            scrubbers: vec![],
+            writer: Writer::Local(RefCell::new(Box::new(writer))),
+            seen: 0,
+            sampled: Arc::default(),
+            write_errors: Arc::default(),
+        }
+    }
+
+    /// Creates a `Sampler` that appends to the dataset file at `path`, creating it if needed. The file is written on a
+    /// `DedicatedThread`, so `sampled` and `write_errors` catch up with the records shortly after they are made.
+    pub fn to_file(rate: f64, path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = BufWriter::new(File::options().create(true).append(true).open(path)?);
+        let mut sampler = Self::new(rate, io::sink());
+        sampler.writer = Writer::File(DedicatedThread::spawn("sampler", move || file));
+        Ok(sampler)
+    }
+
+    pub fn with_scrubber(mut self, scrubber: ScrubFn) -> Self {
+        self.scrubbers.push(scrubber);
+        self
+    }
+
+    /// The number of records written so far.
+    pub fn sampled(&self) -> u64 {
+        self.sampled.load(Ordering::Relaxed)
+    }
+
+    /// The number of records that were chosen but could not be written.
+    pub fn write_errors(&self) -> u64 {
+        self.write_errors.load(Ordering::Relaxed)
+    }
+
+    /// `flush` writes out every record made so far. The future it returns doesn't borrow the `Sampler`, so it can be
+    /// awaited after the `Ref` from `Graph::sampler` is dropped.
+    pub fn flush(&self) -> impl Future<Output = io::Result<()>> {
+        let flushed = match &self.writer {
+            Writer::Local(writer) => Err(writer.borrow_mut().flush()),
+            Writer::File(thread) => Ok(thread.clone()),
+        };
+        async move {
+            match flushed {
+                Err(result) => result,
+                Ok(thread) => match thread.call(|file| file.flush()).await {
+                    Some(result) => result,
+                    None => Err(io::Error::other("the sampler's writer thread has stopped")),
+                },
+            }
+        }
+    }
+
+    /// Decides whether the next execution should be recorded.
+    pub(crate) fn should_sample(&mut self) -> bool {
+        let before = (self.seen as f64 * self.rate).floor();
+        self.seen += 1;
+        (self.seen as f64 * self.rate).floor() > before
+    }
+
+    pub(crate) fn record(&mut self, run_id: RunId, node: &str, inputs: &[String], output: &str) {
+        let record = serde_json::json!({
+            "run_id": run_id.to_string(),
+            "node": node,
+            "inputs": inputs.iter().map(|i| self.scrub(i)).collect::<Vec<_>>(),
+            "output": self.scrub(output),
+        });
+        let (sampled, write_errors) = (self.sampled.clone(), self.write_errors.clone());
+        let count = move |written: io::Result<()>| match written {
+            Ok(()) => sampled.fetch_add(1, Ordering::Relaxed),
+            Err(_) => write_errors.fetch_add(1, Ordering::Relaxed),
+        };
+        match &self.writer {
+            Writer::Local(writer) => {
+                count(writeln!(writer.borrow_mut(), "{record}"));
+            }
+            Writer::File(thread) => {
+                let sent = thread.send(move |file| {
+                    count(writeln!(file, "{record}"));
+                });
+                if !sent {
+                    self.write_errors.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+    }
+
+    fn scrub(&self, value: &str) -> String {
+        self.scrubbers
+            .iter()
+            .fold(value.to_string(), |value, scrubber| scrubber(&value))
+    }
+}
+
+impl Drop for Sampler {
+    /// A file is flushed by its thread once the last record is written and the thread stops.
+    fn drop(&mut self) {
+        if let Writer::Local(writer) = &self.writer {
+            let _ = writer.borrow_mut().flush();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn mask_digits(value: &str) -> String {
+        value
+            .chars()
+            .map(|c| if c.is_ascii_digit() { '#' } else { c })
+            .collect()
+    }
+
+    #[test]
+    fn samples_fraction_and_scrubs() {
+        let buffer = SharedBuffer::default();
+        let mut sampler = Sampler::new(0.5, buffer.clone()).with_scrubber(mask_digits);
+        let run_id = "01ARZ3NDEKTSV4RRFFQ69G5FAV".parse().unwrap();
+        for i in 0..4 {
+            if sampler.should_sample() {
+                sampler.record(run_id, "A", &[format!("call 555-{i}")], "ok");
+            }
+        }
+        assert_eq!(sampler.sampled(), 2);
+
+        let written = String::from_utf8(buffer.0.borrow().clone()).unwrap();
+        let first = written.lines().next().unwrap();
+        assert_eq!(
+            first,
+            r#"{"inputs":["call ###-#"],"node":"A","output":"ok","run_id":"01ARZ3NDEKTSV4RRFFQ69G5FAV"}"#
+        );
+    }
+}
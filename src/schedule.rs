@@ -0,0 +1,333 @@
+use crate::error::RunError;
+use serde_json::{Map, Value};
+use std::collections::{HashMap, HashSet};
+
+/// One input edge: input `slot` of the `Node` at `consumer`, which gets the output called `key` of its producer if
+/// it subscribes to one.
+struct Edge {
+    consumer: usize,
+    slot: usize,
+    key: Option<String>,
+}
+
+/// How the `Node`s of a `Graph` are connected, with every `Node` referred to by its position in staging order. The
+/// `Graph` works this out once and keeps it until another `Node` is staged, so runs don't resolve inputs by name.
+pub(crate) struct Wiring {
+    /// The edges leaving each `Node`.
+    consumers: Vec<Vec<Edge>>,
+    /// The edges leaving `entrypoint`.
+    entrypoint: Vec<Edge>,
+    /// Where each input of each `Node` comes from, `None` standing for `entrypoint`.
+    producers: Vec<Vec<Option<usize>>>,
+    /// Which `Node`s can get all of their inputs: all but those on a cycle and those depending on one.
+    runnable: Vec<bool>,
+}
+
+impl Wiring {
+    /// `inputs` lists the inputs of every `Node`, each as the position of the `Node` it comes from (`None` for
+    /// `entrypoint`) and the key of the output it subscribes to, if any.
+    pub(crate) fn new(inputs: Vec<Vec<(Option<usize>, Option<String>)>>) -> Self {
+        let mut consumers: Vec<Vec<Edge>> = inputs.iter().map(|_| vec![]).collect();
+        let mut entrypoint = vec![];
+        let mut producers: Vec<Vec<Option<usize>>> = vec![];
+        for (consumer, inputs) in inputs.into_iter().enumerate() {
+            producers.push(inputs.iter().map(|(producer, _)| *producer).collect());
+            for (slot, (producer, key)) in inputs.into_iter().enumerate() {
+                let edge = Edge {
+                    consumer,
+                    slot,
+                    key,
+                };
+                match producer {
+                    Some(producer) => consumers[producer].push(edge),
+                    None => entrypoint.push(edge),
+                }
+            }
+        }
+        // Lets each `Node` go once every `Node` it reads from has, so those on or after a cycle never do.
+        let mut missing: Vec<usize> = producers
+            .iter()
+            .map(|p| p.iter().flatten().count())
+            .collect();
+        let mut runnable = vec![false; producers.len()];
+        let mut next: Vec<usize> = (0..producers.len()).filter(|&n| missing[n] == 0).collect();
+        while let Some(node) = next.pop() {
+            runnable[node] = true;
+            for edge in &consumers[node] {
+                missing[edge.consumer] -= 1;
+                if missing[edge.consumer] == 0 {
+                    next.push(edge.consumer);
+                }
+            }
+        }
+        Self {
+            consumers,
+            entrypoint,
+            producers,
+            runnable,
+        }
+    }
+
+    /// Whether the `Node` at `node` can ever get all of its inputs.
+    pub(crate) fn runnable(&self, node: usize) -> bool {
+        self.runnable[node]
+    }
+
+    /// Where each input of the `Node` at `node` comes from, `None` standing for `entrypoint`.
+    pub(crate) fn producers(&self, node: usize) -> &[Option<usize>] {
+        &self.producers[node]
+    }
+
+    /// Which `Node`s the value of the `Node` at `node` depends on, directly or not, by position. `entrypoint`
+    /// depends on none.
+    pub(crate) fn feeding(&self, node: Option<usize>) -> Vec<bool> {
+        let mut feeding = vec![false; self.producers.len()];
+        let mut next: Vec<usize> = node.into_iter().collect();
+        while let Some(node) = next.pop() {
+            for producer in self.producers[node].iter().flatten() {
+                if !std::mem::replace(&mut feeding[*producer], true) {
+                    next.push(*producer);
+                }
+            }
+        }
+        feeding
+    }
+
+    /// The `Node`s without any inputs, which can start straight away.
+    pub(crate) fn sources(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.producers.len()).filter(|&node| self.producers[node].is_empty())
+    }
+}
+
+/// The `Node`s of one run that have received some of their inputs but not all of them yet, with those inputs. A
+/// `Node` only takes up room here while it is partly fed: not before its first input arrives, and not once it has
+/// started, so a run holds on to its frontier rather than to the whole `Graph`.
+pub(crate) struct Frontier<'a> {
+    wiring: &'a Wiring,
+    waiting: HashMap<usize, Waiting>,
+    /// `Node`s that were started before all of their inputs arrived, whose remaining inputs are dropped.
+    started_early: HashSet<usize>,
+    /// `Node`s that can never start because a `Node` they depend on failed, whose inputs are dropped as well.
+    abandoned: HashSet<usize>,
+}
+
+/// A `Node` that has all of its inputs, with those inputs.
+pub(crate) type Ready = (usize, Vec<Option<String>>);
+
+struct Waiting {
+    inputs: Vec<Option<Option<String>>>,
+    missing: usize,
+}
+
+impl<'a> Frontier<'a> {
+    pub(crate) fn new(wiring: &'a Wiring) -> Self {
+        Self {
+            wiring,
+            waiting: HashMap::new(),
+            started_early: HashSet::new(),
+            abandoned: HashSet::new(),
+        }
+    }
+
+    /// `deliver` hands `value`, which the `Node` at `producer` (or `entrypoint` for `None`) called `name` produced,
+    /// to each of its consumers, calling `received` with the consumer and input slot of each delivery, and `dropped`
+    /// for each consumer that takes no more inputs. It returns the consumers that now have all of their inputs, with
+    /// those inputs. Consumers subscribing to an output get that field of the JSON object `value` holds, and the whole
+    /// delivery fails if a field is missing.
+    pub(crate) fn deliver(
+        &mut self,
+        producer: Option<usize>,
+        name: &str,
+        value: Option<String>,
+        mut received: impl FnMut(usize, usize),
+        mut dropped: impl FnMut(),
+    ) -> Result<Vec<Ready>, RunError> {
+        let wiring = self.wiring;
+        let edges = match producer {
+            Some(producer) => &wiring.consumers[producer],
+            None => &wiring.entrypoint,
+        };
+        // Every field is looked up before anything is handed over, so a missing one doesn't leave a partial delivery.
+        let mut object = None;
+        let mut fields = vec![];
+        for edge in edges {
+            fields.push(match (&edge.key, &value) {
+                (Some(key), Some(value)) => {
+                    let object = object.get_or_insert_with(|| parse_object(value));
+                    Some(Some(field(name, object.as_ref(), key)?))
+                }
+                (Some(_), None) => Some(None),
+                (None, _) => None,
+            });
+        }
+        // The whole value is cloned for all but the last consumer taking it, which takes the value itself.
+        let last_whole = edges.iter().rposition(|edge| edge.key.is_none());
+        let mut value = value;
+        let mut ready = vec![];
+        for (i, (edge, field)) in edges.iter().zip(fields).enumerate() {
+            let input = match field {
+                Some(field) => field,
+                None if Some(i) == last_whole => value.take(),
+                None => value.clone(),
+            };
+            if self.started_early.contains(&edge.consumer)
+                || self.abandoned.contains(&edge.consumer)
+            {
+                dropped();
+                continue;
+            }
+            received(edge.consumer, edge.slot);
+            let arity = wiring.producers[edge.consumer].len();
+            let waiting = self
+                .waiting
+                .entry(edge.consumer)
+                .or_insert_with(|| Waiting {
+                    inputs: vec![None; arity],
+                    missing: arity,
+                });
+            waiting.inputs[edge.slot] = Some(input);
+            waiting.missing -= 1;
+            if waiting.missing == 0 {
+                let waiting = self.waiting.remove(&edge.consumer).expect("just inserted");
+                let inputs = waiting
+                    .inputs
+                    .into_iter()
+                    .map(|input| input.expect("every input arrived"));
+                ready.push((edge.consumer, inputs.collect()));
+            }
+        }
+        Ok(ready)
+    }
+
+    /// `abandon` gives up on every `Node` that depends on the one at `failed`, directly or not, unless it was started
+    /// early and may still get a value. The inputs they hold are dropped, and the producers of those inputs are
+    /// returned, one per input.
+    pub(crate) fn abandon(&mut self, failed: usize) -> Vec<Option<usize>> {
+        let mut released = vec![];
+        let mut next = vec![failed];
+        while let Some(node) = next.pop() {
+            for edge in &self.wiring.consumers[node] {
+                let consumer = edge.consumer;
+                if self.started_early.contains(&consumer) || !self.abandoned.insert(consumer) {
+                    continue;
+                }
+                if let Some(waiting) = self.waiting.remove(&consumer) {
+                    let producers = &self.wiring.producers[consumer];
+                    let held = producers.iter().zip(&waiting.inputs);
+                    released.extend(held.filter(|(_, input)| input.is_some()).map(|(p, _)| *p));
+                }
+                next.push(consumer);
+            }
+        }
+        released
+    }
+
+    /// `start_early` marks the `Node` at `node` as started before all of its inputs arrived, so it gets no more of
+    /// them. It returns the inputs it got so far, with `None` for the rest.
+    pub(crate) fn start_early(&mut self, node: usize) -> Vec<Option<String>> {
+        self.started_early.insert(node);
+        match self.waiting.remove(&node) {
+            Some(waiting) => waiting.inputs.into_iter().map(Option::flatten).collect(),
+            None => vec![],
+        }
+    }
+}
+
+/// The output called `key` of the `Node` called `node`, which produced `value`: the whole value without a `key`.
+pub(crate) fn output(
+    node: &str,
+    value: Option<String>,
+    key: Option<&str>,
+) -> Result<Option<String>, RunError> {
+    match (value, key) {
+        (Some(value), Some(key)) => field(node, parse_object(&value).as_ref(), key).map(Some),
+        (value, _) => Ok(value),
+    }
+}
+
+fn parse_object(value: &str) -> Option<Map<String, Value>> {
+    match serde_json::from_str(value) {
+        Ok(Value::Object(object)) => Some(object),
+        _ => None,
+    }
+}
+
+/// The field `key` of `object`, as it is if it is a string and as JSON otherwise.
+fn field(node: &str, object: Option<&Map<String, Value>>, key: &str) -> Result<String, RunError> {
+    match object.and_then(|object| object.get(key)) {
+        Some(Value::String(field)) => Ok(field.clone()),
+        Some(field) => Ok(field.to_string()),
+        None => Err(RunError::MissingOutput {
+            node: node.to_string(),
+            output: key.to_string(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn holds_only_partly_fed_nodes() {
+        // A and B read `entrypoint`, and C reads the output `x` of A along with all of B.
+        let wiring = Wiring::new(vec![
+            vec![(None, None)],
+            vec![(None, None)],
+            vec![(Some(0), Some("x".into())), (Some(1), None)],
+        ]);
+        let mut frontier = Frontier::new(&wiring);
+        let ready = frontier.deliver(None, "entrypoint", Some("in".into()), |_, _| {}, || {});
+        assert_eq!(
+            ready.unwrap(),
+            vec![(0, vec![Some("in".into())]), (1, vec![Some("in".into())])]
+        );
+        assert!(frontier.waiting.is_empty());
+
+        let ready = frontier.deliver(Some(0), "A", Some(r#"{"x": 1}"#.into()), |_, _| {}, || {});
+        assert_eq!(ready.unwrap(), vec![]);
+        assert_eq!(frontier.waiting.len(), 1);
+        let mut received = vec![];
+        let ready = frontier.deliver(
+            Some(1),
+            "B",
+            None,
+            |node, slot| received.push((node, slot)),
+            || {},
+        );
+        assert_eq!(ready.unwrap(), vec![(2, vec![Some("1".into()), None])]);
+        assert_eq!(received, vec![(2, 1)]);
+        assert!(frontier.waiting.is_empty());
+
+        let error = frontier.deliver(Some(0), "A", Some("[]".into()), |_, _| {}, || {});
+        assert_eq!(
+            error.unwrap_err(),
+            RunError::MissingOutput {
+                node: "A".into(),
+                output: "x".into()
+            }
+        );
+    }
+
+    #[test]
+    fn abandons_nodes_downstream_of_a_failure() {
+        // C reads A and B, and D reads C.
+        let wiring = Wiring::new(vec![
+            vec![(None, None)],
+            vec![(None, None)],
+            vec![(Some(0), None), (Some(1), None)],
+            vec![(Some(2), None)],
+        ]);
+        let mut frontier = Frontier::new(&wiring);
+        let ready = frontier.deliver(Some(0), "A", Some("a".into()), |_, _| {}, || {});
+        assert_eq!(ready.unwrap(), vec![]);
+        assert_eq!(frontier.abandon(1), vec![Some(0)]);
+        assert!(frontier.waiting.is_empty());
+        assert_eq!(frontier.abandon(1), vec![]);
+
+        let mut dropped = 0;
+        let ready = frontier.deliver(Some(0), "A", Some("a".into()), |_, _| {}, || dropped += 1);
+        assert_eq!(ready.unwrap(), vec![]);
+        assert_eq!(dropped, 1);
+    }
+}
@@ -0,0 +1,294 @@
+use crate::spec::pointer;
+use regex::Regex;
+use serde_json::{Map, Value};
+use std::fmt::Write;
+use std::future::Future;
+
+tokio::task_local! {
+    static REPAIR: String;
+}
+
+/// An `OutputSchema` is a JSON Schema that the values of a `Node` must follow, see `Graph::set_output_schema`. When a
+/// value doesn't, the `Node`s `op` is called again up to `repairs` times with a repair prompt that lists what was
+/// wrong with the last value, and the run fails with `RunError::SchemaMismatch` if none of them fit either. The prompt
+/// is appended to the inputs of `op`s that take strings, and any `op`, such as one staged with
+/// `Graph::stage_json_node`, can read it with `current_repair`.
+///
+/// Only the keywords most output schemas need are checked: `type`, `enum`, `const`, `not`, `properties`,
+/// `required`, `additionalProperties`, `items`, `minItems`, `maxItems`, `minLength`, `maxLength`, `pattern`,
+/// `minimum`, `maximum`, and `$ref` to a JSON pointer within the schema, such as `#/$defs/node`. Any others are
+/// ignored.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OutputSchema {
+    schema: Value,
+    repairs: u32,
+}
+
+impl OutputSchema {
+    pub fn new(schema: Value) -> Self {
+        Self { schema, repairs: 0 }
+    }
+
+    /// `with_repairs` allows `repairs` more calls of the `op` after a value that doesn't fit.
+    pub fn with_repairs(mut self, repairs: u32) -> Self {
+        self.repairs = repairs;
+        self
+    }
+
+    pub fn schema(&self) -> &Value {
+        &self.schema
+    }
+
+    pub fn repairs(&self) -> u32 {
+        self.repairs
+    }
+
+    /// `check` returns what is wrong with `value`, one problem per entry with the JSON pointer it was found at, or
+    /// nothing if it fits.
+    pub fn check(&self, value: &str) -> Vec<String> {
+        let value: Value = match serde_json::from_str(value) {
+            Ok(value) => value,
+            Err(e) => return vec![format!("not valid JSON: {e}")],
+        };
+        let mut problems = vec![];
+        check(&self.schema, &self.schema, &value, "", &mut problems);
+        problems
+    }
+
+    /// The input appended for another attempt after the `op` produced `value`, which had `problems`.
+    pub fn repair_prompt(&self, value: &str, problems: &[String]) -> String {
+        let mut prompt = format!(
+            "Your previous output does not match the required JSON Schema.\n\nPrevious output:\n{value}\n\nProblems:\n"
+        );
+        for problem in problems {
+            let _ = writeln!(prompt, "- {problem}");
+        }
+        let _ = write!(
+            prompt,
+            "\nRespond again with only JSON that matches this schema:\n{}",
+            self.schema
+        );
+        prompt
+    }
+}
+
+/// `current_repair` gives the repair prompt while the `op` being polled is called again because its last value didn't
+/// fit its `OutputSchema`, and `None` on a first attempt or outside of an `op`.
+pub fn current_repair() -> Option<String> {
+    REPAIR.try_with(Clone::clone).ok()
+}
+
+/// Polls `future` as a repair attempt, so `current_repair` returns `prompt` within it.
+pub(crate) async fn repairing<F: Future>(prompt: String, future: F) -> F::Output {
+    REPAIR.scope(prompt, future).await
+}
+
+fn check(root: &Value, schema: &Value, value: &Value, path: &str, problems: &mut Vec<String>) {
+    let Value::Object(schema) = schema else {
+        if schema == &Value::Bool(false) {
+            problems.push(format!("{path}: no value is allowed here"));
+        }
+        return;
+    };
+    let at = |problem: String| match path {
+        "" => problem,
+        path => format!("{path}: {problem}"),
+    };
+    if let Some(Value::String(reference)) = schema.get("$ref") {
+        match reference.strip_prefix('#').and_then(|p| root.pointer(p)) {
+            Some(referenced) => check(root, referenced, value, path, problems),
+            None => problems.push(at(format!("cannot resolve $ref {reference}"))),
+        }
+    }
+    if let Some(expected) = schema.get("type") {
+        let types: Vec<&str> = match expected {
+            Value::String(name) => vec![name.as_str()],
+            Value::Array(names) => names.iter().filter_map(Value::as_str).collect(),
+            _ => vec![],
+        };
+        if !types.is_empty() && !types.iter().any(|name| is_type(value, name)) {
+            problems.push(at(format!(
+                "expected {}, got {}",
+                types.join(" or "),
+                type_of(value)
+            )));
+            return;
+        }
+    }
+    if let Some(Value::Array(allowed)) = schema.get("enum") {
+        if !allowed.contains(value) {
+            problems.push(at(format!(
+                "{value} is not one of {}",
+                Value::from(allowed.clone())
+            )));
+        }
+    }
+    if let Some(expected) = schema.get("const") {
+        if expected != value {
+            problems.push(at(format!("expected {expected}, got {value}")));
+        }
+    }
+    if let Some(not) = schema.get("not") {
+        let mut matched = vec![];
+        check(root, not, value, path, &mut matched);
+        if matched.is_empty() {
+            problems.push(at(format!("{value} must not match {not}")));
+        }
+    }
+    match value {
+        Value::Object(object) => check_object(root, schema, object, path, problems),
+        Value::Array(items) => {
+            if let Some(min) = schema.get("minItems").and_then(Value::as_u64) {
+                if (items.len() as u64) < min {
+                    problems.push(at(format!(
+                        "expected at least {min} items, got {}",
+                        items.len()
+                    )));
+                }
+            }
+            if let Some(max) = schema.get("maxItems").and_then(Value::as_u64) {
+                if items.len() as u64 > max {
+                    problems.push(at(format!(
+                        "expected at most {max} items, got {}",
+                        items.len()
+                    )));
+                }
+            }
+            if let Some(item) = schema.get("items") {
+                for (i, value) in items.iter().enumerate() {
+                    check(root, item, value, &format!("{path}/{i}"), problems);
+                }
+            }
+        }
+        Value::String(text) => {
+            let length = text.chars().count() as u64;
+            if let Some(min) = schema.get("minLength").and_then(Value::as_u64) {
+                if length < min {
+                    problems.push(at(format!(
+                        "expected at least {min} characters, got {length}"
+                    )));
+                }
+            }
+            if let Some(max) = schema.get("maxLength").and_then(Value::as_u64) {
+                if length > max {
+                    problems.push(at(format!(
+                        "expected at most {max} characters, got {length}"
+                    )));
+                }
+            }
+            if let Some(Value::String(pattern)) = schema.get("pattern") {
+                match Regex::new(pattern) {
+                    Ok(regex) if regex.is_match(text) => {}
+                    Ok(_) => problems.push(at(format!("{value} does not match {pattern}"))),
+                    Err(e) => problems.push(at(format!("invalid pattern {pattern}: {e}"))),
+                }
+            }
+        }
+        Value::Number(number) => {
+            let number = number.as_f64().unwrap_or_default();
+            if let Some(min) = schema.get("minimum").and_then(Value::as_f64) {
+                if number < min {
+                    problems.push(at(format!("expected at least {min}, got {number}")));
+                }
+            }
+            if let Some(max) = schema.get("maximum").and_then(Value::as_f64) {
+                if number > max {
+                    problems.push(at(format!("expected at most {max}, got {number}")));
+                }
+            }
+        }
+        Value::Null | Value::Bool(_) => {}
+    }
+}
+
+fn check_object(
+    root: &Value,
+    schema: &Map<String, Value>,
+    object: &Map<String, Value>,
+    path: &str,
+    problems: &mut Vec<String>,
+) {
+    if let Some(Value::Array(required)) = schema.get("required") {
+        for key in required.iter().filter_map(Value::as_str) {
+            if !object.contains_key(key) {
+                problems.push(format!("{}: missing required property", pointer(path, key)));
+            }
+        }
+    }
+    let properties = match schema.get("properties") {
+        Some(Value::Object(properties)) => Some(properties),
+        _ => None,
+    };
+    for (key, value) in object {
+        let path = pointer(path, key);
+        match (
+            properties.and_then(|p| p.get(key)),
+            schema.get("additionalProperties"),
+        ) {
+            (Some(property), _) => check(root, property, value, &path, problems),
+            (None, Some(additional)) => check(root, additional, value, &path, problems),
+            (None, None) => {}
+        }
+    }
+}
+
+fn is_type(value: &Value, name: &str) -> bool {
+    match name {
+        "integer" => {
+            value.as_i64().is_some()
+                || value.as_u64().is_some()
+                || value.as_f64().is_some_and(|n| n.fract() == 0.0)
+        }
+        name => type_of(value) == name,
+    }
+}
+
+fn type_of(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn reports_every_problem_with_its_path() {
+        let schema = OutputSchema::new(json!({
+            "type": "object",
+            "required": ["answer", "confidence"],
+            "additionalProperties": false,
+            "properties": {
+                "answer": { "type": "string", "minLength": 1 },
+                "confidence": { "type": "number", "minimum": 0, "maximum": 1 },
+                "sources": { "type": "array", "items": { "type": "integer" } }
+            }
+        }));
+        assert_eq!(
+            schema.check(r#"{"answer": "42", "confidence": 0.5, "sources": [1, 2.0]}"#),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            schema.check(r#"{"answer": "", "confidence": 2, "sources": [1, "b"], "extra": true}"#),
+            vec![
+                "/answer: expected at least 1 characters, got 0",
+                "/confidence: expected at most 1, got 2",
+                "/extra: no value is allowed here",
+                "/sources/1: expected integer, got string",
+            ]
+        );
+        assert_eq!(schema.check("[]"), vec!["expected object, got array"]);
+        assert_eq!(
+            schema.check(r#"{"answer": "42", "confidence": 0.5, "a/b~c": 1}"#),
+            vec!["/a~1b~0c: no value is allowed here"]
+        );
+        assert!(schema.check("not json")[0].starts_with("not valid JSON"));
+    }
+}
@@ -0,0 +1,730 @@
+use crate::graph::{split_input, Graph, Op};
+use crate::middleware::{Middleware, MiddlewareRegistry};
+use crate::migrate::Version;
+use crate::registry::OpRegistry;
+use serde::de::{self, Deserialize, Deserializer};
+use serde::ser::{self, Serialize, SerializeStruct, Serializer};
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::error::Error;
+use std::fmt;
+
+/// One `Node` of a `GraphSpec`: the same `name` and `inputs` that `Graph::stage_node` takes, the name its `op`
+/// is registered under in an `OpRegistry`, and optionally the `Version` it is pinned to. `config` is read by ops
+/// registered with `OpRegistry::register_struct`; other ops take none. `tags` are added with `Graph::tag_node`, and
+/// `middleware` is applied to this `Node` alone, after any the spec declares for its tags.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NodeSpec {
+    pub name: String,
+    pub inputs: Vec<String>,
+    pub op: String,
+    pub config: Option<Value>,
+    pub version: Option<Version>,
+    pub tags: BTreeSet<String>,
+    pub middleware: Vec<MiddlewareSpec>,
+}
+
+/// One `Middleware` of a spec: the name it is registered under in a `MiddlewareRegistry`, and its `config`, e.g.
+/// `{"name": "retry", "config": {"retries": 2, "timeout_ms": 5000}}`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MiddlewareSpec {
+    pub name: String,
+    pub config: Option<Value>,
+}
+
+/// A `GraphSpec` describes a `Graph` as data, so it can be kept in a config file rather than in code:
+/// ```json
+/// { "nodes": [ { "name": "A", "inputs": ["entrypoint"], "op": "concat" } ] }
+/// ```
+/// Documents are checked against the schema from `GraphSpec::json_schema` when they are parsed, and every problem is
+/// reported with the JSON pointer of the value it was found at.
+///
+/// Resilience policy can live in the spec too. `middleware` lists, by tag, the `Middleware` stack applied to every
+/// `Node` carrying that tag, and each `Node` can add its own:
+/// ```json
+/// {
+///   "nodes": [ { "name": "A", "inputs": ["entrypoint"], "op": "ask", "tags": ["llm"],
+///                "middleware": [ { "name": "cache", "config": { "ttl_ms": 60000 } } ] } ],
+///   "middleware": { "llm": [ { "name": "retry", "config": { "retries": 2, "timeout_ms": 5000 } } ] }
+/// }
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct GraphSpec {
+    pub nodes: Vec<NodeSpec>,
+    pub middleware: BTreeMap<String, Vec<MiddlewareSpec>>,
+}
+
+/// A problem with a graph spec document. `path` is a JSON pointer such as `/nodes/2/inputs/0`, or empty when the
+/// problem is with the whole document.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SpecError {
+    pub path: String,
+    pub message: String,
+}
+
+impl SpecError {
+    pub(crate) fn new(path: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            path: path.into(),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for SpecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.path.is_empty() {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{}: {}", self.path, self.message)
+        }
+    }
+}
+
+impl Error for SpecError {}
+
+impl GraphSpec {
+    /// The JSON Schema (draft 2020-12) that graph spec documents must follow.
+    pub fn json_schema() -> Value {
+        json!({
+            "$schema": "https://json-schema.org/draft/2020-12/schema",
+            "title": "inference_graph graph spec",
+            "type": "object",
+            "required": ["nodes"],
+            "additionalProperties": false,
+            "properties": {
+                "nodes": {
+                    "type": "array",
+                    "items": { "$ref": "#/$defs/node" }
+                },
+                "middleware": {
+                    "type": "object",
+                    "additionalProperties": { "$ref": "#/$defs/stack" }
+                }
+            },
+            "$defs": {
+                "node": {
+                    "type": "object",
+                    "required": ["name", "inputs", "op"],
+                    "additionalProperties": false,
+                    "properties": {
+                        "name": { "type": "string", "minLength": 1, "not": { "const": "entrypoint" } },
+                        "inputs": { "type": "array", "items": { "type": "string" } },
+                        "op": { "type": "string", "minLength": 1 },
+                        "config": {},
+                        "version": { "type": "string", "pattern": "^[0-9]+\\.[0-9]+\\.[0-9]+$" },
+                        "tags": { "type": "array", "items": { "type": "string", "minLength": 1 } },
+                        "middleware": { "$ref": "#/$defs/stack" }
+                    }
+                },
+                "stack": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "required": ["name"],
+                        "additionalProperties": false,
+                        "properties": {
+                            "name": { "type": "string", "minLength": 1 },
+                            "config": {}
+                        }
+                    }
+                }
+            }
+        })
+    }
+
+    /// Parses and validates a graph spec document, returning every problem found rather than only the first.
+    pub fn from_json(text: &str) -> Result<Self, Vec<SpecError>> {
+        let value: Value = serde_json::from_str(text).map_err(|e| {
+            vec![SpecError::new(
+                "",
+                format!(
+                    "invalid JSON at line {} column {}: {e}",
+                    e.line(),
+                    e.column()
+                ),
+            )]
+        })?;
+        Self::from_value(&value)
+    }
+
+    /// Validates an already parsed graph spec document.
+    pub fn from_value(value: &Value) -> Result<Self, Vec<SpecError>> {
+        let mut errors = vec![];
+        let mut spec = Self::default();
+        if let Some(root) = object(value, "", &["nodes", "middleware"], &mut errors) {
+            match root.get("nodes") {
+                None => errors.push(SpecError::new("", "missing required property `nodes`")),
+                Some(Value::Array(nodes)) => {
+                    for (i, node) in nodes.iter().enumerate() {
+                        if let Some(node) = node_spec(node, &format!("/nodes/{i}"), &mut errors) {
+                            spec.nodes.push(node);
+                        }
+                    }
+                }
+                Some(other) => errors.push(expected("/nodes", "an array", other)),
+            }
+            match root.get("middleware") {
+                None => {}
+                Some(Value::Object(tags)) => {
+                    for (tag, stack) in tags {
+                        let stack =
+                            middleware_stack(stack, &pointer("/middleware", tag), &mut errors);
+                        spec.middleware.insert(tag.clone(), stack);
+                    }
+                }
+                Some(other) => errors.push(expected("/middleware", "an object", other)),
+            }
+        }
+        if errors.is_empty() {
+            Ok(spec)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// `build` stages every `Node` of the spec into a new `Graph`, looking up ops in `registry` and middleware in
+    /// `MiddlewareRegistry::default()`. It fails if a `Node` is defined twice, uses an op or middleware that isn't
+    /// registered, or takes an input that no `Node` provides.
+    pub fn build(&self, registry: &OpRegistry) -> Result<Graph, Vec<SpecError>> {
+        self.build_with_middleware(registry, &MiddlewareRegistry::default())
+    }
+
+    /// `build` with the middleware registered in `middleware`. Each tag's stack is applied first, in the order the
+    /// tags sort in, and then each `Node`s own; within a stack, later middleware wraps earlier.
+    pub fn build_with_middleware(
+        &self,
+        registry: &OpRegistry,
+        middleware: &MiddlewareRegistry,
+    ) -> Result<Graph, Vec<SpecError>> {
+        let mut errors = vec![];
+        let mut names = HashSet::new();
+        for (i, node) in self.nodes.iter().enumerate() {
+            if !names.insert(node.name.as_str()) {
+                errors.push(SpecError::new(
+                    format!("/nodes/{i}/name"),
+                    format!("node `{}` is defined more than once", node.name),
+                ));
+            }
+        }
+        let mut ops = vec![];
+        for (i, node) in self.nodes.iter().enumerate() {
+            match registry.build(&node.op, node.config.as_ref()) {
+                None => errors.push(SpecError::new(
+                    format!("/nodes/{i}/op"),
+                    format!("no op is registered as `{}`", node.op),
+                )),
+                Some(Err(message)) => {
+                    errors.push(SpecError::new(format!("/nodes/{i}/config"), message))
+                }
+                Some(Ok(op)) => ops.push(op),
+            }
+            for (j, input) in node.inputs.iter().enumerate() {
+                let (producer, _) = split_input(input, |name| names.contains(name));
+                if producer != "entrypoint" && !names.contains(producer) {
+                    errors.push(SpecError::new(
+                        format!("/nodes/{i}/inputs/{j}"),
+                        format!("no node is named `{input}`"),
+                    ));
+                }
+            }
+        }
+        let mut tag_stacks = vec![];
+        for (tag, stack) in &self.middleware {
+            let path = pointer("/middleware", tag);
+            tag_stacks.push(build_stack(middleware, stack, &path, &mut errors));
+        }
+        let mut node_stacks = vec![];
+        for (i, node) in self.nodes.iter().enumerate() {
+            let path = format!("/nodes/{i}/middleware");
+            node_stacks.push(build_stack(
+                middleware,
+                &node.middleware,
+                &path,
+                &mut errors,
+            ));
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        let mut graph = Graph::default();
+        for (node, op) in self.nodes.iter().zip(ops) {
+            node.stage(&mut graph, registry, op);
+        }
+        apply_middleware(
+            &mut graph,
+            &self.middleware,
+            tag_stacks,
+            &self.nodes,
+            node_stacks,
+        );
+        Ok(graph)
+    }
+}
+
+impl NodeSpec {
+    /// Stages this `Node` into `graph` with its already built `op`, along with everything else the spec says about
+    /// it except its middleware.
+    pub(crate) fn stage(&self, graph: &mut Graph, registry: &OpRegistry, op: Op) {
+        graph.stage_op(self.name.clone(), self.inputs.clone(), op);
+        graph.set_op_name(&self.name, &self.op);
+        if let Some(config) = &self.config {
+            graph.set_op_config(&self.name, config.clone());
+        }
+        if let Some(factory) = registry.factory(&self.op) {
+            graph.set_op_factory(&self.name, factory);
+        }
+        if let Some(version) = self.version {
+            graph.set_node_version(&self.name, version);
+        }
+        if let Some(signature) = registry.signature(&self.op) {
+            graph.set_signature(&self.name, signature.clone());
+        }
+        for tag in &self.tags {
+            graph.tag_node(&self.name, tag);
+        }
+    }
+}
+
+/// Applies the already built stacks of `tags` and then those of `nodes`, in the order
+/// `GraphSpec::build_with_middleware` describes.
+pub(crate) fn apply_middleware(
+    graph: &mut Graph,
+    tags: &BTreeMap<String, Vec<MiddlewareSpec>>,
+    tag_stacks: Vec<Vec<Box<dyn Middleware>>>,
+    nodes: &[NodeSpec],
+    node_stacks: Vec<Vec<Box<dyn Middleware>>>,
+) {
+    for ((tag, stack), built) in tags.iter().zip(tag_stacks) {
+        let names = graph.nodes_tagged(tag);
+        for layer in built {
+            layer.apply(graph, &names);
+        }
+        graph.set_group_middleware(tag, stack.clone());
+    }
+    for (node, built) in nodes.iter().zip(node_stacks) {
+        let names = [node.name.clone()];
+        for layer in built {
+            layer.apply(graph, &names);
+        }
+        graph.set_node_middleware(&node.name, node.middleware.clone());
+    }
+}
+
+impl Serialize for NodeSpec {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let fields = 3
+            + usize::from(self.config.is_some())
+            + usize::from(self.version.is_some())
+            + usize::from(!self.tags.is_empty())
+            + usize::from(!self.middleware.is_empty());
+        let mut node = serializer.serialize_struct("NodeSpec", fields)?;
+        node.serialize_field("name", &self.name)?;
+        node.serialize_field("inputs", &self.inputs)?;
+        node.serialize_field("op", &self.op)?;
+        match &self.config {
+            Some(config) => node.serialize_field("config", config)?,
+            None => node.skip_field("config")?,
+        }
+        match &self.version {
+            Some(version) => node.serialize_field("version", &version.to_string())?,
+            None => node.skip_field("version")?,
+        }
+        if self.tags.is_empty() {
+            node.skip_field("tags")?;
+        } else {
+            node.serialize_field("tags", &self.tags)?;
+        }
+        if self.middleware.is_empty() {
+            node.skip_field("middleware")?;
+        } else {
+            node.serialize_field("middleware", &self.middleware)?;
+        }
+        node.end()
+    }
+}
+
+impl Serialize for MiddlewareSpec {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut middleware = serializer
+            .serialize_struct("MiddlewareSpec", 1 + usize::from(self.config.is_some()))?;
+        middleware.serialize_field("name", &self.name)?;
+        match &self.config {
+            Some(config) => middleware.serialize_field("config", config)?,
+            None => middleware.skip_field("config")?,
+        }
+        middleware.end()
+    }
+}
+
+impl Serialize for GraphSpec {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let fields = 1 + usize::from(!self.middleware.is_empty());
+        let mut spec = serializer.serialize_struct("GraphSpec", fields)?;
+        spec.serialize_field("nodes", &self.nodes)?;
+        if self.middleware.is_empty() {
+            spec.skip_field("middleware")?;
+        } else {
+            spec.serialize_field("middleware", &self.middleware)?;
+        }
+        spec.end()
+    }
+}
+
+/// Deserializing checks the document like `GraphSpec::from_value`, failing with every problem found.
+impl<'de> Deserialize<'de> for GraphSpec {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = Value::deserialize(deserializer)?;
+        Self::from_value(&value).map_err(|errors| de::Error::custom(joined(&errors)))
+    }
+}
+
+/// A `Graph` serializes as its `topology`, and fails to if that is incomplete.
+impl Serialize for Graph {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let spec = self
+            .topology()
+            .map_err(|errors| ser::Error::custom(joined(&errors)))?;
+        spec.serialize(serializer)
+    }
+}
+
+fn joined(errors: &[SpecError]) -> String {
+    let errors: Vec<String> = errors.iter().map(ToString::to_string).collect();
+    errors.join("; ")
+}
+
+fn node_spec(value: &Value, path: &str, errors: &mut Vec<SpecError>) -> Option<NodeSpec> {
+    let node = object(
+        value,
+        path,
+        &[
+            "name",
+            "inputs",
+            "op",
+            "config",
+            "version",
+            "tags",
+            "middleware",
+        ],
+        errors,
+    )?;
+    let before = errors.len();
+    let name = required_string(node, path, "name", errors);
+    if name.as_deref() == Some("entrypoint") {
+        errors.push(SpecError::new(
+            format!("{path}/name"),
+            "`entrypoint` is reserved for the value passed to `run`",
+        ));
+    }
+    let op = required_string(node, path, "op", errors);
+    let inputs = match node.get("inputs") {
+        None => {
+            errors.push(SpecError::new(path, "missing required property `inputs`"));
+            None
+        }
+        Some(Value::Array(inputs)) => {
+            let mut names = vec![];
+            for (j, input) in inputs.iter().enumerate() {
+                match input {
+                    Value::String(input) => names.push(input.clone()),
+                    other => {
+                        errors.push(expected(&format!("{path}/inputs/{j}"), "a string", other))
+                    }
+                }
+            }
+            Some(names)
+        }
+        Some(other) => {
+            errors.push(expected(&format!("{path}/inputs"), "an array", other));
+            None
+        }
+    };
+    let version = match node.get("version") {
+        None => None,
+        Some(Value::String(version)) => match version.parse::<Version>() {
+            Ok(version) => Some(version),
+            Err(e) => {
+                errors.push(SpecError::new(format!("{path}/version"), e.to_string()));
+                None
+            }
+        },
+        Some(other) => {
+            errors.push(expected(&format!("{path}/version"), "a string", other));
+            None
+        }
+    };
+    let mut tags = BTreeSet::new();
+    match node.get("tags") {
+        None => {}
+        Some(Value::Array(values)) => {
+            for (j, tag) in values.iter().enumerate() {
+                match tag {
+                    Value::String(tag) if tag.is_empty() => errors.push(SpecError::new(
+                        format!("{path}/tags/{j}"),
+                        "must not be empty",
+                    )),
+                    Value::String(tag) => {
+                        tags.insert(tag.clone());
+                    }
+                    other => errors.push(expected(&format!("{path}/tags/{j}"), "a string", other)),
+                }
+            }
+        }
+        Some(other) => errors.push(expected(&format!("{path}/tags"), "an array", other)),
+    }
+    let middleware = match node.get("middleware") {
+        None => vec![],
+        Some(stack) => middleware_stack(stack, &format!("{path}/middleware"), errors),
+    };
+    if errors.len() > before {
+        return None;
+    }
+    Some(NodeSpec {
+        name: name?,
+        inputs: inputs?,
+        op: op?,
+        config: node.get("config").cloned(),
+        version,
+        tags,
+        middleware,
+    })
+}
+
+fn middleware_stack(value: &Value, path: &str, errors: &mut Vec<SpecError>) -> Vec<MiddlewareSpec> {
+    let Value::Array(stack) = value else {
+        errors.push(expected(path, "an array", value));
+        return vec![];
+    };
+    let mut specs = vec![];
+    for (j, middleware) in stack.iter().enumerate() {
+        let path = format!("{path}/{j}");
+        let Some(middleware) = object(middleware, &path, &["name", "config"], errors) else {
+            continue;
+        };
+        if let Some(name) = required_string(middleware, &path, "name", errors) {
+            specs.push(MiddlewareSpec {
+                name,
+                config: middleware.get("config").cloned(),
+            });
+        }
+    }
+    specs
+}
+
+/// Builds every `Middleware` of `stack`, reporting the ones that aren't registered or don't take their config.
+fn build_stack(
+    registry: &MiddlewareRegistry,
+    stack: &[MiddlewareSpec],
+    path: &str,
+    errors: &mut Vec<SpecError>,
+) -> Vec<Box<dyn Middleware>> {
+    let mut built = vec![];
+    for (j, middleware) in stack.iter().enumerate() {
+        match registry.build(&middleware.name, middleware.config.as_ref()) {
+            None => errors.push(SpecError::new(
+                format!("{path}/{j}/name"),
+                format!("no middleware is registered as `{}`", middleware.name),
+            )),
+            Some(Err(message)) => {
+                errors.push(SpecError::new(format!("{path}/{j}/config"), message))
+            }
+            Some(Ok(layer)) => built.push(layer),
+        }
+    }
+    built
+}
+
+/// Checks that `value` is an object with no properties other than `allowed`.
+pub(crate) fn object<'a>(
+    value: &'a Value,
+    path: &str,
+    allowed: &[&str],
+    errors: &mut Vec<SpecError>,
+) -> Option<&'a Map<String, Value>> {
+    let Value::Object(map) = value else {
+        errors.push(expected(path, "an object", value));
+        return None;
+    };
+    for key in map.keys() {
+        if !allowed.contains(&key.as_str()) {
+            errors.push(SpecError::new(
+                pointer(path, key),
+                format!(
+                    "unknown property `{key}`, expected one of: {}",
+                    allowed.join(", ")
+                ),
+            ));
+        }
+    }
+    Some(map)
+}
+
+pub(crate) fn required_string(
+    map: &Map<String, Value>,
+    path: &str,
+    key: &str,
+    errors: &mut Vec<SpecError>,
+) -> Option<String> {
+    match map.get(key) {
+        None => {
+            errors.push(SpecError::new(
+                path,
+                format!("missing required property `{key}`"),
+            ));
+            None
+        }
+        Some(Value::String(s)) if s.is_empty() => {
+            errors.push(SpecError::new(pointer(path, key), "must not be empty"));
+            None
+        }
+        Some(Value::String(s)) => Some(s.clone()),
+        Some(other) => {
+            errors.push(expected(&pointer(path, key), "a string", other));
+            None
+        }
+    }
+}
+
+/// Appends `key` to the JSON pointer `path`, escaping `~` and `/` in it as RFC 6901 requires.
+pub(crate) fn pointer(path: &str, key: &str) -> String {
+    format!("{path}/{}", key.replace('~', "~0").replace('/', "~1"))
+}
+
+pub(crate) fn expected(path: &str, what: &str, found: &Value) -> SpecError {
+    let found = match found {
+        Value::Null => "null",
+        Value::Bool(_) => "a boolean",
+        Value::Number(_) => "a number",
+        Value::String(_) => "a string",
+        Value::Array(_) => "an array",
+        Value::Object(_) => "an object",
+    };
+    SpecError::new(path, format!("expected {what}, found {found}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wrap;
+
+    async fn concat(x: Vec<String>) -> String {
+        x.concat()
+    }
+
+    #[tokio::test]
+    async fn builds_graph_from_spec() {
+        let mut registry = OpRegistry::default();
+        registry.register("concat", wrap!(concat));
+        let spec = GraphSpec::from_json(
+            r#"{"nodes": [
+                {"name": "A", "inputs": ["entrypoint"], "op": "concat"},
+                {"name": "B", "inputs": ["A", "A"], "op": "concat"}
+            ]}"#,
+        )
+        .unwrap();
+
+        let graph = spec.build(&registry).unwrap();
+        let output = graph.run("hubba".into(), "B".into()).await;
+        assert_eq!(output.unwrap(), "hubbahubba".to_string());
+    }
+
+    #[test]
+    fn reports_every_error_with_its_location() {
+        let errors = GraphSpec::from_json(
+            r#"{"nodes": [
+                {"name": "A", "inputs": ["entrypoint", 3], "op": "concat"},
+                {"name": "", "inputs": [], "op": "concat", "retries": 2}
+            ], "version": 1}"#,
+        )
+        .unwrap_err();
+        let errors: Vec<String> = errors.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            errors,
+            vec![
+                "/version: unknown property `version`, expected one of: nodes, middleware",
+                "/nodes/0/inputs/1: expected a string, found a number",
+                "/nodes/1/retries: unknown property `retries`, expected one of: name, inputs, op, config, version, tags, middleware",
+                "/nodes/1/name: must not be empty",
+            ]
+        );
+
+        let spec =
+            GraphSpec::from_json(r#"{"nodes": [{"name": "A", "inputs": ["B"], "op": "x"}]}"#)
+                .unwrap();
+        let errors = spec.build(&OpRegistry::default()).err().unwrap();
+        assert_eq!(errors[0].path, "/nodes/0/op");
+        assert_eq!(errors[1].path, "/nodes/0/inputs/0");
+    }
+
+    #[test]
+    fn published_schema_agrees_with_the_parser() {
+        let schema = crate::schema::OutputSchema::new(GraphSpec::json_schema());
+        let corpus = [
+            r#"{"nodes": []}"#,
+            r#"{"nodes": [{"name": "A", "inputs": ["entrypoint"], "op": "concat"}]}"#,
+            r#"{"nodes": [{"name": "A", "inputs": [], "op": "ask", "config": {"n": 1},
+                "version": "1.2.3", "tags": ["llm"], "middleware": [{"name": "retry"}]}],
+                "middleware": {"llm": [{"name": "cache", "config": {"ttl_ms": 10}}], "a/b~c": []}}"#,
+            r#"{}"#,
+            r#"[]"#,
+            r#"{"nodes": {}}"#,
+            r#"{"nodes": [], "version": 1}"#,
+            r#"{"nodes": [{"name": "entrypoint", "inputs": [], "op": "concat"}]}"#,
+            r#"{"nodes": [{"name": "", "inputs": [], "op": "concat"}]}"#,
+            r#"{"nodes": [{"name": "A", "inputs": [3], "op": "concat"}]}"#,
+            r#"{"nodes": [{"name": "A", "op": "concat"}]}"#,
+            r#"{"nodes": [{"name": "A", "inputs": [], "op": ""}]}"#,
+            r#"{"nodes": [{"name": "A", "inputs": [], "op": "x", "version": "one"}]}"#,
+            r#"{"nodes": [{"name": "A", "inputs": [], "op": "x", "tags": [""]}]}"#,
+            r#"{"nodes": [{"name": "A", "inputs": [], "op": "x", "retries": 2}]}"#,
+            r#"{"nodes": [], "middleware": {"llm": [{"config": {}}]}}"#,
+            r#"{"nodes": [], "middleware": {"llm": [{"name": "retry", "when": 1}]}}"#,
+            r#"{"nodes": [], "middleware": {"llm": {}}}"#,
+        ];
+        for document in corpus {
+            assert_eq!(
+                schema.check(document).is_empty(),
+                GraphSpec::from_json(document).is_ok(),
+                "{document}"
+            );
+        }
+
+        let errors =
+            GraphSpec::from_json(r#"{"nodes": [], "middleware": {"a/b~c": 1}}"#).unwrap_err();
+        assert_eq!(errors[0].path, "/middleware/a~1b~0c");
+    }
+
+    #[tokio::test]
+    async fn graph_topology_round_trips_through_serde() {
+        let mut registry = OpRegistry::default();
+        registry.register("concat", wrap!(concat));
+        let text = r#"{"nodes":[{"name":"A","inputs":["entrypoint"],"op":"concat"},{"name":"B","inputs":["A","entrypoint"],"op":"concat"}]}"#;
+        let spec: GraphSpec = serde_json::from_str(text).unwrap();
+        let graph = spec.build(&registry).unwrap();
+        assert_eq!(serde_json::to_string(&graph).unwrap(), text);
+
+        let copy: GraphSpec =
+            serde_json::from_str(&serde_json::to_string(&graph).unwrap()).unwrap();
+        let output = copy
+            .build(&registry)
+            .unwrap()
+            .run("x".into(), "B".into())
+            .await;
+        assert_eq!(output.unwrap(), "xx");
+
+        let mut unnamed = Graph::default();
+        unnamed.stage_node("A".into(), vec!["entrypoint".into()], wrap!(concat));
+        let error = serde_json::to_string(&unnamed).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "/nodes/0/op: the op of node `A` has no registered name"
+        );
+        let error = serde_json::from_str::<GraphSpec>(r#"{"nodes": 1}"#).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "/nodes: expected an array, found a number"
+        );
+    }
+}
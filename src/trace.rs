@@ -0,0 +1,299 @@
+use crate::graph::split_input;
+use crate::id::RunId;
+use crate::migrate::Version;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// How a traced `Node` came by its value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Source {
+    /// The `Node`s own `op` ran.
+    Op,
+    /// The canary `op` set with `Graph::set_canary` ran instead of the `Node`s own.
+    Canary,
+    /// The value came from the `Node`s cache.
+    Cache,
+    /// The `Node` was disabled and passed on its `disabled_output`.
+    Disabled,
+    /// An operator supplied the value with `RunControl::override_node`. The trace of an overridden `Node` has no
+    /// inputs.
+    Override,
+    /// An input the `Node` requires had no value, so its `op` didn't run and it has none either.
+    Skipped,
+}
+
+/// What one `Node` did during a traced run. `inputs` are the values it received, leaving out inputs that had none,
+/// `output` is its value (`None` if it had none) or the message of the error it failed with, and `started` and
+/// `finished` are measured from the start of the run.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NodeTrace {
+    pub node: String,
+    pub inputs: Vec<String>,
+    pub output: Result<Option<String>, String>,
+    pub source: Source,
+    pub started: Duration,
+    pub finished: Duration,
+}
+
+/// A `RunTrace` records every `Node` that finished during a run of `Graph::run_traced`, in the order they
+/// finished. `Node`s that never got all of their inputs, or were still running when another failed, are left out of
+/// `nodes`, but every `Node` of the `Graph` is listed in `graph` along with its inputs, and in `versions` if it has a
+/// version set.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RunTrace {
+    pub run_id: RunId,
+    pub input: String,
+    pub output_node: String,
+    pub nodes: Vec<NodeTrace>,
+    pub graph: BTreeMap<String, Vec<String>>,
+    pub versions: BTreeMap<String, Version>,
+}
+
+impl RunTrace {
+    /// The trace of the `Node` called `name`, if it finished.
+    pub fn node(&self, name: &str) -> Option<&NodeTrace> {
+        self.nodes.iter().find(|n| n.node == name)
+    }
+
+    /// Every moment at which some `Node` started or finished, in order and without duplicates.
+    pub fn timeline(&self) -> Vec<Duration> {
+        let mut times: Vec<Duration> = self
+            .nodes
+            .iter()
+            .flat_map(|n| [n.started, n.finished])
+            .collect();
+        times.sort();
+        times.dedup();
+        times
+    }
+
+    /// `state_at` reconstructs the run as it was `at` into it: which `Node`s had finished and with what, which were
+    /// running and on which inputs, and which hadn't started yet.
+    pub fn state_at(&self, at: Duration) -> TraceState {
+        let mut state = TraceState {
+            at,
+            ..TraceState::default()
+        };
+        for node in &self.nodes {
+            if node.finished <= at {
+                state
+                    .completed
+                    .insert(node.node.clone(), node.output.clone());
+            } else if node.started <= at {
+                state.running.insert(node.node.clone(), node.inputs.clone());
+            }
+        }
+        state.pending = self
+            .graph
+            .keys()
+            .filter(|name| {
+                !state.completed.contains_key(*name) && !state.running.contains_key(*name)
+            })
+            .cloned()
+            .collect();
+        state
+    }
+}
+
+/// A `Reproduction` is the smallest self-contained account of a failed run: the `Node` that failed, the inputs it
+/// failed on, and the part of the `Graph` it depends on, with the values its ancestors produced. That is enough to
+/// file a bug report, or to stage just those `Node`s in a regression test and feed them `input`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Reproduction {
+    /// The run the failure happened in.
+    pub run_id: RunId,
+    pub node: String,
+    pub error: String,
+    /// The value the run was started with.
+    pub input: String,
+    /// The values the failing `Node` received.
+    pub inputs: Vec<String>,
+    /// The failing `Node` and each of its ancestors, with their inputs.
+    pub graph: BTreeMap<String, Vec<String>>,
+    /// What each ancestor output during the run, leaving out those that had no value.
+    pub recorded: BTreeMap<String, String>,
+}
+
+impl Reproduction {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "run_id": self.run_id.to_string(),
+            "node": self.node,
+            "error": self.error,
+            "input": self.input,
+            "inputs": self.inputs,
+            "graph": self.graph,
+            "recorded": self.recorded,
+        })
+    }
+}
+
+impl RunTrace {
+    /// `reproduction` extracts a `Reproduction` of the first `Node` that failed, or returns `None` if none did.
+    pub fn reproduction(&self) -> Option<Reproduction> {
+        let failed = self.nodes.iter().find(|n| n.output.is_err())?;
+        let mut graph = BTreeMap::new();
+        let mut recorded = BTreeMap::new();
+        let mut todo = vec![failed.node.clone()];
+        while let Some(name) = todo.pop() {
+            if name == "entrypoint" || graph.contains_key(&name) {
+                continue;
+            }
+            let inputs = self.graph.get(&name).cloned().unwrap_or_default();
+            let producers = inputs
+                .iter()
+                .map(|input| split_input(input, |name| self.graph.contains_key(name)).0);
+            todo.extend(producers.map(str::to_string));
+            if let Some(Ok(Some(output))) = self.node(&name).map(|n| &n.output) {
+                recorded.insert(name.clone(), output.clone());
+            }
+            graph.insert(name, inputs);
+        }
+        Some(Reproduction {
+            run_id: self.run_id,
+            node: failed.node.clone(),
+            error: failed.output.clone().err().unwrap_or_default(),
+            input: self.input.clone(),
+            inputs: failed.inputs.clone(),
+            graph,
+            recorded,
+        })
+    }
+}
+
+/// A run as it was at one moment, see `RunTrace::state_at`. `Node`s are keyed and listed by name.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TraceState {
+    pub at: Duration,
+    /// Finished `Node`s, with their value or error.
+    pub completed: BTreeMap<String, Result<Option<String>, String>>,
+    /// Running `Node`s, with the inputs they were working on.
+    pub running: BTreeMap<String, Vec<String>>,
+    pub pending: Vec<String>,
+}
+
+/// A `TraceCursor` steps backwards and forwards through the `RunTrace::timeline` of a historical run, one start or
+/// finish of a `Node` at a time. It starts before anything has happened.
+pub struct TraceCursor<'a> {
+    trace: &'a RunTrace,
+    timeline: Vec<Duration>,
+    position: Option<usize>,
+}
+
+impl<'a> TraceCursor<'a> {
+    pub fn new(trace: &'a RunTrace) -> Self {
+        Self {
+            trace,
+            timeline: trace.timeline(),
+            position: None,
+        }
+    }
+
+    /// Moves to the next moment, returning `false` once at the end.
+    pub fn forward(&mut self) -> bool {
+        let next = self.position.map_or(0, |p| p + 1);
+        if next >= self.timeline.len() {
+            return false;
+        }
+        self.position = Some(next);
+        true
+    }
+
+    /// Moves to the previous moment, returning `false` once back before the start.
+    pub fn back(&mut self) -> bool {
+        match self.position {
+            None => false,
+            Some(0) => {
+                self.position = None;
+                true
+            }
+            Some(p) => {
+                self.position = Some(p - 1);
+                true
+            }
+        }
+    }
+
+    pub fn state(&self) -> TraceState {
+        match self.position {
+            Some(p) => self.trace.state_at(self.timeline[p]),
+            None => TraceState {
+                pending: self.trace.graph.keys().cloned().collect(),
+                ..TraceState::default()
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn traced(node: &str, output: &str, started: u64, finished: u64) -> NodeTrace {
+        NodeTrace {
+            node: node.into(),
+            inputs: vec!["x".into()],
+            output: Ok(Some(output.into())),
+            source: Source::Op,
+            started: Duration::from_millis(started),
+            finished: Duration::from_millis(finished),
+        }
+    }
+
+    #[test]
+    fn steps_through_a_run() {
+        let trace = RunTrace {
+            run_id: RunId::new(),
+            input: "x".into(),
+            output_node: "B".into(),
+            nodes: vec![traced("A", "a", 0, 10), traced("B", "b", 10, 30)],
+            graph: BTreeMap::from([
+                ("A".into(), vec!["entrypoint".into()]),
+                ("B".into(), vec!["A".into()]),
+            ]),
+            versions: BTreeMap::new(),
+        };
+        let mut cursor = TraceCursor::new(&trace);
+        assert_eq!(cursor.state().pending, vec!["A", "B"]);
+
+        assert!(cursor.forward());
+        assert_eq!(cursor.state().running.keys().collect::<Vec<_>>(), vec!["A"]);
+        assert!(cursor.forward());
+        let state = cursor.state();
+        assert_eq!(state.completed["A"], Ok(Some("a".to_string())));
+        assert!(state.running.contains_key("B"));
+        assert!(cursor.forward());
+        assert!(!cursor.forward());
+        assert_eq!(cursor.state().completed.len(), 2);
+
+        assert!(cursor.back());
+        assert_eq!(cursor.state().at, Duration::from_millis(10));
+    }
+
+    #[test]
+    fn extracts_failing_node_and_its_ancestors() {
+        let mut failed = traced("B", "", 10, 20);
+        failed.inputs = vec!["a".into()];
+        failed.output = Err("Node B timed out after 1 attempt(s)".into());
+        let trace = RunTrace {
+            run_id: RunId::new(),
+            input: "x".into(),
+            output_node: "C".into(),
+            nodes: vec![traced("A", "a", 0, 10), traced("D", "d", 0, 5), failed],
+            graph: BTreeMap::from([
+                ("A".into(), vec!["entrypoint".into()]),
+                ("B".into(), vec!["A".into()]),
+                ("C".into(), vec!["B".into(), "D".into()]),
+                ("D".into(), vec!["entrypoint".into()]),
+            ]),
+            versions: BTreeMap::new(),
+        };
+
+        let repro = trace.reproduction().unwrap();
+        assert_eq!(repro.node, "B");
+        assert_eq!(repro.inputs, vec!["a"]);
+        assert_eq!(repro.graph.keys().collect::<Vec<_>>(), vec!["A", "B"]);
+        assert_eq!(repro.to_json()["recorded"], json!({"A": "a"}));
+    }
+}
@@ -0,0 +1,119 @@
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::time::{Duration, Instant};
+
+/// The values that arrived from every source of a `FanIn` for one correlation key.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Combined {
+    pub key: String,
+    /// The value from each source, by source name.
+    pub values: BTreeMap<String, String>,
+}
+
+impl Combined {
+    /// The values as a JSON object keyed by source, e.g. `{"queue": "...", "webhook": "..."}`, to pass to
+    /// `Graph::run` as the `entrypoint` value. An entry `Node` staged with `Graph::stage_json_node` can decode it.
+    pub fn to_entrypoint(&self) -> String {
+        let values: Map<String, Value> = self
+            .values
+            .iter()
+            .map(|(source, value)| (source.clone(), Value::String(value.clone())))
+            .collect();
+        Value::Object(values).to_string()
+    }
+}
+
+/// A `FanIn` correlates events from several trigger sources, such as a webhook, a schedule and a queue, so a run
+/// starts only once every source has delivered a value for the same key within `window` of the first one. A group
+/// that doesn't complete in time is dropped, and the late value starts a new one.
+pub struct FanIn {
+    sources: BTreeSet<String>,
+    window: Duration,
+    pending: HashMap<String, (Instant, BTreeMap<String, String>)>,
+}
+
+impl FanIn {
+    pub fn new<'a>(sources: impl IntoIterator<Item = &'a str>, window: Duration) -> Self {
+        Self {
+            sources: sources.into_iter().map(str::to_string).collect(),
+            window,
+            pending: HashMap::new(),
+        }
+    }
+
+    /// `offer` records that `source` delivered `value` for `key` now, and returns the combined values once every
+    /// source has. A source delivering twice for the same group replaces its earlier value.
+    pub fn offer(&mut self, key: &str, source: &str, value: String) -> Option<Combined> {
+        self.offer_at(Instant::now(), key, source, value)
+    }
+
+    /// `offer` for a value that arrived `at`.
+    pub fn offer_at(
+        &mut self,
+        at: Instant,
+        key: &str,
+        source: &str,
+        value: String,
+    ) -> Option<Combined> {
+        assert!(
+            self.sources.contains(source),
+            "Source {source} is not one of the sources of this fan-in"
+        );
+        let window = self.window;
+        self.pending
+            .retain(|_, (first, _)| at.saturating_duration_since(*first) <= window);
+        let (_, values) = self
+            .pending
+            .entry(key.to_string())
+            .or_insert_with(|| (at, BTreeMap::new()));
+        values.insert(source.to_string(), value);
+        if values.len() < self.sources.len() {
+            return None;
+        }
+        let (_, values) = self.pending.remove(key).expect("group was just updated");
+        Some(Combined {
+            key: key.to_string(),
+            values,
+        })
+    }
+
+    /// How many keys are still waiting on some source.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::Graph;
+    use std::collections::HashMap;
+
+    async fn merge(events: Vec<HashMap<String, String>>) -> String {
+        let event = &events[0];
+        format!("{} at {}", event["webhook"], event["schedule"])
+    }
+
+    #[tokio::test]
+    async fn starts_a_run_once_every_source_arrives_in_time() {
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let mut fan_in = FanIn::new(["webhook", "schedule"], Duration::from_secs(10));
+
+        assert_eq!(fan_in.offer_at(at(0), "a", "webhook", "push".into()), None);
+        assert_eq!(
+            fan_in.offer_at(at(20), "a", "schedule", "noon".into()),
+            None
+        );
+        assert_eq!(fan_in.pending(), 1);
+        let combined = fan_in
+            .offer_at(at(25), "a", "webhook", "retry".into())
+            .unwrap();
+        assert_eq!(fan_in.pending(), 0);
+
+        let mut graph = Graph::default();
+        graph.stage_json_node("merge".into(), vec!["entrypoint".into()], merge);
+        let output = graph.run(combined.to_entrypoint(), "merge".into()).await;
+        assert_eq!(output.unwrap(), "\"retry at noon\"");
+    }
+}
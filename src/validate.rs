@@ -0,0 +1,373 @@
+use crate::graph::split_input;
+use std::collections::{BTreeSet, HashMap};
+use std::error::Error;
+use std::fmt;
+
+/// What kind of value an `op` takes or produces. Every value is passed around as a `String` either way, so this only
+/// documents intent for `Graph::validate` to check.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ContentType {
+    Text,
+    Json,
+    Uri,
+    Binary,
+}
+
+impl ContentType {
+    /// Whether an input declared as `self` can take a value declared as `produced`. `Text` takes JSON and URIs as
+    /// well, since they are text too.
+    pub fn accepts(self, produced: ContentType) -> bool {
+        self == produced || (self == Self::Text && produced != Self::Binary)
+    }
+}
+
+impl fmt::Display for ContentType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Text => "text",
+            Self::Json => "json",
+            Self::Uri => "uri",
+            Self::Binary => "binary",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// An `OpSignature` declares what an `op` expects: how many inputs it takes, the `ContentType` of each of them and
+/// the `ContentType` of its output. Anything left as `None` is not checked.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct OpSignature {
+    pub arity: Option<usize>,
+    pub input: Option<ContentType>,
+    pub output: Option<ContentType>,
+}
+
+impl OpSignature {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_arity(mut self, arity: usize) -> Self {
+        self.arity = Some(arity);
+        self
+    }
+
+    pub fn accepting(mut self, input: ContentType) -> Self {
+        self.input = Some(input);
+        self
+    }
+
+    pub fn producing(mut self, output: ContentType) -> Self {
+        self.output = Some(output);
+        self
+    }
+}
+
+/// A `ResidencyPolicy` lists the regions each data classification may be sent to. Data with a classification the
+/// policy doesn't mention may not leave at all. See `Graph::classify_node` and `Graph::set_destination`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ResidencyPolicy {
+    approved: HashMap<String, BTreeSet<String>>,
+}
+
+impl ResidencyPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `approve` allows data classified as `classification` to be sent to `region`.
+    pub fn approve(mut self, classification: &str, region: &str) -> Self {
+        self.approved
+            .entry(classification.to_string())
+            .or_default()
+            .insert(region.to_string());
+        self
+    }
+
+    fn allows(&self, classification: &str, region: &str) -> bool {
+        self.approved
+            .get(classification)
+            .is_some_and(|regions| regions.contains(region))
+    }
+}
+
+/// A wiring problem `Graph::validate` found at the `Node` called `node`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ValidationError {
+    pub node: String,
+    pub message: String,
+}
+
+impl ValidationError {
+    pub(crate) fn new(node: &str, message: impl Into<String>) -> Self {
+        Self {
+            node: node.to_string(),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.node, self.message)
+    }
+}
+
+impl Error for ValidationError {}
+
+/// What `check` needs to know about a `Node`.
+pub(crate) struct NodeInfo<'a> {
+    pub(crate) name: &'a str,
+    pub(crate) inputs: &'a [String],
+    pub(crate) signature: Option<&'a OpSignature>,
+    pub(crate) classifications: &'a BTreeSet<String>,
+    pub(crate) destination: Option<&'a str>,
+    /// Whether the `op` may have no value.
+    pub(crate) optional: bool,
+    pub(crate) requires_some: bool,
+    pub(crate) accepts_none: bool,
+}
+
+/// Checks every edge between `nodes`, which must be sorted by name, and where their data may end up under
+/// `residency`. Problems come out in the same order.
+pub(crate) fn check(nodes: &[NodeInfo], residency: &ResidencyPolicy) -> Vec<ValidationError> {
+    let find = |name: &str| nodes.binary_search_by(|node| node.name.cmp(name)).ok();
+    let absent = may_be_absent(nodes);
+    let mut errors = vec![];
+    for node in nodes {
+        let signature = node.signature.cloned().unwrap_or_default();
+        if let Some(arity) = signature.arity {
+            if arity != node.inputs.len() {
+                errors.push(ValidationError::new(
+                    node.name,
+                    format!(
+                        "takes {arity} input(s), but is wired to {}",
+                        node.inputs.len()
+                    ),
+                ));
+            }
+        }
+        for (i, input) in node.inputs.iter().enumerate() {
+            let (name, key) = split_input(input, |name| find(name).is_some());
+            if name == "entrypoint" {
+                continue;
+            }
+            let Some(producer) = find(name) else {
+                errors.push(ValidationError::new(
+                    node.name,
+                    format!("input {i} is `{input}`, which is not a node"),
+                ));
+                continue;
+            };
+            if absent[producer] && !node.requires_some && !node.accepts_none {
+                errors.push(ValidationError::new(
+                    node.name,
+                    format!("input {i} is `{input}`, which may have no value, but the node neither requires one nor accepts none"),
+                ));
+            }
+            let produced = nodes[producer].signature.and_then(|s| s.output);
+            if let (Some(_), Some(produced)) = (key, produced) {
+                if produced != ContentType::Json {
+                    errors.push(ValidationError::new(
+                        node.name,
+                        format!("input {i} is `{input}`, but `{name}` produces {produced}, which has no named outputs"),
+                    ));
+                }
+                // The `ContentType` of a single output isn't declared anywhere, so there is nothing more to check.
+                continue;
+            }
+            if let (Some(expected), Some(produced)) = (signature.input, produced) {
+                if !expected.accepts(produced) {
+                    errors.push(ValidationError::new(
+                        node.name,
+                        format!("input {i} is `{input}`, which produces {produced}, but {expected} is expected"),
+                    ));
+                }
+            }
+        }
+    }
+
+    let reached = classifications_reaching(nodes);
+    for (node, classifications) in nodes.iter().zip(&reached) {
+        let Some(region) = node.destination else {
+            continue;
+        };
+        for classification in classifications {
+            if !residency.allows(classification, region) {
+                errors.push(ValidationError::new(
+                    node.name,
+                    format!("receives {classification} data but sends to {region}, which is not approved for it"),
+                ));
+            }
+        }
+    }
+    errors.sort_by(|a, b| a.node.cmp(&b.node));
+    errors
+}
+
+/// Whether each of `nodes` may end up without a value: its `op` may have none, or it is skipped because an input
+/// had none.
+fn may_be_absent(nodes: &[NodeInfo]) -> Vec<bool> {
+    let index: HashMap<&str, usize> = nodes.iter().enumerate().map(|(i, n)| (n.name, i)).collect();
+    let mut absent: Vec<bool> = nodes.iter().map(|n| n.optional).collect();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (i, node) in nodes.iter().enumerate() {
+            if absent[i] || node.accepts_none {
+                continue;
+            }
+            let mut inputs = node
+                .inputs
+                .iter()
+                .filter_map(|input| index.get(split_input(input, |n| index.contains_key(n)).0));
+            if inputs.any(|&j| absent[j]) {
+                absent[i] = true;
+                changed = true;
+            }
+        }
+    }
+    absent
+}
+
+/// The classifications of the data each of `nodes` sees: its own, and those of every `Node` upstream of it.
+fn classifications_reaching(nodes: &[NodeInfo]) -> Vec<BTreeSet<String>> {
+    let index: HashMap<&str, usize> = nodes.iter().enumerate().map(|(i, n)| (n.name, i)).collect();
+    let mut reached: Vec<BTreeSet<String>> =
+        nodes.iter().map(|n| n.classifications.clone()).collect();
+    // Spreading one edge per pass until nothing changes also copes with cycles.
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (i, node) in nodes.iter().enumerate() {
+            for input in node.inputs {
+                let Some(&j) = index.get(split_input(input, |n| index.contains_key(n)).0) else {
+                    continue;
+                };
+                let upstream: Vec<String> = reached[j].difference(&reached[i]).cloned().collect();
+                changed |= !upstream.is_empty();
+                reached[i].extend(upstream);
+            }
+        }
+    }
+    reached
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::Graph;
+    use crate::wrap;
+
+    async fn concat(x: Vec<String>) -> String {
+        x.concat()
+    }
+
+    #[test]
+    fn flags_incompatible_edges() {
+        let mut graph = Graph::default();
+        graph.stage_node("fetch".into(), vec!["entrypoint".into()], wrap!(concat));
+        graph.stage_node("parse".into(), vec!["fetch".into()], wrap!(concat));
+        graph.stage_node("summarize".into(), vec!["parse".into()], wrap!(concat));
+        graph.stage_node(
+            "join".into(),
+            vec!["parse".into(), "missing".into()],
+            wrap!(concat),
+        );
+        graph.set_signature("fetch", OpSignature::new().producing(ContentType::Binary));
+        graph.set_signature(
+            "parse",
+            OpSignature::new()
+                .with_arity(1)
+                .accepting(ContentType::Json)
+                .producing(ContentType::Json),
+        );
+        graph.set_signature("summarize", OpSignature::new().accepting(ContentType::Text));
+        graph.set_signature("join", OpSignature::new().with_arity(1));
+
+        let errors: Vec<String> = graph.validate().iter().map(ToString::to_string).collect();
+        assert_eq!(
+            errors,
+            vec![
+                "join: takes 1 input(s), but is wired to 2",
+                "join: input 1 is `missing`, which is not a node",
+                "parse: input 0 is `fetch`, which produces binary, but json is expected",
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_classified_data_leaving_approved_regions() {
+        let mut graph = Graph::default();
+        graph.stage_node("records".into(), vec!["entrypoint".into()], wrap!(concat));
+        graph.stage_node("summary".into(), vec!["records".into()], wrap!(concat));
+        graph.stage_node("eu_llm".into(), vec!["summary".into()], wrap!(concat));
+        graph.stage_node("us_llm".into(), vec!["summary".into()], wrap!(concat));
+        graph.classify_node("records", "health");
+        graph.set_destination("eu_llm", "eu-west");
+        graph.set_destination("us_llm", "us-east");
+        graph.set_residency_policy(ResidencyPolicy::new().approve("health", "eu-west"));
+
+        let errors: Vec<String> = graph.validate().iter().map(ToString::to_string).collect();
+        assert_eq!(
+            errors,
+            vec!["us_llm: receives health data but sends to us-east, which is not approved for it"]
+        );
+    }
+
+    async fn cite(x: Vec<String>) -> Option<String> {
+        x[0].split_once("[")
+            .map(|(_, citation)| citation.to_string())
+    }
+
+    async fn footnote(x: Vec<Option<String>>) -> String {
+        x[0].clone().unwrap_or_else(|| "no sources".into())
+    }
+
+    #[test]
+    fn checks_consumers_of_values_that_may_be_missing() {
+        let mut graph = Graph::default();
+        graph.stage_optional_node("cite".into(), vec!["entrypoint".into()], cite);
+        graph.stage_node("format".into(), vec!["cite".into()], wrap!(concat));
+        graph.stage_node("publish".into(), vec!["format".into()], wrap!(concat));
+        graph.stage_node_accepting_none("footnote".into(), vec!["format".into()], footnote);
+        graph.stage_node("log".into(), vec!["footnote".into()], wrap!(concat));
+
+        let errors: Vec<String> = graph.validate().iter().map(ToString::to_string).collect();
+        assert_eq!(
+            errors,
+            vec![
+                "format: input 0 is `cite`, which may have no value, but the node neither requires one nor accepts none",
+                "publish: input 0 is `format`, which may have no value, but the node neither requires one nor accepts none",
+            ]
+        );
+        graph.require_some("format");
+        graph.require_some("publish");
+        assert_eq!(graph.validate(), vec![]);
+    }
+
+    #[test]
+    fn checks_inputs_subscribing_to_named_outputs() {
+        let mut graph = Graph::default();
+        graph.stage_node("answer".into(), vec!["entrypoint".into()], wrap!(concat));
+        graph.stage_node("summary".into(), vec!["entrypoint".into()], wrap!(concat));
+        let inputs = vec![
+            "answer.confidence".into(),
+            "summary.text".into(),
+            "search.hits".into(),
+        ];
+        graph.stage_node("report".into(), inputs, wrap!(concat));
+        graph.set_signature("answer", OpSignature::new().producing(ContentType::Json));
+        graph.set_signature("summary", OpSignature::new().producing(ContentType::Text));
+        graph.set_signature("report", OpSignature::new().accepting(ContentType::Uri));
+
+        let errors: Vec<String> = graph.validate().iter().map(ToString::to_string).collect();
+        assert_eq!(
+            errors,
+            vec![
+                "report: input 1 is `summary.text`, but `summary` produces text, which has no named outputs",
+                "report: input 2 is `search.hits`, which is not a node",
+            ]
+        );
+    }
+}